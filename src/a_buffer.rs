@@ -0,0 +1,108 @@
+use blend_mode::BlendMode;
+use color::LinearImage;
+use image::Rgba;
+use z_buffer::{PixelVisibility, ZBuffer};
+
+#[derive(Debug, Clone, Copy)]
+struct Fragment {
+    color: Rgba<f32>,
+    depth: u32,
+    next: Option<usize>,
+}
+
+/// Collects transparent fragments in per-pixel linked lists, so they can be sorted by depth and
+/// composited after all the geometry has been drawn, whatever order it was drawn in.
+#[derive(Debug, Clone)]
+pub struct ABuffer {
+    width: u32,
+    height: u32,
+    heads: Vec<Option<usize>>,
+    fragments: Vec<Fragment>,
+}
+
+impl ABuffer {
+    pub fn new(width: u32, height: u32) -> ABuffer {
+        ABuffer {
+            width,
+            height,
+            heads: vec![None; (width * height) as usize],
+            fragments: Vec::new(),
+        }
+    }
+
+    pub fn insert(&mut self, x: u32, y: u32, color: Rgba<f32>, depth: u32) {
+        let index = (y * self.width + x) as usize;
+        self.fragments.push(Fragment { color, depth, next: self.heads[index] });
+        self.heads[index] = Some(self.fragments.len() - 1);
+    }
+
+    pub fn fragment_count(&self) -> usize {
+        self.fragments.len()
+    }
+
+    pub fn clear(&mut self) {
+        for head in self.heads.iter_mut() {
+            *head = None;
+        }
+        self.fragments.clear();
+    }
+
+    /// Composites the fragments in front of the opaque geometry onto `buffer`, farthest first,
+    /// then empties the lists.
+    pub fn resolve(&mut self, buffer: &mut LinearImage, z_buffer: &ZBuffer, blend_mode: BlendMode) {
+        self.composite(buffer, z_buffer, blend_mode);
+        self.clear();
+    }
+
+    /// Like `resolve`, but keeps the fragments.
+    pub fn composite(&self, buffer: &mut LinearImage, z_buffer: &ZBuffer, blend_mode: BlendMode) {
+        let mut pixel_fragments = Vec::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                pixel_fragments.clear();
+                let mut next = self.heads[(y * self.width + x) as usize];
+                while let Some(index) = next {
+                    let fragment = self.fragments[index];
+                    if z_buffer.test_depth(x, y, fragment.depth) == PixelVisibility::Visible {
+                        pixel_fragments.push(fragment);
+                    }
+                    next = fragment.next;
+                }
+                pixel_fragments.sort_by_key(|fragment| fragment.depth);
+                for fragment in pixel_fragments.iter() {
+                    buffer[(x, y)] = blend_mode.blend(fragment.color, buffer[(x, y)]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fragments_should_be_composited_back_to_front() {
+        let mut buffer = LinearImage::from_pixel(1, 1, Rgba([0.0, 0.0, 0.0, 1.0]));
+        let z_buffer = ZBuffer::new(1, 1);
+        let mut a_buffer = ABuffer::new(1, 1);
+        a_buffer.insert(0, 0, Rgba([0.0, 0.0, 1.0, 0.5]), 20);
+        a_buffer.insert(0, 0, Rgba([1.0, 0.0, 0.0, 0.5]), 10);
+        a_buffer.resolve(&mut buffer, &z_buffer, BlendMode::SourceOver);
+        assert_eq!(buffer[(0, 0)], Rgba([0.25, 0.0, 0.5, 1.0]));
+        assert_eq!(a_buffer.fragment_count(), 0);
+    }
+
+    #[test]
+    fn fragments_behind_opaque_geometry_should_be_discarded() {
+        let mut buffer = LinearImage::from_pixel(2, 1, Rgba([0.0, 0.0, 0.0, 1.0]));
+        let mut z_buffer = ZBuffer::new(2, 1);
+        z_buffer.update_buffer(0, 0, 50);
+        let mut a_buffer = ABuffer::new(2, 1);
+        a_buffer.insert(0, 0, Rgba([1.0, 1.0, 1.0, 1.0]), 40);
+        a_buffer.insert(1, 0, Rgba([1.0, 1.0, 1.0, 1.0]), 40);
+        a_buffer.resolve(&mut buffer, &z_buffer, BlendMode::SourceOver);
+        assert_eq!(buffer[(0, 0)], Rgba([0.0, 0.0, 0.0, 1.0]));
+        assert_eq!(buffer[(1, 0)], Rgba([1.0, 1.0, 1.0, 1.0]));
+    }
+}
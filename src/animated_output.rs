@@ -0,0 +1,437 @@
+use image::png::PNGEncoder;
+use image::{ColorType, RgbaImage};
+use renderer_error::RendererError;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Ways to store the frames of an animation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnimationFormat {
+    /// Looping GIF with a palette of 255 colors shared by all frames, and 1 bit transparency.
+    Gif,
+    /// Looping animated PNG, which keeps full color and alpha.
+    Apng,
+    /// One PNG per frame, numbered from 0 before the extension, e.g. `frame_0000.png`.
+    PngSequence,
+}
+
+impl AnimationFormat {
+    /// `.gif` and `.apng` files, or a sequence for `.png`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, RendererError> {
+        let extension = path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "gif" => Ok(AnimationFormat::Gif),
+            "apng" => Ok(AnimationFormat::Apng),
+            "png" => Ok(AnimationFormat::PngSequence),
+            _ => Err(RendererError::UnsupportedOutputFormat(extension)),
+        }
+    }
+}
+
+/// Saves `frames`, which all have to be the same size, each shown for `delay_ms` milliseconds.
+/// The format follows from the extension of `path` unless it is given. GIF and APNG files are not
+/// written if the sizes differ.
+pub fn save_animation<P: AsRef<Path>>(
+    path: P,
+    frames: &[RgbaImage],
+    delay_ms: u32,
+    format: Option<AnimationFormat>,
+) -> Result<(), RendererError> {
+    let path = path.as_ref();
+    let format = match format {
+        Some(format) => format,
+        None => AnimationFormat::from_path(path)?,
+    };
+    if format == AnimationFormat::PngSequence {
+        for (index, frame) in frames.iter().enumerate() {
+            let (width, height) = frame.dimensions();
+            let file = BufWriter::new(File::create(sequence_path(path, index))?);
+            PNGEncoder::new(file).encode(frame, width, height, ColorType::RGBA(8))?;
+        }
+        return Ok(());
+    }
+    frame_size(frames)?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    match format {
+        AnimationFormat::Gif => write_gif(&mut writer, frames, delay_ms)?,
+        _ => write_apng(&mut writer, frames, delay_ms)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// The size shared by all `frames`, which is an error if any frame differs from the first.
+fn frame_size(frames: &[RgbaImage]) -> Result<(u32, u32), RendererError> {
+    let size = frames.first().map_or((0, 0), |frame| frame.dimensions());
+    match frames.iter().position(|frame| frame.dimensions() != size) {
+        Some(index) => Err(RendererError::FrameSizeMismatch(index, frames[index].width(), frames[index].height())),
+        None => Ok(size),
+    }
+}
+
+/// `path` with the zero padded frame number appended to the file stem.
+pub fn sequence_path(path: &Path, index: usize) -> PathBuf {
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("frame");
+    let name = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => format!("{}_{:04}.{}", stem, index, extension),
+        None => format!("{}_{:04}", stem, index),
+    };
+    path.with_file_name(name)
+}
+
+const GIF_COLORS: usize = 255;
+const GIF_TRANSPARENT_INDEX: u8 = 255;
+const LZW_MAX_CODES: u16 = 4096;
+
+pub fn write_gif<W: Write>(writer: &mut W, frames: &[RgbaImage], delay_ms: u32) -> Result<(), RendererError> {
+    let (width, height) = frame_size(frames)?;
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(RendererError::ImageTooLarge(width, height));
+    }
+    let palette = median_cut(frames, GIF_COLORS);
+    writer.write_all(b"GIF89a")?;
+    writer.write_all(&(width as u16).to_le_bytes())?;
+    writer.write_all(&(height as u16).to_le_bytes())?;
+    // A global color table of 2^(7 + 1) entries, with 8 bits per channel.
+    writer.write_all(&[0xf7, 0, 0])?;
+    let mut table = vec![0; 256 * 3];
+    for (entry, color) in table.chunks_mut(3).zip(palette.iter()) {
+        entry.copy_from_slice(color);
+    }
+    writer.write_all(&table)?;
+    writer.write_all(&[0x21, 0xff, 0x0b])?;
+    writer.write_all(b"NETSCAPE2.0")?;
+    writer.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])?;
+
+    let delay = ((delay_ms + 5) / 10).min(u16::MAX as u32) as u16;
+    let mut nearest = HashMap::new();
+    for frame in frames {
+        // Graphic control extension: clear to the background before the next frame, with a transparent index.
+        writer.write_all(&[0x21, 0xf9, 0x04, 0x09])?;
+        writer.write_all(&delay.to_le_bytes())?;
+        writer.write_all(&[GIF_TRANSPARENT_INDEX, 0x00])?;
+        writer.write_all(&[0x2c, 0, 0, 0, 0])?;
+        writer.write_all(&(width as u16).to_le_bytes())?;
+        writer.write_all(&(height as u16).to_le_bytes())?;
+        writer.write_all(&[0x00])?;
+        let indices: Vec<u8> = frame
+            .pixels()
+            .map(|pixel| {
+                let [red, green, blue, alpha] = pixel.data;
+                if alpha < 128 {
+                    return GIF_TRANSPARENT_INDEX;
+                }
+                *nearest.entry([red, green, blue]).or_insert_with(|| nearest_index(&palette, [red, green, blue]))
+            })
+            .collect();
+        writer.write_all(&[8])?;
+        for block in lzw_encode(&indices).chunks(255) {
+            writer.write_all(&[block.len() as u8])?;
+            writer.write_all(block)?;
+        }
+        writer.write_all(&[0x00])?;
+    }
+    writer.write_all(&[0x3b])?;
+    Ok(())
+}
+
+/// Splits the color cube along the widest channel at the median, until there are `count` boxes,
+/// and returns the average color of each. Transparent pixels are left out.
+fn median_cut(frames: &[RgbaImage], count: usize) -> Vec<[u8; 3]> {
+    let mut histogram = HashMap::new();
+    for pixel in frames.iter().flat_map(|frame| frame.pixels()) {
+        let [red, green, blue, alpha] = pixel.data;
+        if alpha >= 128 {
+            *histogram.entry([red, green, blue]).or_insert(0_u64) += 1;
+        }
+    }
+    let mut boxes = vec![histogram.into_iter().collect::<Vec<_>>()];
+    if boxes[0].is_empty() {
+        return vec![[0, 0, 0]];
+    }
+    while boxes.len() < count {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|&(_, colors)| colors.len() > 1)
+            .map(|(index, colors)| {
+                let range = |channel: usize| {
+                    let values = colors.iter().map(|&(color, _)| color[channel]);
+                    values.clone().max().unwrap() - values.min().unwrap()
+                };
+                let (channel, range) = (0..3).map(|channel| (channel, range(channel))).max_by_key(|&(_, range)| range).unwrap();
+                (index, channel, range)
+            })
+            .max_by_key(|&(_, _, range)| range);
+        let (index, channel) = match widest {
+            Some((index, channel, _)) => (index, channel),
+            None => break,
+        };
+        let mut colors = boxes.swap_remove(index);
+        colors.sort_by_key(|&(color, _)| (color[channel], color));
+        let total: u64 = colors.iter().map(|&(_, count)| count).sum();
+        let mut seen = 0;
+        let split = colors
+            .iter()
+            .position(|&(_, count)| {
+                seen += count;
+                seen * 2 >= total
+            })
+            .map_or(1, |position| position + 1)
+            .clamp(1, colors.len() - 1);
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+    boxes
+        .iter()
+        .map(|colors| {
+            let total: u64 = colors.iter().map(|&(_, count)| count).sum();
+            let mut sums = [0_u64; 3];
+            for &(color, count) in colors {
+                for (sum, &value) in sums.iter_mut().zip(color.iter()) {
+                    *sum += value as u64 * count;
+                }
+            }
+            [(sums[0] / total) as u8, (sums[1] / total) as u8, (sums[2] / total) as u8]
+        })
+        .collect()
+}
+
+fn nearest_index(palette: &[[u8; 3]], color: [u8; 3]) -> u8 {
+    let distance = |candidate: &[u8; 3]| {
+        candidate.iter().zip(color.iter()).map(|(&a, &b)| (a as i32 - b as i32).pow(2)).sum::<i32>()
+    };
+    (0..palette.len()).min_by_key(|&index| distance(&palette[index])).unwrap_or(0) as u8
+}
+
+/// GIF flavoured LZW of 8 bit indices: codes grow from 9 to 12 bits, least significant bit first,
+/// and the table starts over when it is full.
+fn lzw_encode(indices: &[u8]) -> Vec<u8> {
+    let (clear, end) = (256_u16, 257_u16);
+    let mut output = Vec::new();
+    let (mut bits, mut bit_count) = (0_u32, 0_u32);
+    let mut code_size = 9;
+    let mut emit = |code: u16, code_size: u32, output: &mut Vec<u8>| {
+        bits |= (code as u32) << bit_count;
+        bit_count += code_size;
+        while bit_count >= 8 {
+            output.push(bits as u8);
+            bits >>= 8;
+            bit_count -= 8;
+        }
+    };
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = end + 1;
+    emit(clear, code_size, &mut output);
+    let mut prefix: Option<u16> = None;
+    for &index in indices {
+        let current = match prefix {
+            None => {
+                prefix = Some(index as u16);
+                continue;
+            }
+            Some(current) => current,
+        };
+        if let Some(&code) = table.get(&(current, index)) {
+            prefix = Some(code);
+            continue;
+        }
+        emit(current, code_size, &mut output);
+        if next_code < LZW_MAX_CODES {
+            table.insert((current, index), next_code);
+            next_code += 1;
+            if next_code > 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+        } else {
+            emit(clear, code_size, &mut output);
+            table.clear();
+            next_code = end + 1;
+            code_size = 9;
+        }
+        prefix = Some(index as u16);
+    }
+    if let Some(current) = prefix {
+        emit(current, code_size, &mut output);
+    }
+    emit(end, code_size, &mut output);
+    if bit_count > 0 {
+        output.push(bits as u8);
+    }
+    output
+}
+
+/// Writes an animated PNG by encoding every frame as a PNG and moving its image data into the
+/// frame chunks of one file. Viewers without APNG support show the first frame.
+pub fn write_apng<W: Write>(writer: &mut W, frames: &[RgbaImage], delay_ms: u32) -> Result<(), RendererError> {
+    frame_size(frames)?;
+    writer.write_all(&[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'])?;
+    let mut sequence_number = 0_u32;
+    for (index, frame) in frames.iter().enumerate() {
+        let (width, height) = frame.dimensions();
+        let mut png = Vec::new();
+        PNGEncoder::new(&mut png).encode(frame, width, height, ColorType::RGBA(8))?;
+        let chunks = png_chunks(&png);
+        if index == 0 {
+            for &(kind, data) in chunks.iter().filter(|&&(kind, _)| kind == *b"IHDR") {
+                write_chunk(writer, kind, data)?;
+            }
+            let mut animation_control = Vec::new();
+            animation_control.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+            animation_control.extend_from_slice(&0_u32.to_be_bytes()); // loop forever
+            write_chunk(writer, *b"acTL", &animation_control)?;
+        }
+        let mut frame_control = Vec::new();
+        frame_control.extend_from_slice(&sequence_number.to_be_bytes());
+        frame_control.extend_from_slice(&width.to_be_bytes());
+        frame_control.extend_from_slice(&height.to_be_bytes());
+        frame_control.extend_from_slice(&[0; 8]); // offsets
+        frame_control.extend_from_slice(&(delay_ms.min(u16::MAX as u32) as u16).to_be_bytes());
+        frame_control.extend_from_slice(&1000_u16.to_be_bytes());
+        frame_control.extend_from_slice(&[1, 0]); // clear to transparent afterwards, replace what is there
+        write_chunk(writer, *b"fcTL", &frame_control)?;
+        sequence_number += 1;
+        for &(_, data) in chunks.iter().filter(|&&(kind, _)| kind == *b"IDAT") {
+            if index == 0 {
+                write_chunk(writer, *b"IDAT", data)?;
+            } else {
+                let mut frame_data = Vec::with_capacity(data.len() + 4);
+                frame_data.extend_from_slice(&sequence_number.to_be_bytes());
+                frame_data.extend_from_slice(data);
+                write_chunk(writer, *b"fdAT", &frame_data)?;
+                sequence_number += 1;
+            }
+        }
+    }
+    write_chunk(writer, *b"IEND", &[])?;
+    Ok(())
+}
+
+/// The type and data of every chunk of a PNG file.
+fn png_chunks(png: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    let mut position = 8;
+    while position + 12 <= png.len() {
+        let mut length = [0; 4];
+        length.copy_from_slice(&png[position..position + 4]);
+        let length = u32::from_be_bytes(length) as usize;
+        let mut kind = [0; 4];
+        kind.copy_from_slice(&png[position + 4..position + 8]);
+        chunks.push((kind, &png[position + 8..position + 8 + length]));
+        position += 12 + length;
+    }
+    chunks
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: [u8; 4], data: &[u8]) -> Result<(), RendererError> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(&kind)?;
+    writer.write_all(data)?;
+    writer.write_all(&crc32(kind.iter().chain(data.iter())).to_be_bytes())?;
+    Ok(())
+}
+
+fn crc32<'a, I: Iterator<Item = &'a u8>>(bytes: I) -> u32 {
+    let mut crc = !0_u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { crc >> 1 ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::gif::Decoder;
+    use image::{self, DecodingResult, ImageDecoder, Rgba};
+
+    fn frames() -> Vec<RgbaImage> {
+        (0..3)
+            .map(|frame| {
+                RgbaImage::from_fn(40, 30, |x, y| {
+                    if x < 4 {
+                        Rgba([0, 0, 0, 0])
+                    } else {
+                        Rgba([(x * 6 + frame * 20) as u8, (y * 8) as u8, ((x * y) % 256) as u8, 255])
+                    }
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gif_frames_should_decode_to_about_the_same_colors() {
+        let frames = frames();
+        let mut gif = Vec::new();
+        write_gif(&mut gif, &frames, 40).unwrap();
+        let mut decoder = Decoder::new(&gif[..]);
+        for frame in frames.iter() {
+            let pixels = match decoder.read_image().unwrap() {
+                DecodingResult::U8(pixels) => pixels,
+                _ => panic!("expected 8 bit pixels"),
+            };
+            let decoded = RgbaImage::from_raw(40, 30, pixels).unwrap();
+            assert_eq!(decoded[(1, 1)].data[3], 0);
+            for (original, decoded) in frame.pixels().zip(decoded.pixels()).filter(|&(original, _)| original.data[3] == 255) {
+                for channel in 0..3 {
+                    let difference = (original.data[channel] as i32 - decoded.data[channel] as i32).abs();
+                    assert!(difference < 40, "{:?} {:?}", original, decoded);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn frames_of_different_sizes_should_be_refused() {
+        let mut frames = frames();
+        frames.push(RgbaImage::new(20, 30));
+        let mismatch = Err(RendererError::FrameSizeMismatch(frames.len() - 1, 20, 30));
+        let mut output = Vec::new();
+        assert_eq!(write_gif(&mut output, &frames, 40), mismatch);
+        assert_eq!(write_apng(&mut output, &frames, 40), mismatch);
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn gif_should_refuse_frames_larger_than_16_bits() {
+        let too_tall = vec![RgbaImage::new(1, 70_000)];
+        assert_eq!(write_gif(&mut Vec::new(), &too_tall, 40), Err(RendererError::ImageTooLarge(1, 70_000)));
+    }
+
+    #[test]
+    fn apng_should_show_the_first_frame_to_plain_png_decoders() {
+        let frames = frames();
+        let mut apng = Vec::new();
+        write_apng(&mut apng, &frames, 40).unwrap();
+        let chunks = png_chunks(&apng);
+        let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| &kind[..]).collect();
+        assert_eq!(&kinds[..3], &[&b"IHDR"[..], &b"acTL"[..], &b"fcTL"[..]]);
+        assert_eq!(kinds.iter().filter(|&&kind| kind == b"fcTL").count(), 3);
+        assert!(kinds.contains(&&b"fdAT"[..]));
+        let decoded = image::load_from_memory(&apng).unwrap().to_rgba();
+        assert_eq!(decoded.into_raw(), frames[0].clone().into_raw());
+    }
+
+    #[test]
+    fn sequences_should_number_the_frames() {
+        assert_eq!(sequence_path(Path::new("out/head.png"), 7), PathBuf::from("out/head_0007.png"));
+        assert_eq!(AnimationFormat::from_path("spin.GIF"), Ok(AnimationFormat::Gif));
+        assert_eq!(AnimationFormat::from_path("spin.mp4"), Err(RendererError::UnsupportedOutputFormat("mp4".to_string())));
+    }
+
+    #[test]
+    fn crc_should_match_the_png_specification() {
+        assert_eq!(crc32(b"IEND".iter()), 0xae42_6082);
+    }
+}
@@ -0,0 +1,129 @@
+use color::LinearImage;
+use fxaa::apply_fxaa;
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+
+/// Anti-aliasing applied when a renderer's buffer is resolved into the final image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AntiAliasing {
+    None,
+    /// Render at `factor` times the output resolution on both axes, then downsample.
+    Supersampling {
+        factor: u32,
+        filter: DownsampleFilter,
+    },
+    /// Post-process pass run on the final image, cheap but softer than supersampling.
+    Fxaa,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DownsampleFilter {
+    Box,
+    Lanczos3,
+}
+
+impl AntiAliasing {
+    pub fn supersampling_factor(&self) -> u32 {
+        match *self {
+            AntiAliasing::Supersampling { factor, .. } => factor.max(1),
+            _ => 1,
+        }
+    }
+
+    /// Brings a supersampled linear buffer down to the output resolution, while it is still linear.
+    pub fn resolve_samples(&self, image: &LinearImage) -> LinearImage {
+        match *self {
+            AntiAliasing::Supersampling { factor, filter } => downsample(image, factor.max(1), filter),
+            _ => image.clone(),
+        }
+    }
+
+    /// Post-processes the final tone mapped image.
+    pub fn post_process(&self, image: &RgbaImage) -> RgbaImage {
+        match *self {
+            AntiAliasing::Fxaa => apply_fxaa(image),
+            _ => image.clone(),
+        }
+    }
+}
+
+/// Filters with premultiplied alpha, so transparent samples don't pull the color of partly covered
+/// pixels towards their own.
+pub fn downsample(image: &LinearImage, factor: u32, filter: DownsampleFilter) -> LinearImage {
+    let (width, height) = (image.width() / factor, image.height() / factor);
+    let premultiplied = LinearImage::from_fn(image.width(), image.height(), |x, y| {
+        let [red, green, blue, alpha] = image[(x, y)].data;
+        Rgba([red * alpha, green * alpha, blue * alpha, alpha])
+    });
+    let mut downsampled = match filter {
+        DownsampleFilter::Box => box_downsample(&premultiplied, factor, width, height),
+        DownsampleFilter::Lanczos3 => imageops::resize(&premultiplied, width, height, FilterType::Lanczos3),
+    };
+    for pixel in downsampled.pixels_mut() {
+        let alpha = pixel.data[3];
+        for value in pixel.data[..3].iter_mut() {
+            *value = if alpha > f32::EPSILON { *value / alpha } else { 0.0 };
+        }
+    }
+    downsampled
+}
+
+fn box_downsample(image: &LinearImage, factor: u32, width: u32, height: u32) -> LinearImage {
+    let sample_count = (factor * factor) as f32;
+    LinearImage::from_fn(width, height, |x, y| {
+        let mut sum = [0.0; 4];
+        for sample_x in x * factor..(x + 1) * factor {
+            for sample_y in y * factor..(y + 1) * factor {
+                for (total, value) in sum.iter_mut().zip(image[(sample_x, sample_y)].data.iter()) {
+                    *total += *value;
+                }
+            }
+        }
+        Rgba([sum[0] / sample_count, sum[1] / sample_count, sum[2] / sample_count, sum[3] / sample_count])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_filter_should_average_blocks() {
+        let mut image = LinearImage::new(4, 2);
+        image[(0, 0)] = Rgba([1.0, 1.0, 1.0, 1.0]);
+        image[(1, 1)] = Rgba([1.0, 1.0, 1.0, 1.0]);
+        let downsampled = downsample(&image, 2, DownsampleFilter::Box);
+        assert_eq!(downsampled.dimensions(), (2, 1));
+        assert_eq!(downsampled[(0, 0)], Rgba([1.0, 1.0, 1.0, 0.5]));
+        assert_eq!(downsampled[(1, 0)], Rgba([0.0, 0.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn opaque_edges_over_transparency_should_keep_their_color() {
+        // A white opaque left half over a clear color that is black and fully transparent.
+        let image = LinearImage::from_fn(12, 12, |x, _| if x < 5 { Rgba([1.0, 1.0, 1.0, 1.0]) } else { Rgba([0.0, 0.0, 0.0, 0.0]) });
+        assert_eq!(downsample(&image, 3, DownsampleFilter::Box)[(1, 2)], Rgba([1.0, 1.0, 1.0, 2.0 / 3.0]));
+        for &filter in [DownsampleFilter::Box, DownsampleFilter::Lanczos3].iter() {
+            for pixel in downsample(&image, 3, filter).pixels().filter(|pixel| pixel.data[3] > 0.01) {
+                for &value in pixel.data[..3].iter() {
+                    assert!((value - 1.0).abs() < 1e-4, "{:?} {:?}", filter, pixel);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lanczos_filter_should_produce_output_resolution() {
+        let image = LinearImage::from_pixel(9, 6, Rgba([0.2, 0.4, 0.6, 1.0]));
+        let downsampled = downsample(&image, 3, DownsampleFilter::Lanczos3);
+        assert_eq!(downsampled.dimensions(), (3, 2));
+    }
+
+    #[test]
+    fn no_anti_aliasing_should_not_supersample() {
+        assert_eq!(AntiAliasing::None.supersampling_factor(), 1);
+        assert_eq!(AntiAliasing::Fxaa.supersampling_factor(), 1);
+        let ssaa = AntiAliasing::Supersampling { factor: 4, filter: DownsampleFilter::Box };
+        assert_eq!(ssaa.supersampling_factor(), 4);
+    }
+}
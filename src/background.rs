@@ -0,0 +1,171 @@
+use cgmath::{InnerSpace, Matrix3, SquareMatrix, Vector3};
+use color::{IntoLinear, LinearImage};
+use environment::EnvironmentMap;
+use image::{self, Pixel, Rgba};
+use renderer_error::RendererError;
+use std::path::Path;
+
+/// What `Renderer::clear_to_background` fills the buffer with before the geometry is drawn.
+#[derive(Debug, Clone, Copy)]
+pub enum Background<'a> {
+    Color(Rgba<f32>),
+    /// Blends from `top` at the top of the screen to `bottom` at its bottom.
+    Gradient { top: Rgba<f32>, bottom: Rgba<f32> },
+    Cubemap(&'a Cubemap),
+    Environment(&'a EnvironmentMap),
+}
+
+impl<'a> Background<'a> {
+    /// Linear color of the background seen towards the world space `direction`, at `height` from 0 at
+    /// the bottom of the screen to 1 at its top.
+    pub fn color(&self, direction: Vector3<f64>, height: f64) -> Rgba<f32> {
+        match *self {
+            Background::Color(color) => color,
+            Background::Gradient { top, bottom } => {
+                let weight = height.clamp(0.0, 1.0) as f32;
+                let mut color = bottom;
+                for (value, target) in color.data.iter_mut().zip(top.data.iter()) {
+                    *value += (target - *value) * weight;
+                }
+                color
+            }
+            Background::Cubemap(cubemap) => cubemap.sample(direction),
+            Background::Environment(environment) => environment.radiance(direction).to_rgba(),
+        }
+    }
+}
+
+/// Where the background is seen from: a camera at the center of the scene's sky.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackgroundCamera {
+    /// Turns view space, looking towards -z with +y up, into world space.
+    pub rotation: Matrix3<f64>,
+    /// Vertical field of view in radians.
+    pub field_of_view: f64,
+}
+
+impl BackgroundCamera {
+    /// A camera looking towards -z, the same way as normalized device coordinates.
+    pub fn new(field_of_view: f64) -> Self {
+        BackgroundCamera { rotation: Matrix3::identity(), field_of_view }
+    }
+
+    /// A camera looking towards `direction`, tilted so `up` points up on screen.
+    pub fn looking_towards(direction: Vector3<f64>, up: Vector3<f64>, field_of_view: f64) -> Self {
+        let backward = -direction.normalize();
+        let right = up.cross(backward).normalize();
+        BackgroundCamera { rotation: Matrix3::from_cols(right, backward.cross(right), backward), field_of_view }
+    }
+
+    /// World space direction through the point `(x, y)` of the screen, both in [-1, 1] with +y up.
+    pub fn direction(&self, x: f64, y: f64, aspect_ratio: f64) -> Vector3<f64> {
+        let extent = (self.field_of_view / 2.0).tan();
+        self.rotation * Vector3::new(x * extent * aspect_ratio, y * extent, -1.0)
+    }
+}
+
+/// Six square images around the viewer: +x, -x, +y, -y, +z and -z. Each face is seen from the center
+/// of the cube, upright for the side faces and with the top and bottom faces continuing the -z face,
+/// like the horizontal cross layout skyboxes usually come in.
+#[derive(Debug, Clone)]
+pub struct Cubemap {
+    faces: [LinearImage; 6],
+}
+
+impl Cubemap {
+    /// Takes the faces to hold linear color.
+    pub fn new(faces: [LinearImage; 6]) -> Self {
+        Cubemap { faces }
+    }
+
+    /// Opens the faces as sRGB encoded images, in the same order as `new`.
+    pub fn open<P: AsRef<Path>>(paths: [P; 6]) -> Result<Self, RendererError> {
+        let open = |path: &P| -> Result<LinearImage, RendererError> {
+            let image = image::open(path)?.to_rgba();
+            let (width, height) = image.dimensions();
+            Ok(LinearImage::from_fn(width, height, |x, y| image[(x, y)].into_linear()))
+        };
+        Ok(Cubemap::new([
+            open(&paths[0])?,
+            open(&paths[1])?,
+            open(&paths[2])?,
+            open(&paths[3])?,
+            open(&paths[4])?,
+            open(&paths[5])?,
+        ]))
+    }
+
+    /// Bilinearly filtered color towards `direction`, clamped to the edges of its face.
+    pub fn sample(&self, direction: Vector3<f64>) -> Rgba<f32> {
+        let (x, y, z) = (direction.x, direction.y, direction.z);
+        let (face, right, down, major) = if x.abs() >= y.abs() && x.abs() >= z.abs() {
+            if x > 0.0 { (0, z, -y, x) } else { (1, -z, -y, -x) }
+        } else if y.abs() >= z.abs() {
+            if y > 0.0 { (2, x, -z, y) } else { (3, x, z, -y) }
+        } else if z > 0.0 {
+            (4, -x, -y, z)
+        } else {
+            (5, x, -y, -z)
+        };
+        if major <= 0.0 || direction.magnitude2() == 0.0 {
+            return Rgba([0.0, 0.0, 0.0, 0.0]);
+        }
+        let image = &self.faces[face];
+        let (width, height) = image.dimensions();
+        let column = ((right / major + 1.0) / 2.0 * width as f64 - 0.5).clamp(0.0, (width - 1) as f64);
+        let row = ((down / major + 1.0) / 2.0 * height as f64 - 0.5).clamp(0.0, (height - 1) as f64);
+        let (x0, y0) = (column.floor() as u32, row.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+        let (tx, ty) = ((column - x0 as f64) as f32, (row - y0 as f64) as f32);
+        let mut color = [0.0; 4];
+        for (channel, value) in color.iter_mut().enumerate() {
+            let top = image[(x0, y0)].data[channel] * (1.0 - tx) + image[(x1, y0)].data[channel] * tx;
+            let bottom = image[(x0, y1)].data[channel] * (1.0 - tx) + image[(x1, y1)].data[channel] * tx;
+            *value = top * (1.0 - ty) + bottom * ty;
+        }
+        Rgba(color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(value: f32) -> LinearImage {
+        LinearImage::from_pixel(2, 2, Rgba([value, 0.0, 0.0, 1.0]))
+    }
+
+    #[test]
+    fn every_axis_should_land_on_its_face() {
+        let cubemap = Cubemap::new([solid(0.0), solid(1.0), solid(2.0), solid(3.0), solid(4.0), solid(5.0)]);
+        let axes = [(1.0, 0.0, 0.0), (-1.0, 0.0, 0.0), (0.0, 1.0, 0.0), (0.0, -1.0, 0.0), (0.0, 0.0, 1.0), (0.0, 0.0, -1.0)];
+        for (face, &(x, y, z)) in axes.iter().enumerate() {
+            assert_eq!(cubemap.sample(Vector3::new(x, y, z)).data[0], face as f32);
+        }
+    }
+
+    #[test]
+    fn faces_should_be_upright_and_joined_like_a_cross() {
+        // Red on the left half of every face, green on the top half.
+        let face = LinearImage::from_fn(4, 4, |x, y| Rgba([(x < 2) as u8 as f32, (y < 2) as u8 as f32, 0.0, 1.0]));
+        let cubemap = Cubemap::new([face.clone(), face.clone(), face.clone(), face.clone(), face.clone(), face]);
+        let front_upper_left = cubemap.sample(Vector3::new(-0.5, 0.5, -1.0));
+        assert_eq!(front_upper_left, Rgba([1.0, 1.0, 0.0, 1.0]));
+        let right_upper_left = cubemap.sample(Vector3::new(1.0, 0.5, -0.5));
+        assert_eq!(right_upper_left, Rgba([1.0, 1.0, 0.0, 1.0]));
+        // The bottom edge of the top face meets the top edge of the front face.
+        let top_near_the_front = cubemap.sample(Vector3::new(0.5, 1.0, -0.9));
+        assert_eq!(top_near_the_front, Rgba([0.0, 0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn gradient_should_blend_by_height_and_cameras_should_turn() {
+        let gradient = Background::Gradient { top: Rgba([1.0, 0.0, 0.0, 1.0]), bottom: Rgba([0.0, 0.0, 1.0, 1.0]) };
+        assert_eq!(gradient.color(Vector3::new(0.0, 0.0, -1.0), 0.25), Rgba([0.25, 0.0, 0.75, 1.0]));
+        let camera = BackgroundCamera::looking_towards(Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), 1.0);
+        let forward = camera.direction(0.0, 0.0, 1.0);
+        assert!((forward - Vector3::new(1.0, 0.0, 0.0)).magnitude() < 1e-9);
+        assert!(camera.direction(1.0, 0.0, 1.0).z > 0.0);
+        assert!(camera.direction(0.0, 1.0, 1.0).y > 0.0);
+    }
+}
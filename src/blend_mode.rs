@@ -0,0 +1,125 @@
+use image::Rgba;
+
+/// How a drawn color is combined with the color already in the buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BlendMode {
+    /// Classic "over" compositing of straight (non-premultiplied) alpha colors.
+    #[default]
+    SourceOver,
+    /// Adds the source, weighted by its alpha, to the destination.
+    Additive,
+    /// Multiplies the destination by the source, weighted by its alpha.
+    Multiply,
+    /// "Over" compositing of a premultiplied alpha source. The buffer keeps straight alpha, as with every mode.
+    PremultipliedSourceOver,
+}
+
+impl BlendMode {
+    /// Blends linear colors. Color channels are left unclamped, so additive blending can exceed 1.
+    pub fn blend(&self, source: Rgba<f32>, destination: Rgba<f32>) -> Rgba<f32> {
+        let ([src_r, src_g, src_b, src_alpha], [dst_r, dst_g, dst_b, dst_alpha]) = (source.data, destination.data);
+        let (src, dst) = ([src_r, src_g, src_b], [dst_r, dst_g, dst_b]);
+        let src_alpha = src_alpha.clamp(0.0, 1.0);
+        let (color, alpha) = match *self {
+            BlendMode::SourceOver => {
+                let alpha = src_alpha + dst_alpha * (1.0 - src_alpha);
+                let color = if alpha > 0.0 {
+                    per_channel(src, dst, |s, d| (s * src_alpha + d * dst_alpha * (1.0 - src_alpha)) / alpha)
+                } else {
+                    [0.0; 3]
+                };
+                (color, alpha)
+            }
+            BlendMode::Additive => (
+                per_channel(src, dst, |s, d| d + s * src_alpha),
+                src_alpha + dst_alpha,
+            ),
+            BlendMode::Multiply => (
+                per_channel(src, dst, |s, d| d * (1.0 - src_alpha) + d * s * src_alpha),
+                src_alpha + dst_alpha * (1.0 - src_alpha),
+            ),
+            BlendMode::PremultipliedSourceOver => {
+                let alpha = src_alpha + dst_alpha * (1.0 - src_alpha);
+                let color = if alpha > 0.0 {
+                    per_channel(src, dst, |s, d| (s + d * dst_alpha * (1.0 - src_alpha)) / alpha)
+                } else {
+                    [0.0; 3]
+                };
+                (color, alpha)
+            }
+        };
+        Rgba([color[0], color[1], color[2], alpha.clamp(0.0, 1.0)])
+    }
+
+    /// Blends a source that only partially covers the pixel, as on anti-aliased edges.
+    pub fn blend_with_coverage(&self, source: Rgba<f32>, destination: Rgba<f32>, coverage: f64) -> Rgba<f32> {
+        let coverage = coverage.clamp(0.0, 1.0) as f32;
+        let [red, green, blue, alpha] = source.data;
+        let covered = match *self {
+            BlendMode::PremultipliedSourceOver => Rgba([red * coverage, green * coverage, blue * coverage, alpha * coverage]),
+            _ => Rgba([red, green, blue, alpha * coverage]),
+        };
+        self.blend(covered, destination)
+    }
+}
+
+fn per_channel<F: Fn(f32, f32) -> f32>(src: [f32; 3], dst: [f32; 3], f: F) -> [f32; 3] {
+    [f(src[0], dst[0]), f(src[1], dst[1]), f(src[2], dst[2])]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opaque_source_over_should_replace_destination() {
+        let blended = BlendMode::SourceOver.blend(Rgba([0.1, 0.2, 0.3, 1.0]), Rgba([0.8, 0.8, 0.8, 1.0]));
+        assert_eq!(blended, Rgba([0.1, 0.2, 0.3, 1.0]));
+    }
+
+    #[test]
+    fn half_transparent_source_over_should_mix_colors() {
+        let blended = BlendMode::SourceOver.blend(Rgba([1.0, 0.0, 0.0, 0.5]), Rgba([0.0, 0.0, 1.0, 1.0]));
+        assert_eq!(blended, Rgba([0.5, 0.0, 0.5, 1.0]));
+    }
+
+    #[test]
+    fn source_over_transparent_background_should_keep_source_color() {
+        let blended = BlendMode::SourceOver.blend(Rgba([1.0, 0.0, 0.0, 0.5]), Rgba([0.0, 0.0, 0.0, 0.0]));
+        assert_eq!(blended, Rgba([1.0, 0.0, 0.0, 0.5]));
+    }
+
+    #[test]
+    fn additive_should_go_past_one() {
+        let blended = BlendMode::Additive.blend(Rgba([0.75, 0.5, 0.0, 1.0]), Rgba([0.5, 0.25, 0.25, 1.0]));
+        assert_eq!(blended, Rgba([1.25, 0.75, 0.25, 1.0]));
+    }
+
+    #[test]
+    fn multiply_should_darken() {
+        let blended = BlendMode::Multiply.blend(Rgba([0.5, 1.0, 0.0, 1.0]), Rgba([0.5, 0.5, 0.5, 1.0]));
+        assert_eq!(blended, Rgba([0.25, 0.5, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn premultiplied_source_over_should_add_the_scaled_destination() {
+        let blended = BlendMode::PremultipliedSourceOver.blend(Rgba([0.25, 0.0, 0.0, 0.5]), Rgba([0.0, 0.0, 0.5, 1.0]));
+        assert_eq!(blended, Rgba([0.25, 0.0, 0.25, 1.0]));
+    }
+
+    #[test]
+    fn premultiplied_source_over_should_leave_straight_alpha_in_the_buffer() {
+        let premultiplied = BlendMode::PremultipliedSourceOver.blend(Rgba([0.5, 0.0, 0.0, 0.5]), Rgba([0.0, 0.0, 0.0, 0.0]));
+        let straight = BlendMode::SourceOver.blend(Rgba([1.0, 0.0, 0.0, 0.5]), Rgba([0.0, 0.0, 0.0, 0.0]));
+        assert_eq!((premultiplied, straight), (Rgba([1.0, 0.0, 0.0, 0.5]), Rgba([1.0, 0.0, 0.0, 0.5])));
+        let over_half = BlendMode::PremultipliedSourceOver.blend(Rgba([0.25, 0.25, 0.25, 0.5]), Rgba([1.0, 0.0, 0.0, 0.5]));
+        let straight = BlendMode::SourceOver.blend(Rgba([0.5, 0.5, 0.5, 0.5]), Rgba([1.0, 0.0, 0.0, 0.5]));
+        assert_eq!(over_half, straight);
+    }
+
+    #[test]
+    fn coverage_should_scale_the_source_alpha() {
+        let blended = BlendMode::SourceOver.blend_with_coverage(Rgba([1.0, 1.0, 1.0, 1.0]), Rgba([0.0, 0.0, 0.0, 1.0]), 0.5);
+        assert_eq!(blended, Rgba([0.5, 0.5, 0.5, 1.0]));
+    }
+}
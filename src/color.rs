@@ -0,0 +1,93 @@
+use image::{ImageBuffer, Rgb, Rgba};
+
+/// Linear light RGBA with straight alpha, as held by the renderer's color buffer.
+/// Color channels may exceed 1 until the image is tone mapped.
+pub type LinearImage = ImageBuffer<Rgba<f32>, Vec<f32>>;
+
+pub const TRANSPARENT: Rgba<f32> = Rgba { data: [0.0, 0.0, 0.0, 0.0] };
+
+/// Colors that can be drawn with. 8 bit colors are taken to be sRGB encoded, float colors to be linear.
+pub trait IntoLinear {
+    fn into_linear(self) -> Rgba<f32>;
+}
+
+impl IntoLinear for Rgb<u8> {
+    fn into_linear(self) -> Rgba<f32> {
+        let [red, green, blue] = self.data;
+        Rgba([srgb_to_linear(red), srgb_to_linear(green), srgb_to_linear(blue), 1.0])
+    }
+}
+
+impl IntoLinear for Rgba<u8> {
+    fn into_linear(self) -> Rgba<f32> {
+        let [red, green, blue, alpha] = self.data;
+        Rgba([srgb_to_linear(red), srgb_to_linear(green), srgb_to_linear(blue), alpha as f32 / 255.0])
+    }
+}
+
+impl IntoLinear for Rgb<f32> {
+    fn into_linear(self) -> Rgba<f32> {
+        let [red, green, blue] = self.data;
+        Rgba([red, green, blue, 1.0])
+    }
+}
+
+impl IntoLinear for Rgba<f32> {
+    fn into_linear(self) -> Rgba<f32> {
+        self
+    }
+}
+
+pub fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a linear value, clamped to [0, 1], with the sRGB transfer function.
+pub fn linear_to_srgb(value: f32) -> u8 {
+    (encode_srgb(value) * 255.0).round() as u8
+}
+
+/// Like `linear_to_srgb`, but keeps the precision, for quantizing later.
+pub fn encode_srgb(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Converts back to 8 bit sRGB without any tone mapping, clamping what is out of range.
+pub fn to_srgb(color: Rgba<f32>) -> Rgba<u8> {
+    let [red, green, blue, alpha] = color.data;
+    Rgba([
+        linear_to_srgb(red),
+        linear_to_srgb(green),
+        linear_to_srgb(blue),
+        (alpha.clamp(0.0, 1.0) * 255.0).round() as u8,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_srgb_value_should_survive_a_round_trip() {
+        for value in 0..=255 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(value)), value);
+        }
+    }
+
+    #[test]
+    fn srgb_mid_gray_should_be_darker_in_linear_light() {
+        let linear = Rgb([128u8, 128, 128]).into_linear();
+        assert!((linear.data[0] - 0.2158).abs() < 0.001);
+        assert_eq!(linear.data[3], 1.0);
+    }
+}
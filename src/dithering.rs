@@ -0,0 +1,299 @@
+use color::LinearImage;
+use image::{Rgb, Rgba, RgbaImage};
+use std::sync::OnceLock;
+
+const BLUE_NOISE_SIZE: usize = 32;
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+
+/// How the error of rounding to the output palette is spread out, trading banding for fine noise.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Dithering {
+    /// Rounds every pixel to the nearest color.
+    #[default]
+    None,
+    /// Ordered dither with an 8x8 Bayer matrix, which leaves a regular cross-hatch pattern.
+    Bayer,
+    /// Ordered dither with a 32x32 void-and-cluster threshold map, whose noise is hardly noticeable.
+    BlueNoise,
+    /// Error diffusion, which keeps the most detail but lets the pattern shift as the image changes.
+    FloydSteinberg,
+}
+
+/// The colors the final image is reduced to. Alpha always keeps 8 bits.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Palette {
+    /// All 256 levels per channel.
+    #[default]
+    TrueColor,
+    /// Evenly spaced levels per channel, e.g. 2 for the 8 corners of the RGB cube.
+    Levels(u8),
+    /// Any set of colors, of which the nearest is picked.
+    Colors(Vec<Rgb<u8>>),
+}
+
+impl Palette {
+    /// Rough distance between neighbouring colors, which ordered dithering has to bridge.
+    fn spacing(&self) -> f32 {
+        match *self {
+            Palette::TrueColor => 1.0 / 255.0,
+            Palette::Levels(levels) => 1.0 / (levels.max(2) - 1) as f32,
+            Palette::Colors(ref colors) => (1.0 / ((colors.len() as f32).cbrt() - 1.0)).min(1.0),
+        }
+    }
+
+    fn nearest(&self, color: [f32; 3]) -> [f32; 3] {
+        let round_to = |value: f32, steps: f32| (value.clamp(0.0, 1.0) * steps).round() / steps;
+        match *self {
+            Palette::TrueColor => [round_to(color[0], 255.0), round_to(color[1], 255.0), round_to(color[2], 255.0)],
+            Palette::Levels(levels) => {
+                let steps = (levels.max(2) - 1) as f32;
+                [round_to(color[0], steps), round_to(color[1], steps), round_to(color[2], steps)]
+            }
+            Palette::Colors(ref colors) => {
+                let distance = |candidate: &Rgb<u8>| {
+                    candidate
+                        .data
+                        .iter()
+                        .zip(color.iter())
+                        .map(|(&channel, &value)| (channel as f32 / 255.0 - value).powi(2))
+                        .sum::<f32>()
+                };
+                match colors.iter().min_by(|a, b| distance(a).total_cmp(&distance(b))) {
+                    Some(nearest) => {
+                        let [red, green, blue] = nearest.data;
+                        [red as f32 / 255.0, green as f32 / 255.0, blue as f32 / 255.0]
+                    }
+                    None => color,
+                }
+            }
+        }
+    }
+}
+
+/// Reduces an image of display encoded values in [0, 1], such as `tone_map_encoded` produces,
+/// to 8 bits per channel and the colors of `palette`.
+pub fn dither(image: &LinearImage, dithering: Dithering, palette: &Palette) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let mut colors: Vec<[f32; 3]> = image.pixels().map(|pixel| [pixel.data[0], pixel.data[1], pixel.data[2]]).collect();
+    match dithering {
+        Dithering::None => {}
+        Dithering::Bayer => offset_by_thresholds(&mut colors, width, palette.spacing(), bayer_threshold),
+        Dithering::BlueNoise => {
+            let thresholds = blue_noise_thresholds();
+            offset_by_thresholds(&mut colors, width, palette.spacing(), |x, y| {
+                thresholds[(y as usize % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE + x as usize % BLUE_NOISE_SIZE]
+            });
+        }
+        Dithering::FloydSteinberg => diffuse_errors(&mut colors, width, height, palette),
+    }
+    RgbaImage::from_fn(width, height, |x, y| {
+        let index = (y * width + x) as usize;
+        let color = match dithering {
+            Dithering::FloydSteinberg => colors[index],
+            _ => palette.nearest(colors[index]),
+        };
+        let to_u8 = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Rgba([to_u8(color[0]), to_u8(color[1]), to_u8(color[2]), to_u8(image[(x, y)].data[3])])
+    })
+}
+
+fn offset_by_thresholds<F: Fn(u32, u32) -> f32>(colors: &mut [[f32; 3]], width: u32, spacing: f32, threshold: F) {
+    for (index, color) in colors.iter_mut().enumerate() {
+        let offset = (threshold(index as u32 % width, index as u32 / width) - 0.5) * spacing;
+        for value in color.iter_mut() {
+            *value += offset;
+        }
+    }
+}
+
+/// Rounds the pixels in reading order, pushing each one's error onto the neighbours not yet rounded.
+/// Leaves the rounded colors in `colors`.
+fn diffuse_errors(colors: &mut [[f32; 3]], width: u32, height: u32, palette: &Palette) {
+    let (width, height) = (width as i64, height as i64);
+    for y in 0..height {
+        for x in 0..width {
+            let index = (y * width + x) as usize;
+            let old = colors[index];
+            let new = palette.nearest(old);
+            colors[index] = new;
+            for &(offset_x, offset_y, weight) in [(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)].iter() {
+                let (neighbour_x, neighbour_y) = (x + offset_x, y + offset_y);
+                if neighbour_x < 0 || neighbour_x >= width || neighbour_y >= height {
+                    continue;
+                }
+                let neighbour = &mut colors[(neighbour_y * width + neighbour_x) as usize];
+                for channel in 0..3 {
+                    neighbour[channel] += (old[channel] - new[channel]) * weight / 16.0;
+                }
+            }
+        }
+    }
+}
+
+/// Threshold in (0, 1) from the recursively built 8x8 Bayer matrix.
+fn bayer_threshold(x: u32, y: u32) -> f32 {
+    let (x, y) = (x % 8, y % 8);
+    let mut rank = 0;
+    for bit in 0..3 {
+        rank = rank << 2 | ((x ^ y) >> bit & 1) << 1 | (y >> bit & 1);
+    }
+    (rank as f32 + 0.5) / 64.0
+}
+
+/// Points on a torus, with the Gaussian weighted closeness of every cell to all of them.
+#[derive(Clone)]
+struct EnergyMap {
+    kernel: Vec<f32>,
+    energy: Vec<f32>,
+    points: Vec<bool>,
+}
+
+impl EnergyMap {
+    fn new() -> EnergyMap {
+        let count = BLUE_NOISE_SIZE * BLUE_NOISE_SIZE;
+        let wrap = |offset: usize| (offset as f32).min((BLUE_NOISE_SIZE - offset) as f32);
+        let kernel = (0..count)
+            .map(|index| {
+                let (offset_x, offset_y) = (wrap(index % BLUE_NOISE_SIZE), wrap(index / BLUE_NOISE_SIZE));
+                (-(offset_x * offset_x + offset_y * offset_y) / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA)).exp()
+            })
+            .collect();
+        EnergyMap { kernel, energy: vec![0.0; count], points: vec![false; count] }
+    }
+
+    fn set(&mut self, index: usize, point: bool) {
+        if self.points[index] == point {
+            return;
+        }
+        self.points[index] = point;
+        let sign = if point { 1.0 } else { -1.0 };
+        let size = BLUE_NOISE_SIZE;
+        let (point_x, point_y) = (index % size, index / size);
+        for (cell, energy) in self.energy.iter_mut().enumerate() {
+            let offset_x = (cell % size + size - point_x) % size;
+            let offset_y = (cell / size + size - point_y) % size;
+            *energy += sign * self.kernel[offset_y * size + offset_x];
+        }
+    }
+
+    fn point_count(&self) -> usize {
+        self.points.iter().filter(|&&point| point).count()
+    }
+
+    fn tightest_cluster(&self) -> usize {
+        self.extreme(true, |energy, best| energy > best)
+    }
+
+    fn largest_void(&self) -> usize {
+        self.extreme(false, |energy, best| energy < best)
+    }
+
+    fn extreme<F: Fn(f32, f32) -> bool>(&self, point: bool, better: F) -> usize {
+        let mut best: Option<usize> = None;
+        for (index, &energy) in self.energy.iter().enumerate() {
+            if self.points[index] == point && best.is_none_or(|best| better(energy, self.energy[best])) {
+                best = Some(index);
+            }
+        }
+        best.unwrap_or(0)
+    }
+}
+
+/// The blue noise threshold map, built on first use and shared by every `dither` after.
+fn blue_noise_thresholds() -> &'static [f32] {
+    static THRESHOLDS: OnceLock<Vec<f32>> = OnceLock::new();
+    THRESHOLDS.get_or_init(void_and_cluster)
+}
+
+/// Ulichney's void-and-cluster method: every cell is ranked by the order it fills the largest remaining
+/// gap in, giving a threshold map without low frequencies that also tiles seamlessly.
+fn void_and_cluster() -> Vec<f32> {
+    let count = BLUE_NOISE_SIZE * BLUE_NOISE_SIZE;
+    let mut map = EnergyMap::new();
+    // Seed with a low discrepancy pattern, then move points from clusters into voids until stable.
+    for step in 0..count / 10 {
+        let x = (step as f32 * 0.754_877_7).fract() * BLUE_NOISE_SIZE as f32;
+        let y = (step as f32 * 0.569_840_3).fract() * BLUE_NOISE_SIZE as f32;
+        map.set(y as usize * BLUE_NOISE_SIZE + x as usize, true);
+    }
+    for _ in 0..count {
+        let cluster = map.tightest_cluster();
+        map.set(cluster, false);
+        let void = map.largest_void();
+        map.set(void, true);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; count];
+    let prototype = map.clone();
+    let seeded = map.point_count();
+    for rank in (0..seeded).rev() {
+        let cluster = map.tightest_cluster();
+        map.set(cluster, false);
+        ranks[cluster] = rank;
+    }
+    map = prototype;
+    for rank in seeded..count {
+        let void = map.largest_void();
+        map.set(void, true);
+        ranks[void] = rank;
+    }
+    ranks.iter().map(|&rank| (rank as f32 + 0.5) / count as f32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> LinearImage {
+        LinearImage::from_fn(64, 8, |x, _| {
+            let value = x as f32 / 63.0 * 0.49;
+            Rgba([value, value, value, 1.0])
+        })
+    }
+
+    fn mean_red(image: &RgbaImage) -> f32 {
+        image.pixels().map(|pixel| pixel.data[0] as f32 / 255.0).sum::<f32>() / (image.width() * image.height()) as f32
+    }
+
+    #[test]
+    fn bayer_thresholds_should_each_appear_once() {
+        let mut ranks: Vec<u32> = (0..64).map(|index| (bayer_threshold(index % 8, index / 8) * 64.0) as u32).collect();
+        ranks.sort();
+        assert_eq!(ranks, (0..64).collect::<Vec<u32>>());
+        assert_eq!(bayer_threshold(0, 0), 0.5 / 64.0);
+    }
+
+    #[test]
+    fn blue_noise_thresholds_should_each_appear_once() {
+        let mut ranks: Vec<u32> = blue_noise_thresholds().iter().map(|&threshold| (threshold * 1024.0) as u32).collect();
+        ranks.sort();
+        assert_eq!(ranks, (0..1024).collect::<Vec<u32>>());
+        assert!(std::ptr::eq(blue_noise_thresholds(), blue_noise_thresholds()));
+    }
+
+    #[test]
+    fn dithering_should_keep_to_the_palette_and_the_average_brightness() {
+        let image = gradient();
+        let palette = Palette::Levels(2);
+        for &dithering in [Dithering::Bayer, Dithering::BlueNoise, Dithering::FloydSteinberg].iter() {
+            let dithered = dither(&image, dithering, &palette);
+            assert!(dithered.pixels().all(|pixel| pixel.data[0] == 0 || pixel.data[0] == 255));
+            assert!((mean_red(&dithered) - 0.245).abs() < 0.02);
+        }
+        assert_eq!(mean_red(&dither(&image, Dithering::None, &palette)), 0.0);
+    }
+
+    #[test]
+    fn colors_should_snap_to_the_nearest_palette_entry() {
+        let image = LinearImage::from_pixel(1, 1, Rgba([0.9, 0.2, 0.1, 0.5]));
+        let palette = Palette::Colors(vec![Rgb([0, 0, 0]), Rgb([255, 0, 0]), Rgb([255, 255, 255])]);
+        assert_eq!(dither(&image, Dithering::None, &palette)[(0, 0)], Rgba([255, 0, 0, 128]));
+        let not_a_number = LinearImage::from_pixel(1, 1, Rgba([f32::NAN, 0.2, 0.1, 1.0]));
+        for &dithering in [Dithering::None, Dithering::FloydSteinberg].iter() {
+            assert_eq!(dither(&not_a_number, dithering, &palette)[(0, 0)].data[3], 255);
+        }
+    }
+}
@@ -1,4 +0,0 @@
-pub enum DrawMode {
-    Normal,
-    Wireframe,
-}
@@ -0,0 +1,314 @@
+use cgmath::{InnerSpace, Vector3};
+use color::{IntoLinear, LinearImage};
+use image::hdr::HDRDecoder;
+use image::{self, Rgb, Rgba};
+use renderer_error::RendererError;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Number of prefiltered specular levels, for roughness 0, 0.2, ... 1.
+const SPECULAR_LEVELS: usize = 6;
+/// Width of the first blurred level; the sharp level keeps the source's resolution.
+const PREFILTERED_WIDTH: u32 = 256;
+const PREFILTER_SAMPLES: u32 = 64;
+/// Width of the downsampled image projected onto spherical harmonics.
+const IRRADIANCE_WIDTH: u32 = 64;
+
+/// Light arriving from every direction, from an equirectangular (latitude-longitude) image, with its
+/// diffuse irradiance and glossy reflections precomputed for image-based lighting.
+/// Directions are in world space, the same as the normals of the G-buffer: the top row of the image is
+/// straight up (+y) and its center lies towards -z, into the screen.
+#[derive(Debug, Clone)]
+pub struct EnvironmentMap {
+    /// Level 0 is the source, the others are blurred for evenly spaced roughness at decreasing resolutions.
+    specular_levels: Vec<LinearImage>,
+    /// Second order spherical harmonics coefficients of the radiance.
+    irradiance: [[f32; 3]; 9],
+}
+
+impl EnvironmentMap {
+    /// Takes the image to hold linear radiance.
+    pub fn new(image: LinearImage) -> Self {
+        let mut pyramid = vec![image];
+        loop {
+            let (width, height) = pyramid[pyramid.len() - 1].dimensions();
+            if width <= 8 || height <= 4 {
+                break;
+            }
+            let next = downsample(&pyramid[pyramid.len() - 1]);
+            pyramid.push(next);
+        }
+        let irradiance = project_irradiance(&pyramid);
+        let mut specular_levels = vec![pyramid[0].clone()];
+        let (width, height) = pyramid[0].dimensions();
+        let (mut level_width, mut level_height) = (width.min(PREFILTERED_WIDTH), height.min(PREFILTERED_WIDTH / 2));
+        for level in 1..SPECULAR_LEVELS {
+            let roughness = level as f64 / (SPECULAR_LEVELS - 1) as f64;
+            specular_levels.push(prefilter(&pyramid, roughness, level_width.max(8), level_height.max(4)));
+            level_width /= 2;
+            level_height /= 2;
+        }
+        EnvironmentMap { specular_levels, irradiance }
+    }
+
+    /// Opens a Radiance `.hdr` file as it is, or any other image as sRGB encoded color.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, RendererError> {
+        let path = path.as_ref();
+        let is_hdr = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("hdr"));
+        let image = if is_hdr {
+            let decoder = HDRDecoder::new(BufReader::new(File::open(path)?))?;
+            let metadata = decoder.metadata();
+            let pixels = decoder.read_image_hdr()?;
+            LinearImage::from_fn(metadata.width, metadata.height, |x, y| {
+                let [red, green, blue] = pixels[(y * metadata.width + x) as usize].data;
+                Rgba([red, green, blue, 1.0])
+            })
+        } else {
+            let image = image::open(path)?.to_rgb();
+            let (width, height) = image.dimensions();
+            LinearImage::from_fn(width, height, |x, y| image[(x, y)].into_linear())
+        };
+        Ok(EnvironmentMap::new(image))
+    }
+
+    /// The unfiltered light coming from `direction`, as seen in the background.
+    pub fn radiance(&self, direction: Vector3<f64>) -> Rgb<f32> {
+        Rgb(sample(&self.specular_levels[0], direction))
+    }
+
+    /// Irradiance on a surface facing `normal`, divided by pi so that it can be used as the ambient
+    /// light of `lambert`: a uniform environment gives back its own color.
+    pub fn irradiance(&self, normal: Vector3<f64>) -> Rgb<f32> {
+        let normal = normal.normalize();
+        let basis = sh_basis(normal);
+        // Convolution with the clamped cosine, per band, already divided by pi.
+        let bands = [1.0, 2.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0, 0.25, 0.25, 0.25, 0.25, 0.25];
+        let mut irradiance = [0.0; 3];
+        for ((coefficient, &weight), &band) in self.irradiance.iter().zip(basis.iter()).zip(bands.iter()) {
+            for (total, value) in irradiance.iter_mut().zip(coefficient.iter()) {
+                *total += value * (weight * band) as f32;
+            }
+        }
+        Rgb([irradiance[0].max(0.0), irradiance[1].max(0.0), irradiance[2].max(0.0)])
+    }
+
+    /// Light reflected towards `direction` by a surface of the given roughness, blended between the
+    /// two closest prefiltered levels. Multiply it by `environment_brdf` for the specular term.
+    pub fn specular(&self, direction: Vector3<f64>, roughness: f32) -> Rgb<f32> {
+        let position = roughness.clamp(0.0, 1.0) * (self.specular_levels.len() - 1) as f32;
+        let lower = position.floor() as usize;
+        let upper = (lower + 1).min(self.specular_levels.len() - 1);
+        let weight = position - lower as f32;
+        Rgb(mix(sample(&self.specular_levels[lower], direction), sample(&self.specular_levels[upper], direction), weight))
+    }
+}
+
+/// Where `direction` lands in an equirectangular image, in [0, 1] from the left and from the top.
+fn to_equirectangular(direction: Vector3<f64>) -> (f64, f64) {
+    let direction = direction.normalize();
+    let u = 0.5 + direction.x.atan2(-direction.z) / (2.0 * PI);
+    let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+    (u, v)
+}
+
+fn from_equirectangular(u: f64, v: f64) -> Vector3<f64> {
+    let (azimuth, polar) = ((u - 0.5) * 2.0 * PI, v * PI);
+    Vector3::new(polar.sin() * azimuth.sin(), polar.cos(), -polar.sin() * azimuth.cos())
+}
+
+/// Bilinear lookup that wraps around horizontally and clamps at the poles.
+fn sample(image: &LinearImage, direction: Vector3<f64>) -> [f32; 3] {
+    let (width, height) = image.dimensions();
+    let (u, v) = to_equirectangular(direction);
+    let x = u * width as f64 - 0.5;
+    let y = (v * height as f64 - 0.5).clamp(0.0, (height - 1) as f64);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = ((x - x0) as f32, (y - y0) as f32);
+    let texel = |x: f64, y: f64| image[((x as i64).rem_euclid(width as i64) as u32, (y as u32).min(height - 1))].data;
+    let (top_left, top_right) = (texel(x0, y0), texel(x0 + 1.0, y0));
+    let (bottom_left, bottom_right) = (texel(x0, y0 + 1.0), texel(x0 + 1.0, y0 + 1.0));
+    let mut color = [0.0; 3];
+    for (channel, value) in color.iter_mut().enumerate() {
+        let top = top_left[channel] * (1.0 - tx) + top_right[channel] * tx;
+        let bottom = bottom_left[channel] * (1.0 - tx) + bottom_right[channel] * tx;
+        *value = top * (1.0 - ty) + bottom * ty;
+    }
+    color
+}
+
+/// Like `sample`, blending between the two pyramid levels around the fractional `level`.
+fn sample_pyramid(pyramid: &[LinearImage], direction: Vector3<f64>, level: f64) -> [f32; 3] {
+    let level = level.clamp(0.0, (pyramid.len() - 1) as f64);
+    let lower = level.floor() as usize;
+    let upper = (lower + 1).min(pyramid.len() - 1);
+    let weight = (level - lower as f64) as f32;
+    mix(sample(&pyramid[lower], direction), sample(&pyramid[upper], direction), weight)
+}
+
+fn mix(from: [f32; 3], to: [f32; 3], weight: f32) -> [f32; 3] {
+    let mut mixed = from;
+    for (value, target) in mixed.iter_mut().zip(to.iter()) {
+        *value += (target - *value) * weight;
+    }
+    mixed
+}
+
+fn downsample(image: &LinearImage) -> LinearImage {
+    let (width, height) = image.dimensions();
+    LinearImage::from_fn((width / 2).max(1), (height / 2).max(1), |x, y| {
+        let mut color = [0.0; 4];
+        for &(dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+            let texel = image[((x * 2 + dx).min(width - 1), (y * 2 + dy).min(height - 1))];
+            for (total, value) in color.iter_mut().zip(texel.data.iter()) {
+                *total += value * 0.25;
+            }
+        }
+        Rgba(color)
+    })
+}
+
+/// The real spherical harmonics up to the second band.
+fn sh_basis(direction: Vector3<f64>) -> [f64; 9] {
+    let (x, y, z) = (direction.x, direction.y, direction.z);
+    [
+        0.282_095,
+        0.488_603 * y,
+        0.488_603 * z,
+        0.488_603 * x,
+        1.092_548 * x * y,
+        1.092_548 * y * z,
+        0.315_392 * (3.0 * z * z - 1.0),
+        1.092_548 * x * z,
+        0.546_274 * (x * x - y * y),
+    ]
+}
+
+/// Integrates the radiance against the spherical harmonics over a small level of the pyramid.
+fn project_irradiance(pyramid: &[LinearImage]) -> [[f32; 3]; 9] {
+    let image = pyramid
+        .iter()
+        .find(|level| level.width() <= IRRADIANCE_WIDTH)
+        .unwrap_or(&pyramid[pyramid.len() - 1]);
+    let (width, height) = image.dimensions();
+    let mut coefficients = [[0.0_f64; 3]; 9];
+    for (x, y, texel) in image.enumerate_pixels() {
+        let v = (y as f64 + 0.5) / height as f64;
+        let direction = from_equirectangular((x as f64 + 0.5) / width as f64, v);
+        let solid_angle = (2.0 * PI / width as f64) * (PI / height as f64) * (v * PI).sin();
+        for (coefficient, weight) in coefficients.iter_mut().zip(sh_basis(direction).iter()) {
+            for (total, &value) in coefficient.iter_mut().zip(texel.data.iter()) {
+                *total += value as f64 * weight * solid_angle;
+            }
+        }
+    }
+    let mut result = [[0.0; 3]; 9];
+    for (target, coefficient) in result.iter_mut().zip(coefficients.iter()) {
+        *target = [coefficient[0] as f32, coefficient[1] as f32, coefficient[2] as f32];
+    }
+    result
+}
+
+/// Convolves the environment with the GGX lobe of `roughness`, taking the view and normal to be the
+/// reflection direction. Samples are importance sampled and read from the pyramid level matching the
+/// solid angle they stand for, which keeps the result smooth with few samples.
+fn prefilter(pyramid: &[LinearImage], roughness: f64, width: u32, height: u32) -> LinearImage {
+    let alpha = roughness * roughness;
+    let (source_width, source_height) = pyramid[0].dimensions();
+    let texel_solid_angle = 4.0 * PI / (source_width * source_height) as f64;
+    LinearImage::from_fn(width, height, |x, y| {
+        let normal = from_equirectangular((x as f64 + 0.5) / width as f64, (y as f64 + 0.5) / height as f64);
+        let (tangent, bitangent) = basis(normal);
+        let (mut color, mut total_weight) = ([0.0; 3], 0.0);
+        for index in 0..PREFILTER_SAMPLES {
+            let (first, second) = hammersley(index, PREFILTER_SAMPLES);
+            let azimuth = 2.0 * PI * first;
+            let cos_theta = ((1.0 - second) / (1.0 + (alpha * alpha - 1.0) * second)).sqrt();
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            let half = tangent * (sin_theta * azimuth.cos()) + bitangent * (sin_theta * azimuth.sin()) + normal * cos_theta;
+            let light = half * (2.0 * normal.dot(half)) - normal;
+            let n_dot_l = normal.dot(light);
+            if n_dot_l <= 0.0 {
+                continue;
+            }
+            let denominator = cos_theta * cos_theta * (alpha * alpha - 1.0) + 1.0;
+            let probability = alpha * alpha / (PI * denominator * denominator) / 4.0;
+            let sample_solid_angle = 1.0 / (PREFILTER_SAMPLES as f64 * probability + 1e-4);
+            let level = 0.5 * (sample_solid_angle / texel_solid_angle).log2() + 1.0;
+            let radiance = sample_pyramid(pyramid, light, level);
+            for (total, value) in color.iter_mut().zip(radiance.iter()) {
+                *total += value * n_dot_l as f32;
+            }
+            total_weight += n_dot_l as f32;
+        }
+        Rgba([color[0] / total_weight, color[1] / total_weight, color[2] / total_weight, 1.0])
+    })
+}
+
+fn basis(normal: Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
+    let up = if normal.y.abs() < 0.999 { Vector3::new(0.0, 1.0, 0.0) } else { Vector3::new(1.0, 0.0, 0.0) };
+    let tangent = up.cross(normal).normalize();
+    (tangent, normal.cross(tangent))
+}
+
+fn hammersley(index: u32, count: u32) -> (f64, f64) {
+    (index as f64 / count as f64, index.reverse_bits() as f64 / 4_294_967_296.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// White sky above the horizon, black ground below it.
+    fn sky() -> EnvironmentMap {
+        EnvironmentMap::new(LinearImage::from_fn(64, 32, |_, y| {
+            let value = if y < 16 { 1.0 } else { 0.0 };
+            Rgba([value, value, value, 1.0])
+        }))
+    }
+
+    #[test]
+    fn uniform_environment_should_light_like_a_constant_ambient() {
+        let environment = EnvironmentMap::new(LinearImage::from_pixel(32, 16, Rgba([0.5, 1.0, 2.0, 1.0])));
+        for &normal in [Vector3::new(0.0, 1.0, 0.0), Vector3::new(1.0, 0.0, 1.0), Vector3::new(0.0, -1.0, 0.0)].iter() {
+            let irradiance = environment.irradiance(normal);
+            assert!((irradiance.data[2] - 2.0).abs() < 0.02, "{:?}", irradiance);
+            let specular = environment.specular(normal, 0.7);
+            assert!((specular.data[1] - 1.0).abs() < 0.01, "{:?}", specular);
+        }
+    }
+
+    #[test]
+    fn irradiance_should_follow_the_sky() {
+        let environment = sky();
+        let (up, side, down) = (
+            environment.irradiance(Vector3::new(0.0, 1.0, 0.0)).data[0],
+            environment.irradiance(Vector3::new(1.0, 0.0, 0.0)).data[0],
+            environment.irradiance(Vector3::new(0.0, -1.0, 0.0)).data[0],
+        );
+        assert!(up > 0.9 && down < 0.1, "{} {}", up, down);
+        assert!((side - 0.5).abs() < 0.05, "{}", side);
+    }
+
+    #[test]
+    fn rougher_reflections_should_blur_the_horizon() {
+        let environment = sky();
+        let above_horizon = Vector3::new(0.0, 0.1, -1.0);
+        assert_eq!(environment.specular(above_horizon, 0.0).data[0], 1.0);
+        let rough = environment.specular(above_horizon, 1.0).data[0];
+        assert!(rough > 0.3 && rough < 0.9, "{}", rough);
+    }
+
+    #[test]
+    fn center_of_the_image_should_lie_into_the_screen() {
+        let environment = EnvironmentMap::new(LinearImage::from_fn(16, 8, |x, _| {
+            let value = if x == 7 || x == 8 { 1.0 } else { 0.0 };
+            Rgba([value, value, value, 1.0])
+        }));
+        assert_eq!(environment.radiance(Vector3::new(0.0, 0.0, -1.0)).data[0], 1.0);
+        assert_eq!(environment.radiance(Vector3::new(0.0, 0.0, 1.0)).data[0], 0.0);
+        let (u, v) = to_equirectangular(from_equirectangular(0.3, 0.6));
+        assert!((u - 0.3).abs() < 1e-9 && (v - 0.6).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,118 @@
+use cgmath::{Matrix4, SquareMatrix, Vector3};
+use image::Rgb;
+
+/// How the fog thickens with the distance from the viewer. Distances are in normalized device
+/// coordinates, from 0 at the near plane (z = 1) to 2 at the far plane (z = -1).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FogMode {
+    /// No fog before `start`, nothing but fog from `end` on.
+    Linear { start: f64, end: f64 },
+    Exponential { density: f64 },
+    /// Keeps the near field clearer than `Exponential` and closes in faster further away.
+    ExponentialSquared { density: f64 },
+}
+
+/// Fog that lies low: full strength up to `base`, thinning out by `falloff` per unit of height above it.
+/// Heights are world space y.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeightFog {
+    pub base: f64,
+    pub falloff: f64,
+    /// Takes normalized device coordinates back to world space. The identity by default, for scenes
+    /// drawn in normalized device coordinates directly.
+    pub inverse_view_projection: Matrix4<f64>,
+}
+
+impl HeightFog {
+    pub fn new(base: f64, falloff: f64) -> Self {
+        HeightFog { base, falloff, inverse_view_projection: Matrix4::identity() }
+    }
+
+    pub fn set_inverse_view_projection(&mut self, inverse_view_projection: Matrix4<f64>) {
+        self.inverse_view_projection = inverse_view_projection;
+    }
+
+    /// World space height of the point at `ndc` in normalized device coordinates.
+    pub fn height_at(&self, ndc: Vector3<f64>) -> f64 {
+        let world = self.inverse_view_projection * ndc.extend(1.0);
+        world.y / world.w
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fog {
+    pub mode: FogMode,
+    /// Linear color everything fades towards.
+    pub color: Rgb<f32>,
+    pub height: Option<HeightFog>,
+    /// Whether pixels nothing was drawn to are fogged as if they were at the far plane. On by default,
+    /// so the background fades into the fog along with the distant geometry.
+    pub fog_background: bool,
+}
+
+impl Fog {
+    pub fn new(mode: FogMode, color: Rgb<f32>) -> Self {
+        Fog { mode, color, height: None, fog_background: true }
+    }
+
+    pub fn set_height(&mut self, height: Option<HeightFog>) {
+        self.height = height;
+    }
+
+    pub fn set_fog_background(&mut self, fog_background: bool) {
+        self.fog_background = fog_background;
+    }
+
+    /// Share of the fog color in a point at `distance` and `height`, from 0 (clear) to 1 (hidden).
+    pub fn amount(&self, distance: f64, height: f64) -> f32 {
+        let thickness = match self.height {
+            Some(fog) => (-fog.falloff * (height - fog.base).max(0.0)).exp(),
+            None => 1.0,
+        };
+        let amount = match self.mode {
+            FogMode::Linear { start, end } => {
+                let amount = if end > start { (distance - start) / (end - start) } else if distance >= start { 1.0 } else { 0.0 };
+                amount.clamp(0.0, 1.0) * thickness
+            }
+            FogMode::Exponential { density } => 1.0 - (-density * thickness * distance).exp(),
+            FogMode::ExponentialSquared { density } => 1.0 - (-(density * thickness * distance).powi(2)).exp(),
+        };
+        amount.clamp(0.0, 1.0) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes_should_thicken_with_the_distance() {
+        let fog = |mode| Fog::new(mode, Rgb([1.0, 1.0, 1.0]));
+        let linear = fog(FogMode::Linear { start: 0.5, end: 1.5 });
+        assert_eq!((linear.amount(0.25, 0.0), linear.amount(1.0, 0.0), linear.amount(2.0, 0.0)), (0.0, 0.5, 1.0));
+        let exponential = fog(FogMode::Exponential { density: 1.0 });
+        assert!((exponential.amount(1.0, 0.0) - (1.0 - (-1.0_f32).exp())).abs() < 1e-6);
+        let squared = fog(FogMode::ExponentialSquared { density: 1.0 });
+        assert!(squared.amount(0.5, 0.0) < exponential.amount(0.5, 0.0));
+        assert!(squared.amount(2.0, 0.0) > exponential.amount(2.0, 0.0));
+        assert_eq!(squared.amount(0.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn height_fog_should_thin_out_above_its_base() {
+        let mut fog = Fog::new(FogMode::Exponential { density: 2.0 }, Rgb([1.0, 1.0, 1.0]));
+        fog.set_height(Some(HeightFog::new(-0.5, 4.0)));
+        assert_eq!(fog.amount(1.0, -0.75), fog.amount(1.0, -0.5));
+        assert!(fog.amount(1.0, 0.5) < 0.1 * fog.amount(1.0, -0.5));
+    }
+
+    #[test]
+    fn heights_should_be_taken_back_to_world_space() {
+        let mut height_fog = HeightFog::new(0.0, 1.0);
+        assert_eq!(height_fog.height_at(Vector3::new(0.5, -0.25, 1.0)), -0.25);
+        // A camera three units above the ground, seen through a projection that halves everything.
+        let view_projection = Matrix4::from_scale(0.5) * Matrix4::from_translation(Vector3::new(0.0, -3.0, 0.0));
+        height_fog.set_inverse_view_projection(view_projection.invert().unwrap());
+        assert!((height_fog.height_at(Vector3::new(0.0, -0.5, 0.0)) - 2.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,183 @@
+use image::{Rgba, RgbaImage};
+
+const EDGE_THRESHOLD_MIN: f64 = 0.0312;
+const EDGE_THRESHOLD_MAX: f64 = 0.125;
+const SUBPIXEL_QUALITY: f64 = 0.75;
+const SEARCH_STEPS: [f64; 12] = [1.0, 1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0];
+
+/// Fast approximate anti-aliasing (after Timothy Lottes' FXAA 3.11) run on a finished image.
+pub fn apply_fxaa(image: &RgbaImage) -> RgbaImage {
+    let luma = LumaPlane::from_image(image);
+    RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        match luma.edge_offset(x as i64, y as i64) {
+            Some((offset_x, offset_y)) => sample_bilinear(image, x as f64 + offset_x, y as f64 + offset_y),
+            None => image[(x, y)],
+        }
+    })
+}
+
+struct LumaPlane {
+    width: i64,
+    height: i64,
+    values: Vec<f64>,
+}
+
+impl LumaPlane {
+    fn from_image(image: &RgbaImage) -> LumaPlane {
+        LumaPlane {
+            width: image.width() as i64,
+            height: image.height() as i64,
+            values: image.pixels().map(luma).collect(),
+        }
+    }
+
+    fn at(&self, x: i64, y: i64) -> f64 {
+        let x = x.max(0).min(self.width - 1);
+        let y = y.max(0).min(self.height - 1);
+        self.values[(y * self.width + x) as usize]
+    }
+
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = self.at(x0, y0) * (1.0 - tx) + self.at(x0 + 1, y0) * tx;
+        let bottom = self.at(x0, y0 + 1) * (1.0 - tx) + self.at(x0 + 1, y0 + 1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+
+    /// Returns the sub-pixel offset to resample the color from, or `None` if the pixel is not on an edge.
+    fn edge_offset(&self, x: i64, y: i64) -> Option<(f64, f64)> {
+        let center = self.at(x, y);
+        let (north, south) = (self.at(x, y - 1), self.at(x, y + 1));
+        let (west, east) = (self.at(x - 1, y), self.at(x + 1, y));
+        let luma_min = center.min(north).min(south).min(west).min(east);
+        let luma_max = center.max(north).max(south).max(west).max(east);
+        let range = luma_max - luma_min;
+        if range < EDGE_THRESHOLD_MIN.max(luma_max * EDGE_THRESHOLD_MAX) {
+            return None;
+        }
+
+        let (north_west, north_east) = (self.at(x - 1, y - 1), self.at(x + 1, y - 1));
+        let (south_west, south_east) = (self.at(x - 1, y + 1), self.at(x + 1, y + 1));
+        let edge_horizontal = (-2.0 * west + north_west + south_west).abs()
+            + 2.0 * (-2.0 * center + north + south).abs()
+            + (-2.0 * east + north_east + south_east).abs();
+        let edge_vertical = (-2.0 * north + north_west + north_east).abs()
+            + 2.0 * (-2.0 * center + west + east).abs()
+            + (-2.0 * south + south_west + south_east).abs();
+        let is_horizontal = edge_horizontal >= edge_vertical;
+
+        let (luma_negative, luma_positive) = if is_horizontal { (north, south) } else { (west, east) };
+        let gradient_negative = luma_negative - center;
+        let gradient_positive = luma_positive - center;
+        let is_negative_steepest = gradient_negative.abs() >= gradient_positive.abs();
+        let gradient_scaled = 0.25 * gradient_negative.abs().max(gradient_positive.abs());
+        let (step, local_average) = if is_negative_steepest {
+            (-1.0, 0.5 * (luma_negative + center))
+        } else {
+            (1.0, 0.5 * (luma_positive + center))
+        };
+
+        let (along_x, along_y) = if is_horizontal { (1.0, 0.0) } else { (0.0, 1.0) };
+        let (across_x, across_y) = (along_y * step, along_x * step);
+        let start = (x as f64 + across_x * 0.5, y as f64 + across_y * 0.5);
+
+        let mut end_negative = (start.0 - along_x, start.1 - along_y);
+        let mut end_positive = (start.0 + along_x, start.1 + along_y);
+        let mut delta_negative = self.sample(end_negative.0, end_negative.1) - local_average;
+        let mut delta_positive = self.sample(end_positive.0, end_positive.1) - local_average;
+        for step_length in SEARCH_STEPS.iter().skip(1) {
+            let reached_negative = delta_negative.abs() >= gradient_scaled;
+            let reached_positive = delta_positive.abs() >= gradient_scaled;
+            if reached_negative && reached_positive {
+                break;
+            }
+            if !reached_negative {
+                end_negative = (end_negative.0 - along_x * step_length, end_negative.1 - along_y * step_length);
+                delta_negative = self.sample(end_negative.0, end_negative.1) - local_average;
+            }
+            if !reached_positive {
+                end_positive = (end_positive.0 + along_x * step_length, end_positive.1 + along_y * step_length);
+                delta_positive = self.sample(end_positive.0, end_positive.1) - local_average;
+            }
+        }
+
+        let (distance_negative, distance_positive) = if is_horizontal {
+            (x as f64 - end_negative.0, end_positive.0 - x as f64)
+        } else {
+            (y as f64 - end_negative.1, end_positive.1 - y as f64)
+        };
+        let is_negative_closer = distance_negative < distance_positive;
+        let distance = distance_negative.min(distance_positive);
+        let edge_length = distance_negative + distance_positive;
+        let end_delta = if is_negative_closer { delta_negative } else { delta_positive };
+        let is_center_smaller = center < local_average;
+        let edge_blend = if (end_delta < 0.0) != is_center_smaller {
+            0.5 - distance / edge_length
+        } else {
+            0.0
+        };
+
+        let average = (2.0 * (north + south + west + east) + north_west + north_east + south_west + south_east) / 12.0;
+        let subpixel = ((average - center).abs() / range).clamp(0.0, 1.0);
+        let subpixel = (-2.0 * subpixel + 3.0) * subpixel * subpixel;
+        let subpixel_blend = subpixel * subpixel * SUBPIXEL_QUALITY;
+
+        let blend = edge_blend.max(subpixel_blend);
+        Some((across_x * blend, across_y * blend))
+    }
+}
+
+fn luma(pixel: &Rgba<u8>) -> f64 {
+    (0.299 * pixel.data[0] as f64 + 0.587 * pixel.data[1] as f64 + 0.114 * pixel.data[2] as f64) / 255.0
+}
+
+fn sample_bilinear(image: &RgbaImage, x: f64, y: f64) -> Rgba<u8> {
+    let max_x = image.width() as i64 - 1;
+    let max_y = image.height() as i64 - 1;
+    let clamped = |x: i64, y: i64| image[(x.max(0).min(max_x) as u32, y.max(0).min(max_y) as u32)];
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let corners = [
+        (clamped(x0, y0), (1.0 - tx) * (1.0 - ty)),
+        (clamped(x0 + 1, y0), tx * (1.0 - ty)),
+        (clamped(x0, y0 + 1), (1.0 - tx) * ty),
+        (clamped(x0 + 1, y0 + 1), tx * ty),
+    ];
+    let mut result = [0.0; 4];
+    for &(pixel, weight) in corners.iter() {
+        for (total, value) in result.iter_mut().zip(pixel.data.iter()) {
+            *total += *value as f64 * weight;
+        }
+    }
+    Rgba([
+        result[0].round() as u8,
+        result[1].round() as u8,
+        result[2].round() as u8,
+        result[3].round() as u8,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_image_should_be_left_untouched() {
+        let image = RgbaImage::from_pixel(4, 4, Rgba([30, 60, 90, 255]));
+        assert_eq!(apply_fxaa(&image).into_raw(), image.into_raw());
+    }
+
+    #[test]
+    fn staircase_edge_should_be_smoothed() {
+        let image = RgbaImage::from_fn(8, 8, |x, y| if x / 2 > y { Rgba([255, 255, 255, 255]) } else { Rgba([0, 0, 0, 255]) });
+        let smoothed = apply_fxaa(&image);
+        let blended_pixels = smoothed
+            .pixels()
+            .filter(|pixel| pixel.data[0] > 0 && pixel.data[0] < 255)
+            .count();
+        assert!(blended_pixels > 0);
+    }
+}
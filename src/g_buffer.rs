@@ -0,0 +1,187 @@
+use cgmath::{Vector2, Vector3};
+use color::{to_srgb, LinearImage};
+use image::{Rgba, RgbaImage};
+use wavefront_obj::obj::Vertex;
+use z_buffer::MAX_DEPTH;
+
+/// A vertex with the attributes the deferred geometry pass interpolates across the triangle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurfaceVertex {
+    /// Normalized device coordinates, like the vertices of the other draw calls.
+    pub position: Vertex,
+    /// World space normal, need not be unit length.
+    pub normal: Vector3<f64>,
+    pub uv: Vector2<f64>,
+}
+
+impl SurfaceVertex {
+    pub fn new(position: Vertex, normal: Vector3<f64>, uv: Vector2<f64>) -> Self {
+        SurfaceVertex { position, normal, uv }
+    }
+}
+
+/// The closest surface seen through a pixel, as the lighting pass gets it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurfaceSample {
+    pub albedo: Rgba<f32>,
+    /// Unit length world space normal.
+    pub normal: Vector3<f64>,
+    pub uv: Vector2<f64>,
+    /// From 0 (farthest) to `MAX_DEPTH` (closest), the same as in the depth buffer.
+    pub depth: u32,
+    pub material_id: u32,
+    /// Share of the ambient light reaching the surface, filled in by SSAO before shading.
+    pub ambient_occlusion: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GBufferChannel {
+    Albedo,
+    Normal,
+    Depth,
+    MaterialId,
+    Uv,
+}
+
+/// Per pixel surface attributes written by the geometry pass of deferred rendering.
+/// Pixels no surface was drawn to stay empty.
+#[derive(Debug, Clone)]
+pub struct GBuffer {
+    width: u32,
+    height: u32,
+    samples: Vec<Option<SurfaceSample>>,
+}
+
+impl GBuffer {
+    pub fn new(width: u32, height: u32) -> GBuffer {
+        GBuffer { width, height, samples: vec![None; (width * height) as usize] }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Option<&SurfaceSample> {
+        self.samples[(y * self.width + x) as usize].as_ref()
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, sample: SurfaceSample) {
+        self.samples[(y * self.width + x) as usize] = Some(sample);
+    }
+
+    pub fn clear(&mut self) {
+        for sample in self.samples.iter_mut() {
+            *sample = None;
+        }
+    }
+
+    /// The raw values of a channel, for formats that keep floats: linear albedo, normal and uv
+    /// components, depth in [0, 1] where 1 is closest, and the material ID.
+    /// Empty pixels are transparent black.
+    pub fn channel(&self, channel: GBufferChannel) -> LinearImage {
+        LinearImage::from_fn(self.width, self.height, |x, y| match self.get(x, y) {
+            Some(sample) => match channel {
+                GBufferChannel::Albedo => sample.albedo,
+                GBufferChannel::Normal => {
+                    let normal = sample.normal.cast::<f32>().unwrap();
+                    Rgba([normal.x, normal.y, normal.z, 1.0])
+                }
+                GBufferChannel::Depth => {
+                    let depth = sample.depth as f32 / MAX_DEPTH as f32;
+                    Rgba([depth, depth, depth, 1.0])
+                }
+                GBufferChannel::MaterialId => {
+                    let id = sample.material_id as f32;
+                    Rgba([id, id, id, 1.0])
+                }
+                GBufferChannel::Uv => Rgba([sample.uv.x as f32, sample.uv.y as f32, 0.0, 1.0]),
+            },
+            None => Rgba([0.0, 0.0, 0.0, 0.0]),
+        })
+    }
+
+    /// An 8 bit image of a channel. Normals are mapped from [-1, 1] to [0, 255] and uvs wrap around.
+    /// The material ID is stored losslessly, its lowest byte in red, then green and blue.
+    pub fn channel_preview(&self, channel: GBufferChannel) -> RgbaImage {
+        let to_u8 = |value: f64| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        RgbaImage::from_fn(self.width, self.height, |x, y| match self.get(x, y) {
+            Some(sample) => match channel {
+                GBufferChannel::Albedo => to_srgb(sample.albedo),
+                GBufferChannel::Normal => {
+                    let to_unsigned = |value: f64| to_u8(value * 0.5 + 0.5);
+                    Rgba([to_unsigned(sample.normal.x), to_unsigned(sample.normal.y), to_unsigned(sample.normal.z), 255])
+                }
+                GBufferChannel::Depth => {
+                    let depth = (sample.depth >> 16) as u8;
+                    Rgba([depth, depth, depth, 255])
+                }
+                GBufferChannel::MaterialId => {
+                    let [low, middle, high, _] = sample.material_id.to_le_bytes();
+                    Rgba([low, middle, high, 255])
+                }
+                GBufferChannel::Uv => Rgba([to_u8(sample.uv.x.rem_euclid(1.0)), to_u8(sample.uv.y.rem_euclid(1.0)), 0, 255]),
+            },
+            None => Rgba([0, 0, 0, 0]),
+        })
+    }
+
+    /// Shrinks the buffer by `factor` in both directions, keeping the sample closest to the center of
+    /// every block. Attributes such as normals and material IDs don't average meaningfully.
+    pub fn downsample(&self, factor: u32) -> GBuffer {
+        let (width, height) = (self.width / factor, self.height / factor);
+        let mut samples = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                samples.push(self.get(x * factor + factor / 2, y * factor + factor / 2).cloned());
+            }
+        }
+        GBuffer { width, height, samples }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(material_id: u32) -> SurfaceSample {
+        SurfaceSample {
+            albedo: Rgba([1.0, 0.5, 0.0, 1.0]),
+            normal: Vector3::new(0.0, 0.0, -1.0),
+            uv: Vector2::new(1.25, 0.5),
+            depth: MAX_DEPTH,
+            material_id,
+            ambient_occlusion: 1.0,
+        }
+    }
+
+    #[test]
+    fn channels_should_keep_raw_values_and_leave_empty_pixels_transparent() {
+        let mut g_buffer = GBuffer::new(2, 1);
+        g_buffer.set(1, 0, sample(7));
+        assert_eq!(g_buffer.channel(GBufferChannel::Normal)[(1, 0)], Rgba([0.0, 0.0, -1.0, 1.0]));
+        assert_eq!(g_buffer.channel(GBufferChannel::Uv)[(1, 0)], Rgba([1.25, 0.5, 0.0, 1.0]));
+        assert_eq!(g_buffer.channel(GBufferChannel::Depth)[(1, 0)], Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(g_buffer.channel(GBufferChannel::MaterialId)[(0, 0)], Rgba([0.0, 0.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn previews_should_encode_normals_and_ids() {
+        let mut g_buffer = GBuffer::new(1, 1);
+        g_buffer.set(0, 0, sample(0x0003_02ff));
+        assert_eq!(g_buffer.channel_preview(GBufferChannel::Normal)[(0, 0)], Rgba([128, 128, 0, 255]));
+        assert_eq!(g_buffer.channel_preview(GBufferChannel::MaterialId)[(0, 0)], Rgba([0xff, 0x02, 0x03, 255]));
+        assert_eq!(g_buffer.channel_preview(GBufferChannel::Uv)[(0, 0)], Rgba([64, 128, 0, 255]));
+    }
+
+    #[test]
+    fn downsampling_should_keep_the_sample_closest_to_each_center() {
+        let mut g_buffer = GBuffer::new(6, 3);
+        g_buffer.set(1, 1, sample(4));
+        g_buffer.set(3, 1, sample(5));
+        g_buffer.set(5, 0, sample(6));
+        let downsampled = g_buffer.downsample(3);
+        assert_eq!(downsampled.dimensions(), (2, 1));
+        assert_eq!(downsampled.get(0, 0).map(|sample| sample.material_id), Some(4));
+        assert_eq!(downsampled.get(1, 0), None);
+    }
+}
@@ -0,0 +1,124 @@
+use cgmath::Vector3;
+use color::LinearImage;
+use image::{Rgba, RgbaImage};
+
+/// Identifies a triangle: the mesh it belongs to and its position within the mesh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct PrimitiveId {
+    pub object_id: u32,
+    pub triangle_index: u32,
+}
+
+impl PrimitiveId {
+    pub fn new(object_id: u32, triangle_index: u32) -> Self {
+        PrimitiveId { object_id, triangle_index }
+    }
+}
+
+/// The triangle seen through a pixel, with the weights of its three vertices at the pixel center.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PickHit {
+    pub id: PrimitiveId,
+    pub barycentrics: Vector3<f64>,
+}
+
+/// Per pixel ID of the closest filled triangle. Lines, points and transparent fragments are not recorded.
+#[derive(Debug, Clone)]
+pub struct IdBuffer {
+    width: u32,
+    height: u32,
+    hits: Vec<Option<PickHit>>,
+}
+
+impl IdBuffer {
+    pub fn new(width: u32, height: u32) -> IdBuffer {
+        IdBuffer { width, height, hits: vec![None; (width * height) as usize] }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Option<PickHit> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.hits[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, hit: PickHit) {
+        self.hits[(y * self.width + x) as usize] = Some(hit);
+    }
+
+    pub fn clear(&mut self) {
+        for hit in self.hits.iter_mut() {
+            *hit = None;
+        }
+    }
+
+    /// Object ID in red and triangle index in green, as floats. Empty pixels are transparent black.
+    pub fn to_linear_image(&self) -> LinearImage {
+        LinearImage::from_fn(self.width, self.height, |x, y| match self.get(x, y) {
+            Some(hit) => Rgba([hit.id.object_id as f32, hit.id.triangle_index as f32, 0.0, 1.0]),
+            None => Rgba([0.0, 0.0, 0.0, 0.0]),
+        })
+    }
+
+    /// Segmentation image with a distinct, stable color for every object. Empty pixels are transparent.
+    pub fn to_false_color(&self) -> RgbaImage {
+        RgbaImage::from_fn(self.width, self.height, |x, y| match self.get(x, y) {
+            Some(hit) => false_color(hit.id.object_id),
+            None => Rgba([0, 0, 0, 0]),
+        })
+    }
+
+    /// Shrinks the buffer by `factor` in both directions, keeping the hit closest to the center of every
+    /// block, the one `Renderer::pick` returns for that pixel.
+    pub fn downsample(&self, factor: u32) -> IdBuffer {
+        let (width, height) = (self.width / factor, self.height / factor);
+        let mut hits = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                hits.push(self.get(x * factor + factor / 2, y * factor + factor / 2));
+            }
+        }
+        IdBuffer { width, height, hits }
+    }
+}
+
+/// Scatters consecutive IDs over the color cube, so neighbouring objects are told apart easily.
+fn false_color(id: u32) -> Rgba<u8> {
+    let [_, red, green, blue] = (id.wrapping_add(1)).wrapping_mul(0x9e37_79b1).to_le_bytes();
+    Rgba([red, green, blue, 255])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn false_colors_should_tell_objects_apart() {
+        let mut id_buffer = IdBuffer::new(3, 1);
+        let hit = |object_id| PickHit { id: PrimitiveId::new(object_id, 5), barycentrics: Vector3::new(1.0, 0.0, 0.0) };
+        id_buffer.set(0, 0, hit(0));
+        id_buffer.set(1, 0, hit(1));
+        let image = id_buffer.to_false_color();
+        assert_ne!(image[(0, 0)], image[(1, 0)]);
+        assert_eq!(image[(0, 0)].data[3], 255);
+        assert_eq!(image[(2, 0)], Rgba([0, 0, 0, 0]));
+        assert_eq!(id_buffer.to_linear_image()[(1, 0)], Rgba([1.0, 5.0, 0.0, 1.0]));
+        assert_eq!(id_buffer.get(3, 0), None);
+    }
+
+    #[test]
+    fn downsampling_should_keep_the_hit_at_each_pixel_center() {
+        let mut id_buffer = IdBuffer::new(4, 2);
+        let hit = |object_id| PickHit { id: PrimitiveId::new(object_id, 0), barycentrics: Vector3::new(1.0, 0.0, 0.0) };
+        id_buffer.set(1, 1, hit(3));
+        id_buffer.set(2, 0, hit(4));
+        let downsampled = id_buffer.downsample(2);
+        assert_eq!(downsampled.dimensions(), (2, 1));
+        assert_eq!(downsampled.get(0, 0).map(|hit| hit.id.object_id), Some(3));
+        assert_eq!(downsampled.get(1, 0), None);
+    }
+}
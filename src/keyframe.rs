@@ -0,0 +1,183 @@
+use cgmath::{InnerSpace, Matrix4, Quaternion, Vector3};
+
+/// Translation, rotation and scale of a node relative to its parent, applied in reverse order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3<f64>,
+    pub rotation: Quaternion<f64>,
+    pub scale: Vector3<f64>,
+}
+
+impl Transform {
+    pub fn new(translation: Vector3<f64>, rotation: Quaternion<f64>, scale: Vector3<f64>) -> Self {
+        Transform { translation, rotation, scale }
+    }
+
+    pub fn matrix(&self) -> Matrix4<f64> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::new(Vector3::new(0.0, 0.0, 0.0), Quaternion::new(1.0, 0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0))
+    }
+}
+
+/// How a channel gets from one keyframe to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Interpolation {
+    /// Holds every keyframe until the next one.
+    Step,
+    /// Blends linearly, and rotations along the shorter arc with slerp.
+    #[default]
+    Linear,
+}
+
+/// The values of a channel, one for every keyframe.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Keyframes {
+    Translation(Vec<Vector3<f64>>),
+    Rotation(Vec<Quaternion<f64>>),
+    Scale(Vec<Vector3<f64>>),
+}
+
+impl Keyframes {
+    fn len(&self) -> usize {
+        match *self {
+            Keyframes::Translation(ref values) | Keyframes::Scale(ref values) => values.len(),
+            Keyframes::Rotation(ref values) => values.len(),
+        }
+    }
+}
+
+/// Animates one property of one node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    /// Index of the animated node in its skeleton.
+    pub node: usize,
+    /// Keyframe times in seconds, ascending.
+    pub times: Vec<f64>,
+    pub keyframes: Keyframes,
+    pub interpolation: Interpolation,
+}
+
+impl Channel {
+    pub fn new(node: usize, times: Vec<f64>, keyframes: Keyframes, interpolation: Interpolation) -> Self {
+        Channel { node, times, keyframes, interpolation }
+    }
+
+    /// Sets the animated property of `transform` to its value at `time`. Before the first and after the
+    /// last keyframe the value holds.
+    pub fn apply(&self, time: f64, transform: &mut Transform) {
+        let count = self.times.len().min(self.keyframes.len());
+        if count == 0 {
+            return;
+        }
+        let next = self.times[..count].iter().position(|&key_time| key_time > time).unwrap_or(count);
+        let (from, to, amount) = if next == 0 {
+            (0, 0, 0.0)
+        } else if next == count || self.interpolation == Interpolation::Step {
+            (next - 1, next - 1, 0.0)
+        } else {
+            let (start, end) = (self.times[next - 1], self.times[next]);
+            (next - 1, next, (time - start) / (end - start))
+        };
+        match self.keyframes {
+            Keyframes::Translation(ref values) => transform.translation = values[from].lerp(values[to], amount),
+            Keyframes::Scale(ref values) => transform.scale = values[from].lerp(values[to], amount),
+            Keyframes::Rotation(ref values) => transform.rotation = slerp(values[from], values[to], amount),
+        }
+    }
+}
+
+/// Rotates along the shorter of the two arcs between `from` and `to`.
+fn slerp(from: Quaternion<f64>, to: Quaternion<f64>, amount: f64) -> Quaternion<f64> {
+    let to = if from.dot(to) < 0.0 { -to } else { to };
+    from.slerp(to, amount).normalize()
+}
+
+/// A named set of channels that play together, such as a walk cycle.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AnimationClip {
+    pub name: String,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    pub fn new(name: &str, channels: Vec<Channel>) -> Self {
+        AnimationClip { name: name.to_string(), channels }
+    }
+
+    /// Time of the last keyframe of any channel, in seconds.
+    pub fn duration(&self) -> f64 {
+        self.channels.iter().filter_map(|channel| channel.times.last()).fold(0.0, |duration, &time| duration.max(time))
+    }
+
+    /// Poses the nodes of `pose` the clip animates at `time`, leaving the others as they are.
+    pub fn apply(&self, time: f64, pose: &mut [Transform]) {
+        for channel in &self.channels {
+            if let Some(transform) = pose.get_mut(channel.node) {
+                channel.apply(time, transform);
+            }
+        }
+    }
+
+    /// The times of `frame_count` evenly spaced frames from the start to the end of the clip, for
+    /// rendering it frame by frame.
+    pub fn frame_times(&self, frame_count: u32) -> Vec<f64> {
+        let duration = self.duration();
+        (0..frame_count).map(|frame| duration * frame as f64 / (frame_count.max(2) - 1) as f64).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Rad, Rotation3};
+    use std::f64::consts::PI;
+
+    #[test]
+    fn translations_should_interpolate_between_keyframes() {
+        let channel = Channel::new(
+            0,
+            vec![1.0, 3.0],
+            Keyframes::Translation(vec![Vector3::new(0.0, 0.0, 0.0), Vector3::new(4.0, 0.0, 0.0)]),
+            Interpolation::Linear,
+        );
+        let translation_at = |time| {
+            let mut transform = Transform::default();
+            channel.apply(time, &mut transform);
+            transform.translation.x
+        };
+        assert_eq!(translation_at(0.0), 0.0);
+        assert_eq!(translation_at(2.5), 3.0);
+        assert_eq!(translation_at(5.0), 4.0);
+        let step = Channel { interpolation: Interpolation::Step, ..channel.clone() };
+        let mut transform = Transform::default();
+        step.apply(2.5, &mut transform);
+        assert_eq!(transform.translation.x, 0.0);
+    }
+
+    #[test]
+    fn rotations_should_take_the_shorter_arc() {
+        // The same quarter turn, once written as its negation, must not spin the long way round.
+        let quarter_turn = -Quaternion::from_angle_z(Rad(PI / 2.0));
+        let channel = Channel::new(
+            3,
+            vec![0.0, 1.0],
+            Keyframes::Rotation(vec![Quaternion::new(1.0, 0.0, 0.0, 0.0), quarter_turn]),
+            Interpolation::Linear,
+        );
+        let clip = AnimationClip::new("turn", vec![channel]);
+        let mut pose = vec![Transform::default(); 4];
+        clip.apply(0.5, &mut pose);
+        let turned = pose[3].rotation * Vector3::new(1.0, 0.0, 0.0);
+        let expected = Vector3::new((PI / 4.0).cos(), (PI / 4.0).sin(), 0.0);
+        assert!((turned - expected).magnitude() < 1e-9);
+        assert_eq!(clip.duration(), 1.0);
+        assert_eq!(clip.frame_times(3), vec![0.0, 0.5, 1.0]);
+    }
+}
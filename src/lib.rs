@@ -1,4 +1,5 @@
 extern crate cgmath;
+extern crate gltf;
 extern crate image;
 extern crate num;
 extern crate wavefront_obj;
@@ -6,10 +7,40 @@ extern crate wavefront_obj;
 pub mod renderer_error;
 pub mod bounding_box;
 pub mod triangle;
-pub mod draw_mode;
 pub mod triangle_drawer;
 pub mod vertex_coordinate_mapper;
 pub mod line_drawer;
 pub mod renderer;
 pub mod outside_trait_impls;
-pub mod z_buffer;
\ No newline at end of file
+pub mod z_buffer;
+pub mod anti_aliasing;
+pub mod fxaa;
+pub mod line_style;
+pub mod stroke;
+pub mod line_clipper;
+pub mod blend_mode;
+pub mod material;
+pub mod a_buffer;
+pub mod stencil_buffer;
+pub mod render_state;
+pub mod point_drawer;
+pub mod output_format;
+pub mod color;
+pub mod tone_mapping;
+pub mod texture;
+pub mod dithering;
+pub mod g_buffer;
+pub mod lighting;
+pub mod id_buffer;
+pub mod ssao;
+pub mod pbr;
+pub mod environment;
+pub mod background;
+pub mod fog;
+pub mod toon;
+pub mod line_art;
+pub mod terminal;
+pub mod animated_output;
+pub mod turntable;
+pub mod keyframe;
+pub mod skinning;
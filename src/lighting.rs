@@ -0,0 +1,62 @@
+use cgmath::{InnerSpace, Vector3};
+use g_buffer::SurfaceSample;
+use image::{Rgb, Rgba};
+
+/// A light infinitely far away, so it shines from the same direction everywhere, like the sun.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DirectionalLight {
+    /// Points from the surface towards the light, in world space.
+    pub direction: Vector3<f64>,
+    /// Linear color, which may be brighter than 1.
+    pub color: Rgb<f32>,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vector3<f64>, color: Rgb<f32>) -> Self {
+        DirectionalLight { direction: direction.normalize(), color }
+    }
+}
+
+/// Diffuse only shading of a G-buffer sample, for use with `Renderer::shade_g_buffer`.
+/// The ambient light is attenuated by the sample's ambient occlusion.
+pub fn lambert(sample: &SurfaceSample, lights: &[DirectionalLight], ambient: Rgb<f32>) -> Rgba<f32> {
+    let mut light = ambient.data;
+    for channel in light.iter_mut() {
+        *channel *= sample.ambient_occlusion;
+    }
+    for source in lights {
+        let intensity = sample.normal.dot(source.direction).max(0.0) as f32;
+        for (total, channel) in light.iter_mut().zip(source.color.data.iter()) {
+            *total += channel * intensity;
+        }
+    }
+    let albedo = sample.albedo.data;
+    Rgba([albedo[0] * light[0], albedo[1] * light[1], albedo[2] * light[2], albedo[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Vector2;
+
+    #[test]
+    fn light_should_fall_off_with_the_angle_and_not_come_from_behind() {
+        let sample = SurfaceSample {
+            albedo: Rgba([1.0, 0.5, 1.0, 1.0]),
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            uv: Vector2::new(0.0, 0.0),
+            depth: 0,
+            material_id: 0,
+            ambient_occlusion: 0.5,
+        };
+        let lights = [
+            DirectionalLight::new(Vector3::new(0.0, 1.0, 1.0), Rgb([2.0, 2.0, 0.0])),
+            DirectionalLight::new(Vector3::new(0.0, 0.0, -1.0), Rgb([5.0, 5.0, 5.0])),
+        ];
+        let color = lambert(&sample, &lights, Rgb([0.25, 0.25, 0.25]));
+        assert!((color.data[0] - (0.125 + 2.0_f32.sqrt())).abs() < 1e-6);
+        assert!((color.data[1] - (0.125 + 2.0_f32.sqrt()) * 0.5).abs() < 1e-6);
+        assert_eq!(color.data[2], 0.125);
+        assert_eq!(color.data[3], 1.0);
+    }
+}
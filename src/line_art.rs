@@ -0,0 +1,330 @@
+use cgmath::{InnerSpace, Vector3};
+use image::Rgb;
+use outside_trait_impls::VecFrom;
+use renderer::Renderer;
+use renderer_error::RendererError;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use vertex_coordinate_mapper::FramebufferOrigin;
+use wavefront_obj::obj::Vertex;
+use z_buffer::{ZBuffer, MAX_DEPTH};
+
+/// Why a mesh edge is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EdgeKind {
+    /// Between a triangle facing the viewer and one facing away.
+    Silhouette,
+    /// Between two triangles facing the viewer at a sharp angle.
+    Crease,
+    /// Belonging to a single triangle, like the rim of an open mesh.
+    Boundary,
+}
+
+impl EdgeKind {
+    fn name(self) -> &'static str {
+        match self {
+            EdgeKind::Silhouette => "silhouette",
+            EdgeKind::Crease => "crease",
+            EdgeKind::Boundary => "boundary",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineArtSettings {
+    /// Angle in radians between the normals of two triangles above which their shared edge is a crease.
+    pub crease_angle: f64,
+    /// How far, in normalized device coordinates, an edge may lie behind the depth buffer and still show.
+    pub depth_tolerance: f64,
+    pub stroke_width: f64,
+    pub color: Rgb<u8>,
+}
+
+impl Default for LineArtSettings {
+    fn default() -> Self {
+        LineArtSettings { crease_angle: 0.5, depth_tolerance: 0.01, stroke_width: 1.0, color: Rgb([0, 0, 0]) }
+    }
+}
+
+/// A visible piece of an edge, in pixels of the render with y pointing down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineSegment {
+    pub kind: EdgeKind,
+    pub start: (f64, f64),
+    pub end: (f64, f64),
+}
+
+/// The visible edges of a mesh, ready to be written out as SVG.
+#[derive(Debug, Clone)]
+pub struct LineArt {
+    width: u32,
+    height: u32,
+    settings: LineArtSettings,
+    segments: Vec<LineSegment>,
+}
+
+/// Finds the silhouettes, creases and boundaries of a mesh given as triangles in normalized device
+/// coordinates, with counterclockwise triangles facing the viewer, and removes the parts hidden by
+/// the mesh itself using a depth buffer of `width` x `height`.
+pub fn trace_line_art(
+    triangles: &[[Vertex; 3]],
+    width: u32,
+    height: u32,
+    settings: &LineArtSettings,
+) -> Result<LineArt, RendererError> {
+    if width == 0 || height == 0 {
+        return Ok(LineArt { width, height, settings: *settings, segments: Vec::new() });
+    }
+    let mut renderer = Renderer::new(width, height);
+    renderer.set_framebuffer_origin(FramebufferOrigin::TopLeft);
+    for triangle in triangles {
+        renderer.draw_filled_triangle_2d(&triangle[0], &triangle[1], &triangle[2], Rgb([0, 0, 0]))?;
+    }
+    let (_, z_buffer) = renderer.unpack_linear();
+
+    let mut edges: HashMap<(EdgeKey, EdgeKey), Edge> = HashMap::new();
+    for triangle in triangles {
+        let (a, b, c) = (Vector3::from_vertex(&triangle[0]), Vector3::from_vertex(&triangle[1]), Vector3::from_vertex(&triangle[2]));
+        let normal = (b - a).cross(c - a);
+        if normal.magnitude2() == 0.0 {
+            continue;
+        }
+        for &(start, end) in [(0, 1), (1, 2), (2, 0)].iter() {
+            let (start, end) = (triangle[start], triangle[end]);
+            let (start_key, end_key) = (EdgeKey::new(&start), EdgeKey::new(&end));
+            let key = if start_key < end_key { (start_key, end_key) } else { (end_key, start_key) };
+            edges.entry(key).or_insert_with(|| Edge { start, end, normals: Vec::new() }).normals.push(normal.normalize());
+        }
+    }
+
+    let min_cosine = settings.crease_angle.cos();
+    let mut segments = Vec::new();
+    for edge in edges.values() {
+        let normals = &edge.normals;
+        let facing = normals.iter().filter(|normal| normal.z > 0.0).count();
+        let kind = if normals.len() == 1 {
+            EdgeKind::Boundary
+        } else if facing > 0 && facing < normals.len() {
+            EdgeKind::Silhouette
+        } else if facing > 0 && normals[0].dot(normals[1]) < min_cosine {
+            EdgeKind::Crease
+        } else {
+            continue;
+        };
+        visible_parts(&edge.start, &edge.end, &z_buffer, settings.depth_tolerance, &mut |from, to| {
+            segments.push(LineSegment { kind, start: from, end: to });
+        });
+    }
+    // Hash map order is random, keep the output the same from run to run.
+    segments.sort_by(|first, second| {
+        let coordinates = |segment: &LineSegment| [segment.start.0, segment.start.1, segment.end.0, segment.end.1];
+        let (first_coordinates, second_coordinates) = (coordinates(first), coordinates(second));
+        let by_kind = first.kind.name().cmp(second.kind.name());
+        first_coordinates.iter().zip(second_coordinates.iter()).fold(by_kind, |order, (a, b)| order.then(a.total_cmp(b)))
+    });
+    Ok(LineArt { width, height, settings: *settings, segments })
+}
+
+/// A mesh edge with the normals of the triangles sharing it.
+struct Edge {
+    start: Vertex,
+    end: Vertex,
+    normals: Vec<Vector3<f64>>,
+}
+
+/// Vertex positions compared bit for bit, so triangles sharing an edge find each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct EdgeKey(u64, u64, u64);
+
+impl EdgeKey {
+    fn new(vertex: &Vertex) -> Self {
+        // Adding 0.0 turns -0.0 into 0.0.
+        EdgeKey((vertex.x + 0.0).to_bits(), (vertex.y + 0.0).to_bits(), (vertex.z + 0.0).to_bits())
+    }
+}
+
+/// Walks the edge in half pixel steps and reports the runs not covered by anything closer. A point is
+/// only hidden if the depth buffer is in front of it all around, so edges are not hidden by the very
+/// triangles they bound.
+fn visible_parts<F: FnMut((f64, f64), (f64, f64))>(
+    start: &Vertex,
+    end: &Vertex,
+    z_buffer: &ZBuffer,
+    tolerance: f64,
+    report: &mut F,
+) {
+    let (width, height) = z_buffer.dimensions();
+    let to_pixel = |vertex: &Vertex| {
+        let x = (vertex.x + 1.0) * (width - 1) as f64 / 2.0;
+        let y = (1.0 - vertex.y) * (height - 1) as f64 / 2.0;
+        (x, y, (vertex.z + 1.0) * MAX_DEPTH as f64 / 2.0)
+    };
+    let (start, end) = (to_pixel(start), to_pixel(end));
+    let length = ((end.0 - start.0).powi(2) + (end.1 - start.1).powi(2)).sqrt();
+    let steps = (length * 2.0).ceil().max(1.0) as u32;
+    let tolerance = tolerance / 2.0 * MAX_DEPTH as f64;
+    let point_at = |t: f64| (start.0 + (end.0 - start.0) * t, start.1 + (end.1 - start.1) * t);
+    let is_visible = |t: f64| {
+        let (x, y) = point_at(t);
+        let depth = start.2 + (end.2 - start.2) * t;
+        let (x, y) = (x.round() as i64, y.round() as i64);
+        let mut farthest = MAX_DEPTH;
+        for neighbour_y in (y - 1).max(0)..(y + 2).min(height as i64) {
+            for neighbour_x in (x - 1).max(0)..(x + 2).min(width as i64) {
+                farthest = farthest.min(z_buffer.get_depth(neighbour_x as u32, neighbour_y as u32));
+            }
+        }
+        depth + tolerance >= farthest as f64
+    };
+    // Pixel centers, so the lines land on the same pixels as in the raster render.
+    let center = |(x, y): (f64, f64)| (x + 0.5, y + 0.5);
+    let mut run_start = None;
+    for step in 0..=steps {
+        let t = step as f64 / steps as f64;
+        match (is_visible(t), run_start) {
+            (true, None) => run_start = Some(t),
+            (false, Some(from)) => {
+                let to = (step - 1) as f64 / steps as f64;
+                if to > from {
+                    report(center(point_at(from)), center(point_at(to)));
+                }
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(from) = run_start {
+        if from < 1.0 {
+            report(center(point_at(from)), center(point_at(1.0)));
+        }
+    }
+}
+
+impl LineArt {
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn segments(&self) -> &[LineSegment] {
+        &self.segments
+    }
+
+    /// Writes an SVG document the size of the render, with one `<path>` per kind of edge. The paths
+    /// have the kind as their class, so they can be restyled with CSS.
+    pub fn write_svg<W: Write>(&self, writer: &mut W) -> Result<(), RendererError> {
+        let [red, green, blue] = self.settings.color.data;
+        writeln!(
+            writer,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}">"#,
+            self.width, self.height
+        )?;
+        for &kind in [EdgeKind::Silhouette, EdgeKind::Crease, EdgeKind::Boundary].iter() {
+            let mut path = String::new();
+            for segment in self.segments.iter().filter(|segment| segment.kind == kind) {
+                if !path.is_empty() {
+                    path.push(' ');
+                }
+                path.push_str(&format!(
+                    "M{} {}L{} {}",
+                    round(segment.start.0),
+                    round(segment.start.1),
+                    round(segment.end.0),
+                    round(segment.end.1)
+                ));
+            }
+            if path.is_empty() {
+                continue;
+            }
+            writeln!(
+                writer,
+                r##"<path class="{}" d="{}" fill="none" stroke="#{:02x}{:02x}{:02x}" stroke-width="{}" stroke-linecap="round"/>"##,
+                kind.name(),
+                path,
+                red,
+                green,
+                blue,
+                self.settings.stroke_width
+            )?;
+        }
+        writeln!(writer, "</svg>")?;
+        Ok(())
+    }
+
+    pub fn save_svg<P: AsRef<Path>>(&self, path: P) -> Result<(), RendererError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_svg(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Two decimals are plenty at pixel scale and keep the file small.
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f64, y: f64, z: f64) -> Vertex {
+        Vertex { x, y, z }
+    }
+
+    /// A square facing the viewer, in two triangles, and a smaller one in front of its left half.
+    fn scene() -> Vec<[Vertex; 3]> {
+        let square = |left: f64, right: f64, bottom: f64, top: f64, z: f64| {
+            vec![
+                [vertex(left, bottom, z), vertex(right, bottom, z), vertex(right, top, z)],
+                [vertex(left, bottom, z), vertex(right, top, z), vertex(left, top, z)],
+            ]
+        };
+        let mut triangles = square(-0.5, 0.5, -0.5, 0.5, 0.0);
+        triangles.extend(square(-0.9, -0.1, -0.2, 0.2, 0.5));
+        triangles
+    }
+
+    #[test]
+    fn hidden_parts_of_edges_should_be_removed() {
+        let art = trace_line_art(&scene(), 101, 101, &LineArtSettings::default()).unwrap();
+        assert!(art.segments().iter().all(|segment| segment.kind == EdgeKind::Boundary));
+        // The shared diagonals are neither creases nor silhouettes.
+        assert_eq!(art.segments().iter().filter(|segment| segment.start.0 != segment.end.0 && segment.start.1 != segment.end.1).count(), 0);
+        // The left edge of the big square is cut where the small one covers it, around y = 50.
+        let left_edge = art.segments().iter().filter(|segment| segment.start.0 == 25.5 && segment.end.0 == 25.5).count();
+        assert_eq!(left_edge, 2);
+        let right_edge = art.segments().iter().filter(|segment| segment.start.0 == 75.5 && segment.end.0 == 75.5).count();
+        assert_eq!(right_edge, 1);
+    }
+
+    #[test]
+    fn folded_edges_should_be_creases_and_silhouettes() {
+        let (top, bottom, left, right) = (vertex(0.0, 0.5, 0.0), vertex(0.0, -0.5, 0.0), vertex(-0.5, 0.0, 0.5), vertex(0.5, 0.0, 0.5));
+        let ridge = [[bottom, right, top], [bottom, top, left]];
+        let art = trace_line_art(&ridge, 21, 21, &LineArtSettings::default()).unwrap();
+        assert!(art.segments().iter().any(|segment| segment.kind == EdgeKind::Crease));
+        let folded_back = [[bottom, right, top], [bottom, left, top]];
+        let art = trace_line_art(&folded_back, 21, 21, &LineArtSettings::default()).unwrap();
+        assert!(art.segments().iter().any(|segment| segment.kind == EdgeKind::Silhouette));
+    }
+
+    #[test]
+    fn empty_target_should_have_no_lines() {
+        let art = trace_line_art(&scene(), 0, 0, &LineArtSettings::default()).unwrap();
+        assert!(art.segments().is_empty());
+    }
+
+    #[test]
+    fn svg_should_hold_a_path_per_kind() {
+        let art = trace_line_art(&scene(), 101, 101, &LineArtSettings::default()).unwrap();
+        let mut svg = Vec::new();
+        art.write_svg(&mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" width="101" height="101""#));
+        assert_eq!(svg.matches("<path").count(), 1);
+        assert!(svg.contains(r#"class="boundary" d="M"#));
+        assert!(svg.trim_end().ends_with("</svg>"));
+    }
+}
@@ -0,0 +1,75 @@
+use cgmath::{Point2, Point3};
+
+/// What to do with lines that reach outside the image.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ClipPolicy {
+    /// Draw only the part of the line inside the image.
+    #[default]
+    Clip,
+    /// Refuse to draw the line and report `RendererError::PixelOutOfImageBounds`.
+    Error,
+}
+
+/// Liang–Barsky clipping of a segment against the rectangle spanned by `min` and `max`.
+/// The depth is interpolated along with the clipped end points.
+/// Returns `None` when no part of the segment is inside.
+pub fn clip_line(
+    start: Point3<f64>,
+    end: Point3<f64>,
+    min: Point2<f64>,
+    max: Point2<f64>,
+) -> Option<(Point3<f64>, Point3<f64>)> {
+    let delta = end - start;
+    let boundaries = [
+        (-delta.x, start.x - min.x),
+        (delta.x, max.x - start.x),
+        (-delta.y, start.y - min.y),
+        (delta.y, max.y - start.y),
+    ];
+    let (mut entering, mut leaving) = (0.0_f64, 1.0_f64);
+    for &(direction, distance) in boundaries.iter() {
+        if direction == 0.0 {
+            if distance < 0.0 {
+                return None;
+            }
+            continue;
+        }
+        let t = distance / direction;
+        if direction < 0.0 {
+            entering = entering.max(t);
+        } else {
+            leaving = leaving.min(t);
+        }
+        if entering > leaving {
+            return None;
+        }
+    }
+    Some((start + delta * entering, start + delta * leaving))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(start: Point3<f64>, end: Point3<f64>) -> Option<(Point3<f64>, Point3<f64>)> {
+        clip_line(start, end, Point2::new(0.0, 0.0), Point2::new(9.0, 9.0))
+    }
+
+    #[test]
+    fn line_inside_should_be_left_untouched() {
+        let (start, end) = (Point3::new(1.0, 2.0, 3.0), Point3::new(8.0, 7.0, 6.0));
+        assert_eq!(clip(start, end), Some((start, end)));
+    }
+
+    #[test]
+    fn line_outside_should_be_rejected() {
+        assert_eq!(clip(Point3::new(10.0, 0.0, 0.0), Point3::new(20.0, 9.0, 0.0)), None);
+        assert_eq!(clip(Point3::new(-5.0, 12.0, 0.0), Point3::new(12.0, 30.0, 0.0)), None);
+    }
+
+    #[test]
+    fn crossing_line_should_be_cut_at_the_border() {
+        let clipped = clip(Point3::new(-9.0, 4.0, 0.0), Point3::new(18.0, 4.0, 27.0));
+        assert_eq!(clipped, Some((Point3::new(0.0, 4.0, 9.0), Point3::new(9.0, 4.0, 18.0))));
+    }
+}
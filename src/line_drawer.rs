@@ -1,15 +1,25 @@
-use cgmath::{Point3};
-use image::{Rgb, RgbImage};
+use blend_mode::BlendMode;
+use cgmath::{Point2, Point3};
+use color::LinearImage;
+use image::Rgba;
+use line_clipper::{clip_line, ClipPolicy};
+use line_style::LineStyle;
+use renderer_error::RendererError;
 use std::mem;
-use z_buffer::ZBuffer;
+use stroke::{plot_coverage, StrokeDrawer};
+use z_buffer::{DepthState, ZBuffer};
 use z_buffer::PixelVisibility;
 
 pub struct LineDrawer<'a> {
     start: Point3<u32>,
     end: Point3<u32>,
-    col: Rgb<u8>,
+    col: Rgba<f32>,
+    style: LineStyle,
+    clip_policy: ClipPolicy,
+    blend_mode: BlendMode,
+    depth: DepthState,
     is_steep: bool,
-    buffer: &'a mut RgbImage,
+    buffer: &'a mut LinearImage,
     z_buffer: &'a mut ZBuffer,
 }
 
@@ -17,11 +27,22 @@ impl<'a> LineDrawer<'a> {
     pub fn new(
         start: Point3<u32>,
         end: Point3<u32>,
-        col: Rgb<u8>,
-        buffer: &'a mut RgbImage,
+        col: Rgba<f32>,
+        buffer: &'a mut LinearImage,
         z_buffer: &'a mut ZBuffer,
     ) -> Self {
-        let mut drawer = LineDrawer::create_initial_instance(start, end, col, buffer, z_buffer);
+        LineDrawer::with_style(start, end, col, LineStyle::default(), buffer, z_buffer)
+    }
+
+    pub fn with_style(
+        start: Point3<u32>,
+        end: Point3<u32>,
+        col: Rgba<f32>,
+        style: LineStyle,
+        buffer: &'a mut LinearImage,
+        z_buffer: &'a mut ZBuffer,
+    ) -> Self {
+        let mut drawer = LineDrawer::create_initial_instance(start, end, col, style, buffer, z_buffer);
         drawer.make_line_shallow();
         drawer.order_points();
         drawer
@@ -30,14 +51,19 @@ impl<'a> LineDrawer<'a> {
     fn create_initial_instance(
         start: Point3<u32>,
         end: Point3<u32>,
-        col: Rgb<u8>,
-        buffer: &'a mut RgbImage,
+        col: Rgba<f32>,
+        style: LineStyle,
+        buffer: &'a mut LinearImage,
         z_buffer: &'a mut ZBuffer,
     ) -> Self {
         LineDrawer {
             start,
             end,
             col,
+            style,
+            clip_policy: ClipPolicy::default(),
+            blend_mode: BlendMode::default(),
+            depth: DepthState::default(),
             is_steep: false,
             buffer,
             z_buffer,
@@ -60,16 +86,128 @@ impl<'a> LineDrawer<'a> {
         }
     }
 
-    pub fn draw_line(&mut self) {
+    pub fn set_clip_policy(&mut self, clip_policy: ClipPolicy) {
+        self.clip_policy = clip_policy;
+    }
+
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    pub fn set_depth_state(&mut self, depth: DepthState) {
+        self.depth = depth;
+    }
+
+    pub fn draw_line(&mut self) -> Result<(), RendererError> {
+        self.check_bounds()?;
+        if !self.style.is_hairline() {
+            // Wide strokes only ever touch pixels inside the image, clipping them would cut off their caps.
+            self.draw_wide_line();
+        } else if self.clip_to_buffer() {
+            if self.style.anti_aliased {
+                self.draw_anti_aliased_line();
+            } else {
+                self.draw_aliased_line();
+            }
+        }
+        Ok(())
+    }
+
+    fn check_bounds(&self) -> Result<(), RendererError> {
+        if self.clip_policy == ClipPolicy::Clip {
+            return Ok(());
+        }
+        let (width, height) = self.buffer.dimensions();
+        for point in [self.start, self.end].iter() {
+            let (x, y) = if self.is_steep { (point.y, point.x) } else { (point.x, point.y) };
+            if x >= width || y >= height {
+                return Err(RendererError::PixelOutOfImageBounds(width, height, Point2::new(x, y)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Cuts the line down to the part inside the image, returns `false` if nothing is left to draw.
+    fn clip_to_buffer(&mut self) -> bool {
+        let (width, height) = self.buffer.dimensions();
+        if width == 0 || height == 0 {
+            return false;
+        }
+        let (start, end) = if self.is_steep {
+            (transpose(self.start), transpose(self.end))
+        } else {
+            (self.start, self.end)
+        };
+        if start.x < width && start.y < height && end.x < width && end.y < height {
+            return true;
+        }
+        let clipped = clip_line(
+            start.cast::<f64>().unwrap(),
+            end.cast::<f64>().unwrap(),
+            Point2::new(0.0, 0.0),
+            Point2::new((width - 1) as f64, (height - 1) as f64),
+        );
+        match clipped {
+            Some((start, end)) => {
+                let round = |p: Point3<f64>| Point3::new(p.x.round() as u32, p.y.round() as u32, p.z.round() as u32);
+                self.start = round(start);
+                self.end = round(end);
+                self.is_steep = false;
+                self.make_line_shallow();
+                self.order_points();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn draw_aliased_line(&mut self) {
         for x in self.start.x..self.end.x + 1 {
             let current_point = self.get_current_point(x);
-            match self.z_buffer.update_buffer(current_point.x, current_point.y, current_point.z).unwrap() {
+            match self.z_buffer.update_with_state(current_point.x, current_point.y, current_point.z, self.depth) {
                 PixelVisibility::Visible => {self.fill_next_line_point(x)},
                 PixelVisibility::Hidden => {},
             }
         }
     }
 
+    /// Xiaolin Wu's line: every column covers the two pixels straddling the ideal line,
+    /// weighted by how close the line passes to each of them.
+    fn draw_anti_aliased_line(&mut self) {
+        let gradient = if self.end.x == self.start.x {
+            0.0
+        } else {
+            (self.end.y as f64 - self.start.y as f64) / (self.end.x - self.start.x) as f64
+        };
+        for x in self.start.x..self.end.x + 1 {
+            let exact_y = self.start.y as f64 + gradient * (x - self.start.x) as f64;
+            let lower_y = exact_y.floor();
+            let fraction = exact_y - lower_y;
+            let z = lerp(self.start.z, self.end.z, self.get_lerp_amount(x));
+            self.plot_shallow(x, lower_y as u32, z, 1.0 - fraction);
+            if fraction > 0.0 {
+                self.plot_shallow(x, lower_y as u32 + 1, z, fraction);
+            }
+        }
+    }
+
+    fn draw_wide_line(&mut self) {
+        let (start, end) = if self.is_steep {
+            (transpose(self.start), transpose(self.end))
+        } else {
+            (self.start, self.end)
+        };
+        let mut stroke = StrokeDrawer::new(&[start, end], false, self.col, self.style, self.buffer, self.z_buffer);
+        stroke.set_blend_mode(self.blend_mode);
+        stroke.set_depth_state(self.depth);
+        stroke.draw();
+    }
+
+    fn plot_shallow(&mut self, x: u32, y: u32, z: u32, coverage: f64) {
+        let (x, y) = if self.is_steep { (y, x) } else { (x, y) };
+        plot_coverage(self.buffer, self.z_buffer, Point3::new(x, y, z), self.col, coverage, self.blend_mode, self.depth);
+    }
+
     fn get_current_point(&self, x: u32) -> Point3<u32> {
         let offset_y = lerp(self.start.y, self.end.y, self.get_lerp_amount(x));
         let (x_coord, y_coord) = if self.is_steep {
@@ -87,11 +225,8 @@ impl<'a> LineDrawer<'a> {
             self.end.y,
             self.get_lerp_amount(x),
         );
-        if self.is_steep {
-            self.buffer[(base_offset, x)] = self.col;
-        } else {
-            self.buffer[(x, base_offset)] = self.col;
-        }
+        let (x, y) = if self.is_steep { (base_offset, x) } else { (x, base_offset) };
+        self.buffer[(x, y)] = self.blend_mode.blend(self.col, self.buffer[(x, y)]);
     }
 
     #[inline]
@@ -100,6 +235,10 @@ impl<'a> LineDrawer<'a> {
     }
 }
 
+fn transpose(point: Point3<u32>) -> Point3<u32> {
+    Point3::new(point.y, point.x, point.z)
+}
+
 #[inline]
 pub fn lerp(start: u32, end: u32, lerp_amount: f64) -> u32 {
     (start as f64 + (end as i32 - start as i32) as f64 * lerp_amount).round() as u32
@@ -111,95 +250,170 @@ mod test {
 
     #[test]
     fn draw_a_zero_length_line_should_draw_a_dot() {
-        let mut buffer = RgbImage::new(2, 2);
+        let mut buffer = LinearImage::new(2, 2);
         let mut z_buffer = ZBuffer::new(2, 2);
         LineDrawer::new(
             Point3::new(0, 0, 0),
             Point3::new(0, 0, 0),
-            Rgb([1, 1, 1]),
+            Rgba([1.0, 1.0, 1.0, 1.0]),
             &mut buffer,
             &mut z_buffer,
-        ).draw_line();
-        assert_eq!(buffer[(0, 0)], Rgb([1, 1, 1]));
-        assert_eq!(buffer[(1, 1)], Rgb([0, 0, 0]));
-        assert_eq!(buffer[(0, 1)], Rgb([0, 0, 0]));
-        assert_eq!(buffer[(1, 0)], Rgb([0, 0, 0]));
+        ).draw_line().unwrap();
+        assert_eq!(buffer[(0, 0)], Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(buffer[(1, 1)], Rgba([0.0, 0.0, 0.0, 0.0]));
+        assert_eq!(buffer[(0, 1)], Rgba([0.0, 0.0, 0.0, 0.0]));
+        assert_eq!(buffer[(1, 0)], Rgba([0.0, 0.0, 0.0, 0.0]));
     }
 
     #[test]
     fn draw_even_line() {
-        let mut buffer = RgbImage::new(2, 2);
+        let mut buffer = LinearImage::new(2, 2);
         let mut z_buffer = ZBuffer::new(2, 2);
         LineDrawer::new(
             Point3::new(0, 0, 0),
             Point3::new(1, 1, 0),
-            Rgb([1, 1, 1]),
+            Rgba([1.0, 1.0, 1.0, 1.0]),
             &mut buffer,
             &mut z_buffer,
-        ).draw_line();
+        ).draw_line().unwrap();
         drawer_should_have_drawn_line_from_bottom_left_to_top_right(&buffer);
     }
 
     #[test]
     fn parameter_order_should_not_matter() {
-        let mut buffer = RgbImage::new(2, 2);
+        let mut buffer = LinearImage::new(2, 2);
         let mut z_buffer = ZBuffer::new(2, 2);
         LineDrawer::new(
             Point3::new(1, 1, 3),
             Point3::new(0, 0, 3),
-            Rgb([1, 1, 1]),
+            Rgba([1.0, 1.0, 1.0, 1.0]),
             &mut buffer,
             &mut z_buffer,
-        ).draw_line();
+        ).draw_line().unwrap();
         drawer_should_have_drawn_line_from_bottom_left_to_top_right(&buffer);
     }
 
     #[test]
     fn should_be_able_to_draw_shallow_line() {
-        let mut buffer = RgbImage::new(2, 2);
+        let mut buffer = LinearImage::new(2, 2);
         let mut z_buffer = ZBuffer::new(2, 2);
         LineDrawer::new(
             Point3::new(0, 0, 0),
             Point3::new(1, 0, 0),
-            Rgb([1, 1, 1]),
+            Rgba([1.0, 1.0, 1.0, 1.0]),
             &mut buffer,
             &mut z_buffer,
-        ).draw_line();
+        ).draw_line().unwrap();
         drawer_should_have_drawn_flat_line(&buffer);
     }
 
     #[test]
     fn should_be_able_to_draw_steep_line() {
-        let mut buffer = RgbImage::new(2, 2);
+        let mut buffer = LinearImage::new(2, 2);
         let mut z_buffer = ZBuffer::new(2, 2);
         LineDrawer::new(
             Point3::new(0, 0, 0),
             Point3::new(0, 1, 0),
-            Rgb([1, 1, 1]),
+            Rgba([1.0, 1.0, 1.0, 1.0]),
             &mut buffer,
             &mut z_buffer,
-        ).draw_line();
+        ).draw_line().unwrap();
         drawer_should_have_drawn_straight_vertical_line(&buffer);
     }
 
-    fn drawer_should_have_drawn_line_from_bottom_left_to_top_right(buffer: &RgbImage) {
-        assert_eq!(buffer[(0, 0)], Rgb([1, 1, 1]));
-        assert_eq!(buffer[(1, 1)], Rgb([1, 1, 1]));
-        assert_eq!(buffer[(0, 1)], Rgb([0, 0, 0]));
-        assert_eq!(buffer[(1, 0)], Rgb([0, 0, 0]));
+    #[test]
+    fn anti_aliased_line_should_split_coverage_between_neighbours() {
+        let mut buffer = LinearImage::new(3, 2);
+        let mut z_buffer = ZBuffer::new(3, 2);
+        let style = LineStyle { anti_aliased: true, ..LineStyle::default() };
+        LineDrawer::with_style(
+            Point3::new(0, 0, 0),
+            Point3::new(2, 1, 0),
+            Rgba([0.8, 0.8, 0.8, 1.0]),
+            style,
+            &mut buffer,
+            &mut z_buffer,
+        ).draw_line().unwrap();
+        assert_eq!(buffer[(0, 0)], Rgba([0.8, 0.8, 0.8, 1.0]));
+        assert_eq!(buffer[(1, 0)], Rgba([0.8, 0.8, 0.8, 0.5]));
+        assert_eq!(buffer[(1, 1)], Rgba([0.8, 0.8, 0.8, 0.5]));
+        assert_eq!(buffer[(2, 1)], Rgba([0.8, 0.8, 0.8, 1.0]));
+        assert_eq!(buffer[(2, 0)], Rgba([0.0, 0.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn wide_steep_line_should_cover_neighbouring_columns() {
+        let mut buffer = LinearImage::new(5, 5);
+        let mut z_buffer = ZBuffer::new(5, 5);
+        let style = LineStyle { width: 3.0, ..LineStyle::default() };
+        LineDrawer::with_style(
+            Point3::new(2, 0, 0),
+            Point3::new(2, 4, 0),
+            Rgba([1.0, 1.0, 1.0, 1.0]),
+            style,
+            &mut buffer,
+            &mut z_buffer,
+        ).draw_line().unwrap();
+        for y in 0..5 {
+            assert_eq!(buffer[(1, y)], Rgba([1.0, 1.0, 1.0, 1.0]));
+            assert_eq!(buffer[(2, y)], Rgba([1.0, 1.0, 1.0, 1.0]));
+            assert_eq!(buffer[(3, y)], Rgba([1.0, 1.0, 1.0, 1.0]));
+            assert_eq!(buffer[(0, y)], Rgba([0.0, 0.0, 0.0, 0.0]));
+        }
+    }
+
+    #[test]
+    fn line_leaving_the_image_should_be_clipped() {
+        let mut buffer = LinearImage::new(3, 3);
+        let mut z_buffer = ZBuffer::new(3, 3);
+        let result = LineDrawer::new(
+            Point3::new(0, 1, 0),
+            Point3::new(8, 1, 0),
+            Rgba([1.0, 1.0, 1.0, 1.0]),
+            &mut buffer,
+            &mut z_buffer,
+        ).draw_line();
+        assert_eq!(result, Ok(()));
+        assert_eq!(buffer[(0, 1)], Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(buffer[(2, 1)], Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(buffer[(2, 2)], Rgba([0.0, 0.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn line_outside_the_image_should_be_an_error_when_asked_for() {
+        let mut buffer = LinearImage::new(3, 3);
+        let mut z_buffer = ZBuffer::new(3, 3);
+        let mut drawer = LineDrawer::new(
+            Point3::new(0, 0, 0),
+            Point3::new(1, 5, 0),
+            Rgba([1.0, 1.0, 1.0, 1.0]),
+            &mut buffer,
+            &mut z_buffer,
+        );
+        drawer.set_clip_policy(ClipPolicy::Error);
+        let result = drawer.draw_line();
+        assert_eq!(result, Err(RendererError::PixelOutOfImageBounds(3, 3, Point2::new(1, 5))));
+        assert_eq!(buffer[(0, 0)], Rgba([0.0, 0.0, 0.0, 0.0]));
+    }
+
+    fn drawer_should_have_drawn_line_from_bottom_left_to_top_right(buffer: &LinearImage) {
+        assert_eq!(buffer[(0, 0)], Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(buffer[(1, 1)], Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(buffer[(0, 1)], Rgba([0.0, 0.0, 0.0, 0.0]));
+        assert_eq!(buffer[(1, 0)], Rgba([0.0, 0.0, 0.0, 0.0]));
     }
 
-    fn drawer_should_have_drawn_flat_line(buffer: &RgbImage) {
-        assert_eq!(buffer[(0, 0)], Rgb([1, 1, 1]));
-        assert_eq!(buffer[(1, 1)], Rgb([0, 0, 0]));
-        assert_eq!(buffer[(1, 0)], Rgb([1, 1, 1]));
-        assert_eq!(buffer[(0, 1)], Rgb([0, 0, 0]));
+    fn drawer_should_have_drawn_flat_line(buffer: &LinearImage) {
+        assert_eq!(buffer[(0, 0)], Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(buffer[(1, 1)], Rgba([0.0, 0.0, 0.0, 0.0]));
+        assert_eq!(buffer[(1, 0)], Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(buffer[(0, 1)], Rgba([0.0, 0.0, 0.0, 0.0]));
     }
 
-    fn drawer_should_have_drawn_straight_vertical_line(buffer: &RgbImage) {
-        assert_eq!(buffer[(0, 0)], Rgb([1, 1, 1]));
-        assert_eq!(buffer[(1, 1)], Rgb([0, 0, 0]));
-        assert_eq!(buffer[(0, 1)], Rgb([1, 1, 1]));
-        assert_eq!(buffer[(1, 0)], Rgb([0, 0, 0]));
+    fn drawer_should_have_drawn_straight_vertical_line(buffer: &LinearImage) {
+        assert_eq!(buffer[(0, 0)], Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(buffer[(1, 1)], Rgba([0.0, 0.0, 0.0, 0.0]));
+        assert_eq!(buffer[(0, 1)], Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(buffer[(1, 0)], Rgba([0.0, 0.0, 0.0, 0.0]));
     }
 }
@@ -0,0 +1,47 @@
+/// How lines are stroked. The default is the classic 1 pixel wide aliased line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineStyle {
+    pub width: f64,
+    pub anti_aliased: bool,
+    pub cap: LineCap,
+    pub join: LineJoin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineCap {
+    Butt,
+    Square,
+    Round,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineJoin {
+    Miter,
+    Bevel,
+    Round,
+}
+
+impl LineStyle {
+    pub fn new(width: f64, anti_aliased: bool, cap: LineCap, join: LineJoin) -> Self {
+        LineStyle {
+            width,
+            anti_aliased,
+            cap,
+            join,
+        }
+    }
+
+    pub fn is_hairline(&self) -> bool {
+        self.width <= 1.0
+    }
+
+    pub fn half_width(&self) -> f64 {
+        self.width / 2.0
+    }
+}
+
+impl Default for LineStyle {
+    fn default() -> Self {
+        LineStyle::new(1.0, false, LineCap::Butt, LineJoin::Miter)
+    }
+}
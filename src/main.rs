@@ -7,13 +7,23 @@ extern crate rand;
 extern crate wavefront_obj;
 
 use cgmath::prelude::*;
-use cgmath::Vector3;
-use image::{ImageRgb8, ImageLuma8, Rgb};
+use cgmath::{Vector2, Vector3};
+use image::{Rgb, Rgba};
 use mini_renderer::{renderer::Renderer, outside_trait_impls::VecFrom};
+use mini_renderer::background::{Background, BackgroundCamera};
+use mini_renderer::g_buffer::SurfaceVertex;
+use mini_renderer::lighting::DirectionalLight;
+use mini_renderer::material::parse_mtl;
+use mini_renderer::pbr::{cook_torrance, PbrMaterial};
+use mini_renderer::ssao::SsaoSettings;
+use mini_renderer::tone_mapping::ToneMapping;
+use mini_renderer::vertex_coordinate_mapper::FramebufferOrigin;
 use rand::prelude::*;
-use std::fs::File;
+use std::env;
+use std::fs::{self, File};
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::path::Path;
 use wavefront_obj::obj;
 use wavefront_obj::obj::Primitive;
 
@@ -25,56 +35,118 @@ fn write_renderer(renderer: Renderer) {
 
 }
 
+/// The materials of the OBJ's material library, after a plain white one for groups without a material.
+fn load_materials(scene: &obj::ObjSet, directory: &Path) -> Vec<(String, PbrMaterial)> {
+    let mut materials = vec![(String::new(), PbrMaterial::new(Rgba([1.0, 1.0, 1.0, 1.0]), 0.0, 1.0))];
+    if let Some(ref library) = scene.material_library {
+        for material in parse_mtl(&fs::read_to_string(directory.join(library)).unwrap()) {
+            let pbr_material = PbrMaterial::from_mtl(&material, directory).unwrap();
+            materials.push((material.name, pbr_material));
+        }
+    }
+    materials
+}
+
 fn draw_obj() {
-    let mut reader = BufReader::new(File::open("resources/african_head.obj").unwrap());
+    // The first argument that is not a flag picks the OBJ file, the head by default.
+    let obj_path = env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let obj_path = Path::new(obj_path.as_ref().map_or("resources/african_head.obj", |path| path.as_str()));
+    let mut reader = BufReader::new(File::open(obj_path).unwrap());
     let mut obj_file_text = String::new();
     reader.read_to_string(&mut obj_file_text).unwrap();
     let scene = obj::parse(obj_file_text).unwrap();
+    let materials = load_materials(&scene, obj_path.parent().unwrap_or_else(|| Path::new("")));
     let the_mesh = &scene.objects[0];
     let mut renderer = Renderer::new(2500, 2500);
-    renderer.clear_to_color(Rgb([0, 20, 25]));
+    renderer.set_framebuffer_origin(FramebufferOrigin::TopLeft);
+    renderer.set_tone_mapping(ToneMapping::AcesFilmic);
+    // `--ssao` shades the head in a deferred pass with ambient occlusion instead of the flat wireframe look.
+    let ssao = env::args().any(|arg| arg == "--ssao");
+    if ssao {
+        renderer.set_deferred(true);
+        renderer.set_ssao(Some(SsaoSettings::new(25.0, 16, 3)));
+    }
+    let render_state = *renderer.get_render_state();
+    let background = Background::Gradient { top: Rgba([0.0, 0.02, 0.03, 1.0]), bottom: Rgba([0.0, 0.002, 0.004, 1.0]) };
+    renderer.clear_to_background(&background, &BackgroundCamera::new(1.0));
     let mut _rng = thread_rng();
     let light_direction = Vector3::new(0.0, 0.0, 1.0);
     for material_group in &the_mesh.geometry {
+        let material_id = material_group
+            .material_name
+            .as_ref()
+            .and_then(|name| materials.iter().position(|(material_name, _)| material_name == name))
+            .unwrap_or(0);
+        let [red, green, blue, alpha] = materials[material_id].1.base_color.data;
         for shape in material_group.shapes.iter() {
-            if let Primitive::Triangle((a_vert_ind, ..), (b_vert_ind, ..), (c_ind, _, ..)) =
-                shape.primitive
-            {
-                let vertex_a = the_mesh.vertices[a_vert_ind];
-                let vertex_b = the_mesh.vertices[b_vert_ind];
-                let vertex_c = the_mesh.vertices[c_ind];
-                let vec_a = Vector3::from_vertex(&vertex_a);
-                let vec_b = Vector3::from_vertex(&vertex_b);
-                let vec_c = Vector3::from_vertex(&vertex_c);
-                let norm = (vec_a - vec_b).cross(vec_a - vec_c).normalize();
-                let intensity = norm.dot(light_direction.clone());
-                if intensity > 0.0 {
-                    let rgb_value = (intensity * 85.0) as u8;
-                    renderer
-                        .draw_filled_triangle_2d(
-                            &vertex_a,
-                            &vertex_b,
-                            &vertex_c,
-                            Rgb([rgb_value, rgb_value, rgb_value]),
-                        )
-                        .unwrap();
+            match shape.primitive {
+                Primitive::Triangle((a_vert_ind, a_uv, _), (b_vert_ind, b_uv, _), (c_ind, c_uv, _)) => {
+                    let vertex_a = the_mesh.vertices[a_vert_ind];
+                    let vertex_b = the_mesh.vertices[b_vert_ind];
+                    let vertex_c = the_mesh.vertices[c_ind];
+                    let vec_a = Vector3::from_vertex(&vertex_a);
+                    let vec_b = Vector3::from_vertex(&vertex_b);
+                    let vec_c = Vector3::from_vertex(&vertex_c);
+                    let norm = (vec_a - vec_b).cross(vec_a - vec_c).normalize();
+                    let intensity = norm.dot(light_direction.clone());
+                    if intensity > 0.0 && ssao {
+                        let surface = |vertex, uv: Option<usize>| {
+                            let uv = uv.map(|uv| the_mesh.tex_vertices[uv]);
+                            let uv = uv.map_or(Vector2::new(0.0, 0.0), |uv| Vector2::new(uv.u, uv.v));
+                            SurfaceVertex::new(vertex, norm, uv)
+                        };
+                        renderer
+                            .draw_surface_triangle(
+                                &[surface(vertex_a, a_uv), surface(vertex_b, b_uv), surface(vertex_c, c_uv)],
+                                Rgb([0.6_f32, 0.6, 0.6]),
+                                material_id as u32,
+                                &render_state,
+                            )
+                            .unwrap();
+                    } else if intensity > 0.0 {
+                        let shade = (intensity * 0.15) as f32;
+                        renderer
+                            .draw_filled_triangle_with_wireframe_2d(
+                                &vertex_a,
+                                &vertex_b,
+                                &vertex_c,
+                                Rgba([shade * red, shade * green, shade * blue, alpha]),
+                                Rgb([230, 240, 250]),
+                            )
+                            .unwrap();
+                    } else {
+                        renderer
+                            .draw_triangle_2d(
+                                &vertex_a,
+                                &vertex_b,
+                                &vertex_c,
+                                Rgb([230, 240, 250]),
+                            )
+                            .unwrap();
+                    }
+                }
+                Primitive::Line((a_vert_ind, ..), (b_vert_ind, ..)) => {
+                    let vertex_a = the_mesh.vertices[a_vert_ind];
+                    let vertex_b = the_mesh.vertices[b_vert_ind];
+                    renderer.draw_line_2d(&vertex_a, &vertex_b, Rgb([230, 240, 250])).unwrap();
+                }
+                Primitive::Point((vert_ind, ..)) => {
+                    renderer.draw_point_2d(&the_mesh.vertices[vert_ind], Rgb([230, 240, 250])).unwrap();
                 }
-                renderer
-                    .draw_triangle_2d(
-                        &vertex_a,
-                        &vertex_b,
-                        &vertex_c,
-                        Rgb([230, 240, 250]),
-                    )
-                    .unwrap();
-            } else {
-                panic!("Invalid obj format (line or point detected)");
             }
         }
     }
-    let  (mut image_buffer, mut z_buffer) = renderer.unpack();
-    let image_buffer = ImageRgb8(image_buffer).flipv();
-    image_buffer.as_rgb8().unwrap().save("image.png").unwrap();
-    let z_buffer = ImageLuma8(z_buffer.unpack()).flipv();
-    z_buffer.as_luma8().unwrap().save("image_z.png").unwrap();
+    if ssao {
+        let lights = [DirectionalLight::new(light_direction, Rgb([0.7, 0.7, 0.7]))];
+        let view_direction = Vector3::new(0.0, 0.0, 1.0);
+        renderer
+            .shade_g_buffer(|sample| {
+                let material = &materials[sample.material_id as usize].1;
+                cook_torrance(sample, material, &lights, Rgb([0.5, 0.5, 0.5]), view_direction)
+            })
+            .unwrap();
+    }
+    renderer.save("image.png", None).unwrap();
+    let (_, z_buffer) = renderer.unpack();
+    z_buffer.unpack().save("image_z.png").unwrap();
 }
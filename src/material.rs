@@ -0,0 +1,123 @@
+use image::Rgba;
+
+/// The parts of a Wavefront MTL material the renderer uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    pub name: String,
+    pub diffuse: [f64; 3],
+    /// Opacity from the `d` statement, or one minus the `Tr` transparency.
+    pub alpha: f64,
+    pub diffuse_map: Option<String>,
+    /// Emitted color from `Ke`.
+    pub emissive: [f64; 3],
+    pub emissive_map: Option<String>,
+    /// `Pr` and `Pm` from the PBR extension, absent for classic materials.
+    pub roughness: Option<f64>,
+    pub metallic: Option<f64>,
+    pub roughness_map: Option<String>,
+    pub metallic_map: Option<String>,
+}
+
+impl Material {
+    pub fn new(name: &str) -> Self {
+        Material {
+            name: name.to_owned(),
+            diffuse: [1.0, 1.0, 1.0],
+            alpha: 1.0,
+            diffuse_map: None,
+            emissive: [0.0, 0.0, 0.0],
+            emissive_map: None,
+            roughness: None,
+            metallic: None,
+            roughness_map: None,
+            metallic_map: None,
+        }
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.alpha < 1.0
+    }
+
+    /// `Kd` is already linear, so unlike texture colors it is used as is.
+    pub fn diffuse_color(&self) -> Rgba<f32> {
+        Rgba([
+            self.diffuse[0] as f32,
+            self.diffuse[1] as f32,
+            self.diffuse[2] as f32,
+            self.alpha.clamp(0.0, 1.0) as f32,
+        ])
+    }
+}
+
+/// Reads the materials of an MTL file. Unlike `wavefront_obj::mtl::parse` the statements may come
+/// in any order, may be missing, and `Tr` is understood. Unknown statements are skipped.
+pub fn parse_mtl(source: &str) -> Vec<Material> {
+    let mut materials: Vec<Material> = Vec::new();
+    for line in source.lines() {
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let arguments: Vec<&str> = words.collect();
+        if keyword == "newmtl" {
+            materials.push(Material::new(&arguments.join(" ")));
+            continue;
+        }
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => continue,
+        };
+        let numbers: Vec<f64> = arguments.iter().filter_map(|word| word.parse().ok()).collect();
+        let color = |red: f64| [red, *numbers.get(1).unwrap_or(&red), *numbers.get(2).unwrap_or(&red)];
+        let path = || arguments.last().map(|path| path.to_string());
+        match (keyword, numbers.first()) {
+            ("Kd", Some(&red)) => material.diffuse = color(red),
+            ("Ke", Some(&red)) => material.emissive = color(red),
+            ("d", Some(&opacity)) => material.alpha = opacity,
+            ("Tr", Some(&transparency)) => material.alpha = 1.0 - transparency,
+            ("Pr", Some(&roughness)) => material.roughness = Some(roughness),
+            ("Pm", Some(&metallic)) => material.metallic = Some(metallic),
+            ("map_Kd", _) => material.diffuse_map = path(),
+            ("map_Ke", _) => material.emissive_map = path(),
+            ("map_Pr", _) => material.roughness_map = path(),
+            ("map_Pm", _) => material.metallic_map = path(),
+            _ => {}
+        }
+    }
+    materials
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_be_able_to_parse_transparency() {
+        let materials = parse_mtl("newmtl glass\nKd 0.2 0.4 1.0\nd 0.5\n\nnewmtl smoke\nTr 0.75\nmap_Kd smoke.png\n");
+        assert_eq!(materials.len(), 2);
+        assert_eq!(materials[0].diffuse_color(), Rgba([0.2, 0.4, 1.0, 0.5]));
+        assert!(materials[0].is_transparent());
+        assert_eq!(materials[1].alpha, 0.25);
+        assert_eq!(materials[1].diffuse_map, Some("smoke.png".to_owned()));
+    }
+
+    #[test]
+    fn should_be_able_to_parse_pbr_extension() {
+        let materials = parse_mtl("newmtl gold\nKd 1.0 0.8 0.3\nPr 0.25\nPm 1\nKe 0.5\nmap_Pr -bm 1 gold_rough.png\n");
+        assert_eq!(materials[0].roughness, Some(0.25));
+        assert_eq!(materials[0].metallic, Some(1.0));
+        assert_eq!(materials[0].emissive, [0.5, 0.5, 0.5]);
+        assert_eq!(materials[0].roughness_map, Some("gold_rough.png".to_owned()));
+        assert_eq!(materials[0].metallic_map, None);
+    }
+
+    #[test]
+    fn should_be_able_to_parse_cube_material() {
+        let materials = parse_mtl(include_str!("../resources/cube.mtl"));
+        assert_eq!(materials.len(), 1);
+        assert_eq!(materials[0].name, "default");
+        assert_eq!(materials[0].diffuse_color(), Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert!(!materials[0].is_transparent());
+    }
+}
@@ -0,0 +1,290 @@
+use color::LinearImage;
+use image::bmp::BMPEncoder;
+use image::png::PNGEncoder;
+use image::{ColorType, Pixel, RgbaImage};
+use renderer_error::RendererError;
+use std::io::Write;
+use std::path::Path;
+use vertex_coordinate_mapper::FramebufferOrigin;
+use z_buffer::{ZBuffer, MAX_DEPTH};
+
+/// Image formats a renderer can be saved as.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Png,
+    /// Binary portable pixmap, RGB without alpha.
+    Ppm,
+    /// Binary portable graymap of the image luminance.
+    Pgm,
+    /// Uncompressed 32 bit Truevision TGA, keeping alpha.
+    Tga,
+    Bmp,
+    /// Uncompressed OpenEXR with the linear 32 bit float R, G, B and A channels from before tone mapping
+    /// and a depth channel Z, where 1 is closest.
+    Exr,
+}
+
+impl OutputFormat {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, RendererError> {
+        let extension = path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "png" => Ok(OutputFormat::Png),
+            "ppm" => Ok(OutputFormat::Ppm),
+            "pgm" => Ok(OutputFormat::Pgm),
+            "tga" => Ok(OutputFormat::Tga),
+            "bmp" => Ok(OutputFormat::Bmp),
+            "exr" => Ok(OutputFormat::Exr),
+            _ => Err(RendererError::UnsupportedOutputFormat(extension)),
+        }
+    }
+}
+
+/// Writes `image` with its first row at the top. Formats that can hold them take the untone mapped
+/// `linear_image` and the depth in `z_buffer` instead. The rows of all three are in the order of `origin`:
+/// the encoders that write row by row read them backwards rather than having them flipped first.
+pub fn write_image<W: Write>(
+    writer: &mut W,
+    mut image: RgbaImage,
+    linear_image: &LinearImage,
+    z_buffer: &ZBuffer,
+    origin: FramebufferOrigin,
+    format: OutputFormat,
+) -> Result<(), RendererError> {
+    let (width, height) = image.dimensions();
+    match format {
+        OutputFormat::Png | OutputFormat::Bmp => {
+            // These encoders take the whole image top row first, so the rows are swapped where they are.
+            if origin == FramebufferOrigin::BottomLeft {
+                flip_vertical_in_place(&mut image);
+            }
+            if format == OutputFormat::Png {
+                PNGEncoder::new(writer).encode(&image, width, height, ColorType::RGBA(8))?
+            } else {
+                BMPEncoder::new(writer).encode(&image, width, height, ColorType::RGBA(8))?
+            }
+        }
+        OutputFormat::Ppm => write_ppm(writer, &image, origin)?,
+        OutputFormat::Pgm => write_pgm(writer, &image, origin)?,
+        OutputFormat::Tga => write_tga(writer, &image, origin)?,
+        OutputFormat::Exr => write_exr(writer, linear_image, z_buffer, origin)?,
+    }
+    Ok(())
+}
+
+/// Mirrors `image` top to bottom without a second image.
+pub fn flip_vertical_in_place(image: &mut RgbaImage) {
+    let (width, height) = image.dimensions();
+    let row_length = width as usize * 4;
+    let data: &mut [u8] = image;
+    for y in 0..height as usize / 2 {
+        let (top, bottom) = data.split_at_mut((height as usize - 1 - y) * row_length);
+        top[y * row_length..(y + 1) * row_length].swap_with_slice(&mut bottom[..row_length]);
+    }
+}
+
+/// The buffer rows in output order, top first.
+fn rows(height: u32, origin: FramebufferOrigin) -> impl Iterator<Item = u32> {
+    (0..height).map(move |y| match origin {
+        FramebufferOrigin::TopLeft => y,
+        FramebufferOrigin::BottomLeft => height - 1 - y,
+    })
+}
+
+pub fn write_ppm<W: Write>(writer: &mut W, image: &RgbaImage, origin: FramebufferOrigin) -> Result<(), RendererError> {
+    write!(writer, "P6\n{} {}\n255\n", image.width(), image.height())?;
+    let mut line = Vec::with_capacity(image.width() as usize * 3);
+    for y in rows(image.height(), origin) {
+        line.clear();
+        for x in 0..image.width() {
+            line.extend_from_slice(&image[(x, y)].to_rgb().data);
+        }
+        writer.write_all(&line)?;
+    }
+    Ok(())
+}
+
+/// Binary portable graymap of the luminance of `image`.
+pub fn write_pgm<W: Write>(writer: &mut W, image: &RgbaImage, origin: FramebufferOrigin) -> Result<(), RendererError> {
+    write!(writer, "P5\n{} {}\n255\n", image.width(), image.height())?;
+    let mut line = Vec::with_capacity(image.width() as usize);
+    for y in rows(image.height(), origin) {
+        line.clear();
+        line.extend((0..image.width()).map(|x| image[(x, y)].to_luma().data[0]));
+        writer.write_all(&line)?;
+    }
+    Ok(())
+}
+
+/// TGA stores either row order, so the rows are written as they are.
+pub fn write_tga<W: Write>(writer: &mut W, image: &RgbaImage, origin: FramebufferOrigin) -> Result<(), RendererError> {
+    let (width, height) = image.dimensions();
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(RendererError::ImageTooLarge(width, height));
+    }
+    let mut header = [0u8; 18];
+    header[2] = 2; // uncompressed true color
+    header[12..14].copy_from_slice(&(width as u16).to_le_bytes());
+    header[14..16].copy_from_slice(&(height as u16).to_le_bytes());
+    header[16] = 32;
+    header[17] = match origin {
+        FramebufferOrigin::TopLeft => 0x28, // 8 alpha bits, first row at the top
+        FramebufferOrigin::BottomLeft => 0x08,
+    };
+    writer.write_all(&header)?;
+    let mut data = Vec::with_capacity((width * height * 4) as usize);
+    for pixel in image.pixels() {
+        let [red, green, blue, alpha] = pixel.data;
+        data.extend_from_slice(&[blue, green, red, alpha]);
+    }
+    writer.write_all(&data)?;
+    Ok(())
+}
+
+pub fn write_exr<W: Write>(
+    writer: &mut W,
+    image: &LinearImage,
+    z_buffer: &ZBuffer,
+    origin: FramebufferOrigin,
+) -> Result<(), RendererError> {
+    let (width, height) = image.dimensions();
+    let channels = ["A", "B", "G", "R", "Z"];
+    let mut header = Vec::new();
+    header.extend_from_slice(&20_000_630_u32.to_le_bytes());
+    header.extend_from_slice(&2_u32.to_le_bytes());
+    let mut channel_list = Vec::new();
+    for name in channels.iter() {
+        channel_list.extend_from_slice(name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&2_i32.to_le_bytes()); // 32 bit float
+        channel_list.extend_from_slice(&[0, 0, 0, 0]); // linear, reserved
+        channel_list.extend_from_slice(&1_i32.to_le_bytes());
+        channel_list.extend_from_slice(&1_i32.to_le_bytes());
+    }
+    channel_list.push(0);
+    let mut window = Vec::new();
+    for value in [0, 0, width as i32 - 1, height as i32 - 1].iter() {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+    write_exr_attribute(&mut header, "channels", "chlist", &channel_list);
+    write_exr_attribute(&mut header, "compression", "compression", &[0]);
+    write_exr_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_exr_attribute(&mut header, "displayWindow", "box2i", &window);
+    write_exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    write_exr_attribute(&mut header, "pixelAspectRatio", "float", &1.0_f32.to_le_bytes());
+    write_exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    write_exr_attribute(&mut header, "screenWindowWidth", "float", &1.0_f32.to_le_bytes());
+    header.push(0);
+    writer.write_all(&header)?;
+
+    let line_size = width as u64 * channels.len() as u64 * 4;
+    let first_line = header.len() as u64 + height as u64 * 8;
+    let mut offsets = Vec::with_capacity(height as usize * 8);
+    for y in 0..height as u64 {
+        offsets.extend_from_slice(&(first_line + y * (line_size + 8)).to_le_bytes());
+    }
+    writer.write_all(&offsets)?;
+
+    let mut line = Vec::with_capacity(line_size as usize + 8);
+    for (line_index, y) in rows(height, origin).enumerate() {
+        line.clear();
+        line.extend_from_slice(&(line_index as i32).to_le_bytes());
+        line.extend_from_slice(&(line_size as i32).to_le_bytes());
+        for &channel in [3, 2, 1, 0].iter() {
+            for x in 0..width {
+                line.extend_from_slice(&image[(x, y)].data[channel].to_le_bytes());
+            }
+        }
+        for x in 0..width {
+            let depth = z_buffer.get_depth(x, y) as f32 / MAX_DEPTH as f32;
+            line.extend_from_slice(&depth.to_le_bytes());
+        }
+        writer.write_all(&line)?;
+    }
+    Ok(())
+}
+
+fn write_exr_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn format_should_be_inferred_from_extension() {
+        assert_eq!(OutputFormat::from_path("render.PPM"), Ok(OutputFormat::Ppm));
+        assert_eq!(OutputFormat::from_path("out/depth.exr"), Ok(OutputFormat::Exr));
+        assert_eq!(
+            OutputFormat::from_path("render.jpg"),
+            Err(RendererError::UnsupportedOutputFormat("jpg".to_owned()))
+        );
+    }
+
+    #[test]
+    fn ppm_should_have_header_and_rgb_data() {
+        let image = RgbaImage::from_pixel(2, 1, Rgba([1, 2, 3, 4]));
+        let mut output = Vec::new();
+        let (linear_image, z_buffer) = (LinearImage::new(2, 1), ZBuffer::new(2, 1));
+        write_image(&mut output, image, &linear_image, &z_buffer, FramebufferOrigin::TopLeft, OutputFormat::Ppm).unwrap();
+        assert_eq!(output, b"P6\n2 1\n255\n\x01\x02\x03\x01\x02\x03".to_vec());
+    }
+
+    #[test]
+    fn bottom_left_rows_should_be_written_last_first() {
+        let image = RgbaImage::from_fn(1, 3, |_, y| Rgba([y as u8, 0, 0, 255]));
+        let (linear_image, z_buffer) = (LinearImage::new(1, 3), ZBuffer::new(1, 3));
+        let write = |format| {
+            let mut output = Vec::new();
+            write_image(&mut output, image.clone(), &linear_image, &z_buffer, FramebufferOrigin::BottomLeft, format).unwrap();
+            output
+        };
+        assert_eq!(&write(OutputFormat::Ppm)[11..], &[2, 0, 0, 1, 0, 0, 0, 0, 0]);
+        let tga = write(OutputFormat::Tga);
+        assert_eq!((tga[17], &tga[18..22]), (0x08, &[0, 0, 0, 255][..]));
+        let mut flipped = image.clone();
+        flip_vertical_in_place(&mut flipped);
+        assert_eq!(flipped.into_raw(), vec![2, 0, 0, 255, 1, 0, 0, 255, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn tga_should_store_bgra() {
+        let image = RgbaImage::from_pixel(1, 1, Rgba([1, 2, 3, 4]));
+        let mut output = Vec::new();
+        write_tga(&mut output, &image, FramebufferOrigin::TopLeft).unwrap();
+        assert_eq!(output.len(), 18 + 4);
+        assert_eq!(output[12], 1);
+        assert_eq!(&output[18..], &[3, 2, 1, 4]);
+        let too_wide = RgbaImage::new(70_000, 1);
+        assert_eq!(write_tga(&mut Vec::new(), &too_wide, FramebufferOrigin::TopLeft), Err(RendererError::ImageTooLarge(70_000, 1)));
+    }
+
+    #[test]
+    fn exr_should_end_with_the_last_scan_line() {
+        let image = LinearImage::from_pixel(3, 2, Rgba([1.0, 1.0, 1.0, 1.0]));
+        let mut z_buffer = ZBuffer::new(3, 2);
+        z_buffer.update_buffer(2, 1, MAX_DEPTH);
+        let mut output = Vec::new();
+        write_exr(&mut output, &image, &z_buffer, FramebufferOrigin::TopLeft).unwrap();
+        assert_eq!(&output[..4], &[0x76, 0x2f, 0x31, 0x01]);
+        let last_depth = &output[output.len() - 4..];
+        assert_eq!(last_depth, &1.0_f32.to_le_bytes());
+        let line_size = 8 + 3 * 5 * 4;
+        let first_offset_at = output.len() - 2 * line_size - 2 * 8;
+        let mut first_offset = [0; 8];
+        first_offset.copy_from_slice(&output[first_offset_at..first_offset_at + 8]);
+        let first_offset = u64::from_le_bytes(first_offset);
+        assert_eq!(first_offset as usize, output.len() - 2 * line_size);
+    }
+}
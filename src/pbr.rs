@@ -0,0 +1,426 @@
+use cgmath::{InnerSpace, Vector2, Vector3};
+use color::LinearImage;
+use environment::EnvironmentMap;
+use g_buffer::SurfaceSample;
+use gltf::image::{Data, Format};
+use image::{DynamicImage, ImageBuffer, Rgb, Rgba};
+use lighting::DirectionalLight;
+use material::Material;
+use renderer_error::RendererError;
+use std::f32::consts::PI;
+use std::path::Path;
+use texture::Texture;
+
+const MIN_ROUGHNESS: f32 = 0.045;
+
+/// One channel of a data texture, such as the green roughness channel of a glTF metallic-roughness map.
+#[derive(Debug, Clone)]
+pub struct TextureChannel {
+    pub texture: Texture,
+    pub channel: usize,
+}
+
+impl TextureChannel {
+    pub fn new(texture: Texture, channel: usize) -> Self {
+        TextureChannel { texture, channel }
+    }
+
+    fn sample(&self, uv: Vector2<f64>) -> f32 {
+        self.texture.sample(uv.x, uv.y).data[self.channel]
+    }
+}
+
+/// Metallic-roughness material, laid out like glTF's: every map is multiplied by its constant factor.
+#[derive(Debug, Clone)]
+pub struct PbrMaterial {
+    /// Linear base color, also multiplied by the albedo of the G-buffer sample.
+    pub base_color: Rgba<f32>,
+    /// An sRGB color texture.
+    pub base_color_map: Option<Texture>,
+    pub metallic: f32,
+    pub metallic_map: Option<TextureChannel>,
+    pub roughness: f32,
+    pub roughness_map: Option<TextureChannel>,
+    /// Baked ambient occlusion, multiplied with the sample's SSAO.
+    pub occlusion_map: Option<TextureChannel>,
+    /// Linear emitted color, which may be brighter than 1.
+    pub emissive: Rgb<f32>,
+    /// An sRGB color texture.
+    pub emissive_map: Option<Texture>,
+}
+
+/// A material's inputs looked up at one point of the surface.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PbrInputs {
+    pub base_color: Rgba<f32>,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub occlusion: f32,
+    pub emissive: Rgb<f32>,
+}
+
+impl PbrMaterial {
+    pub fn new(base_color: Rgba<f32>, metallic: f32, roughness: f32) -> Self {
+        PbrMaterial {
+            base_color,
+            base_color_map: None,
+            metallic,
+            metallic_map: None,
+            roughness,
+            roughness_map: None,
+            occlusion_map: None,
+            emissive: Rgb([0.0, 0.0, 0.0]),
+            emissive_map: None,
+        }
+    }
+
+    /// Maps an MTL material with the `Pr`/`Pm` extension, loading its textures relative to `directory`.
+    /// Materials without it come out as rough dielectrics.
+    pub fn from_mtl<P: AsRef<Path>>(material: &Material, directory: P) -> Result<Self, RendererError> {
+        let directory = directory.as_ref();
+        let color_map = |path: &Option<String>| match *path {
+            Some(ref path) => Texture::open(directory.join(path)).map(Some),
+            None => Ok(None),
+        };
+        let data_map = |path: &Option<String>| match *path {
+            Some(ref path) => Texture::open_data(directory.join(path)).map(|texture| Some(TextureChannel::new(texture, 0))),
+            None => Ok(None),
+        };
+        let [red, green, blue] = material.emissive;
+        Ok(PbrMaterial {
+            base_color: material.diffuse_color(),
+            base_color_map: color_map(&material.diffuse_map)?,
+            metallic: material.metallic.unwrap_or(0.0) as f32,
+            metallic_map: data_map(&material.metallic_map)?,
+            roughness: material.roughness.unwrap_or(1.0) as f32,
+            roughness_map: data_map(&material.roughness_map)?,
+            occlusion_map: None,
+            emissive: Rgb([red as f32, green as f32, blue as f32]),
+            emissive_map: color_map(&material.emissive_map)?,
+        })
+    }
+
+    /// Maps a glTF metallic-roughness material, taking its textures from `images` as `gltf::import` returns
+    /// them. Every map is sampled with the first set of texture coordinates; normal maps are not supported.
+    pub fn from_gltf(material: gltf::Material, images: &[Data]) -> Result<Self, RendererError> {
+        let image = |texture: gltf::Texture| match images.get(texture.source().index()) {
+            Some(data) => gltf_image(data),
+            None => Err(RendererError::Gltf(format!("image {} was not loaded", texture.source().index()))),
+        };
+        let pbr = material.pbr_metallic_roughness();
+        let mut result = PbrMaterial::new(Rgba(pbr.base_color_factor()), pbr.metallic_factor(), pbr.roughness_factor());
+        if let Some(info) = pbr.base_color_texture() {
+            result.base_color_map = Some(Texture::from_image(&image(info.texture())?));
+        }
+        if let Some(info) = pbr.metallic_roughness_texture() {
+            result.set_metallic_roughness_map(Texture::from_data_image(&image(info.texture())?));
+        }
+        if let Some(occlusion) = material.occlusion_texture() {
+            // Bakes the strength in, which scales how far occlusion darkens from 1.
+            let (map, strength) = (image(occlusion.texture())?.to_rgba(), occlusion.strength());
+            let baked = LinearImage::from_fn(map.width(), map.height(), |x, y| {
+                let value = 1.0 + strength * (map[(x, y)].data[0] as f32 / 255.0 - 1.0);
+                Rgba([value, value, value, 1.0])
+            });
+            result.occlusion_map = Some(TextureChannel::new(Texture::from_linear_image(baked), 0));
+        }
+        result.emissive = Rgb(material.emissive_factor());
+        if let Some(info) = material.emissive_texture() {
+            result.emissive_map = Some(Texture::from_image(&image(info.texture())?));
+        }
+        Ok(result)
+    }
+
+    /// Uses a glTF packed metallic-roughness texture, which keeps roughness in green and metalness in blue.
+    pub fn set_metallic_roughness_map(&mut self, texture: Texture) {
+        self.roughness_map = Some(TextureChannel::new(texture.clone(), 1));
+        self.metallic_map = Some(TextureChannel::new(texture, 2));
+    }
+
+    pub fn inputs_at(&self, uv: Vector2<f64>) -> PbrInputs {
+        let mut base_color = self.base_color;
+        if let Some(ref map) = self.base_color_map {
+            let texel = map.sample(uv.x, uv.y);
+            for (channel, value) in base_color.data.iter_mut().zip(texel.data.iter()) {
+                *channel *= value;
+            }
+        }
+        let mut emissive = self.emissive;
+        if let Some(ref map) = self.emissive_map {
+            let texel = map.sample(uv.x, uv.y);
+            for (channel, value) in emissive.data.iter_mut().zip(texel.data.iter()) {
+                *channel *= value;
+            }
+        }
+        let scaled = |factor: f32, map: &Option<TextureChannel>| match *map {
+            Some(ref map) => factor * map.sample(uv),
+            None => factor,
+        };
+        PbrInputs {
+            base_color,
+            metallic: scaled(self.metallic, &self.metallic_map).clamp(0.0, 1.0),
+            roughness: scaled(self.roughness, &self.roughness_map).clamp(MIN_ROUGHNESS, 1.0),
+            occlusion: scaled(1.0, &self.occlusion_map),
+            emissive,
+        }
+    }
+}
+
+/// The 8 bit image behind decoded glTF image data. 16 bit images keep their high byte.
+fn gltf_image(data: &Data) -> Result<DynamicImage, RendererError> {
+    let (channels, wide) = match data.format {
+        Format::R8 => (1, false),
+        Format::R8G8 => (2, false),
+        Format::R8G8B8 => (3, false),
+        Format::R8G8B8A8 => (4, false),
+        Format::R16 => (1, true),
+        Format::R16G16 => (2, true),
+        Format::R16G16B16 => (3, true),
+        Format::R16G16B16A16 => (4, true),
+        Format::R32G32B32FLOAT | Format::R32G32B32A32FLOAT => {
+            return Err(RendererError::Gltf("float images are not supported as textures".to_string()))
+        }
+    };
+    let pixels = if wide {
+        data.pixels.chunks_exact(2).map(|value| (u16::from_ne_bytes([value[0], value[1]]) >> 8) as u8).collect()
+    } else {
+        data.pixels.clone()
+    };
+    let (width, height) = (data.width, data.height);
+    // The importer stores gray images in red, and gray with alpha in red and green.
+    let image = match channels {
+        1 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        2 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+        3 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        _ => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+    };
+    image.ok_or_else(|| RendererError::Gltf("image data is shorter than its dimensions".to_string()))
+}
+
+/// Cook-Torrance shading of a G-buffer sample, for use with `Renderer::shade_g_buffer`: GGX distribution,
+/// Smith-Schlick geometry and Schlick Fresnel for specular, Lambert for diffuse. Light colors mean the
+/// same as for `lambert`, so a white rough dielectric lit head on comes out about as bright with both.
+/// `view_direction` points from the surface towards the viewer, `(0, 0, 1)` for normalized device coordinates.
+pub fn cook_torrance(
+    sample: &SurfaceSample,
+    material: &PbrMaterial,
+    lights: &[DirectionalLight],
+    ambient: Rgb<f32>,
+    view_direction: Vector3<f64>,
+) -> Rgba<f32> {
+    shade(sample, material, lights, view_direction, |_, _| (ambient.data, ambient.data))
+}
+
+/// `cook_torrance` with image-based ambient light: diffuse irradiance and prefiltered reflections of
+/// `environment` instead of a constant color, so the environment map alone can light the scene.
+pub fn cook_torrance_ibl(
+    sample: &SurfaceSample,
+    material: &PbrMaterial,
+    lights: &[DirectionalLight],
+    environment: &EnvironmentMap,
+    view_direction: Vector3<f64>,
+) -> Rgba<f32> {
+    shade(sample, material, lights, view_direction, |reflection, roughness| {
+        (environment.irradiance(sample.normal).data, environment.specular(reflection, roughness).data)
+    })
+}
+
+/// Cook-Torrance with `ambient` giving the diffuse and specular ambient light for the reflected view
+/// direction and the roughness.
+fn shade<F>(
+    sample: &SurfaceSample,
+    material: &PbrMaterial,
+    lights: &[DirectionalLight],
+    view_direction: Vector3<f64>,
+    ambient: F,
+) -> Rgba<f32>
+where
+    F: Fn(Vector3<f64>, f32) -> ([f32; 3], [f32; 3]),
+{
+    let inputs = material.inputs_at(sample.uv);
+    let mut base_color = [0.0; 3];
+    for (channel, value) in base_color.iter_mut().enumerate() {
+        *value = inputs.base_color.data[channel] * sample.albedo.data[channel];
+    }
+    let specular_color = mix(base_color, 0.04, inputs.metallic);
+    let diffuse_color = scale(base_color, 1.0 - inputs.metallic);
+    let normal = sample.normal;
+    let view = view_direction.normalize();
+    let n_dot_v = (normal.dot(view) as f32).max(1e-4);
+    let alpha = inputs.roughness * inputs.roughness;
+
+    let mut color = [0.0; 3];
+    for light in lights {
+        let n_dot_l = normal.dot(light.direction) as f32;
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+        let half = (light.direction + view).normalize();
+        let n_dot_h = (normal.dot(half) as f32).max(0.0);
+        let v_dot_h = (view.dot(half) as f32).max(0.0);
+        let distribution = ggx_distribution(n_dot_h, alpha);
+        let geometry = smith_geometry(n_dot_l, n_dot_v, inputs.roughness);
+        let fresnel = schlick_fresnel(specular_color, v_dot_h);
+        for channel in 0..3 {
+            let specular = distribution * geometry * fresnel[channel] / (4.0 * n_dot_l * n_dot_v);
+            let diffuse = (1.0 - fresnel[channel]) * diffuse_color[channel];
+            color[channel] += (diffuse + PI * specular) * light.color.data[channel] * n_dot_l;
+        }
+    }
+
+    let ambient_specular = environment_brdf(specular_color, inputs.roughness, n_dot_v);
+    let occlusion = sample.ambient_occlusion * inputs.occlusion;
+    let reflection = normal * (2.0 * normal.dot(view)) - view;
+    let (diffuse_light, specular_light) = ambient(reflection, inputs.roughness);
+    for channel in 0..3 {
+        let ambient = diffuse_light[channel] * diffuse_color[channel] + specular_light[channel] * ambient_specular[channel];
+        color[channel] += ambient * occlusion;
+        color[channel] += inputs.emissive.data[channel];
+    }
+    Rgba([color[0], color[1], color[2], inputs.base_color.data[3] * sample.albedo.data[3]])
+}
+
+fn ggx_distribution(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha_squared = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha_squared - 1.0) + 1.0;
+    alpha_squared / (PI * denominator * denominator)
+}
+
+fn smith_geometry(n_dot_l: f32, n_dot_v: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let schlick_ggx = |n_dot_x: f32| n_dot_x / (n_dot_x * (1.0 - k) + k);
+    schlick_ggx(n_dot_l) * schlick_ggx(n_dot_v)
+}
+
+fn schlick_fresnel(specular_color: [f32; 3], v_dot_h: f32) -> [f32; 3] {
+    let weight = (1.0 - v_dot_h).powi(5);
+    let mut fresnel = specular_color;
+    for value in fresnel.iter_mut() {
+        *value += (1.0 - *value) * weight;
+    }
+    fresnel
+}
+
+/// Karis' analytic fit of the split sum environment BRDF, giving the specular reflectance under uniform light.
+pub fn environment_brdf(specular_color: [f32; 3], roughness: f32, n_dot_v: f32) -> [f32; 3] {
+    let r = [
+        1.0 - roughness,
+        roughness * -0.0275 + 0.0425,
+        roughness * -0.572 + 1.04,
+        roughness * 0.022 - 0.04,
+    ];
+    let a004 = (r[0] * r[0]).min((-9.28 * n_dot_v).exp2()) * r[0] + r[1];
+    let (scale_term, bias_term) = (-1.04 * a004 + r[2], 1.04 * a004 + r[3]);
+    let mut reflectance = specular_color;
+    for value in reflectance.iter_mut() {
+        *value = *value * scale_term + bias_term;
+    }
+    reflectance
+}
+
+fn mix(color: [f32; 3], dielectric: f32, metallic: f32) -> [f32; 3] {
+    let mut mixed = color;
+    for value in mixed.iter_mut() {
+        *value = dielectric * (1.0 - metallic) + *value * metallic;
+    }
+    mixed
+}
+
+fn scale(color: [f32; 3], factor: f32) -> [f32; 3] {
+    [color[0] * factor, color[1] * factor, color[2] * factor]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color::LinearImage;
+
+    fn facing_sample() -> SurfaceSample {
+        SurfaceSample {
+            albedo: Rgba([1.0, 1.0, 1.0, 1.0]),
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            uv: Vector2::new(0.5, 0.5),
+            depth: 0,
+            material_id: 0,
+            ambient_occlusion: 1.0,
+        }
+    }
+
+    fn shade(material: &PbrMaterial, light_direction: Vector3<f64>) -> Rgba<f32> {
+        let lights = [DirectionalLight::new(light_direction, Rgb([1.0, 1.0, 1.0]))];
+        cook_torrance(&facing_sample(), material, &lights, Rgb([0.0, 0.0, 0.0]), Vector3::new(0.0, 0.0, 1.0))
+    }
+
+    #[test]
+    fn rough_dielectric_should_look_about_like_lambert() {
+        let color = shade(&PbrMaterial::new(Rgba([0.5, 0.5, 0.5, 1.0]), 0.0, 1.0), Vector3::new(0.0, 0.0, 1.0));
+        assert!((color.data[0] - 0.5).abs() < 0.05, "{:?}", color);
+    }
+
+    #[test]
+    fn smooth_metal_should_have_a_tight_bright_highlight() {
+        let metal = PbrMaterial::new(Rgba([1.0, 0.8, 0.3, 1.0]), 1.0, 0.2);
+        let head_on = shade(&metal, Vector3::new(0.0, 0.0, 1.0));
+        let grazing = shade(&metal, Vector3::new(1.0, 0.0, 1.0));
+        assert!(head_on.data[0] > 2.0);
+        assert!(grazing.data[0] < 0.1);
+        assert!(head_on.data[2] < head_on.data[0]);
+    }
+
+    #[test]
+    fn smooth_metal_should_mirror_the_environment() {
+        // A bright stripe towards +x, seen in a mirror tilted 45 degrees from the viewer.
+        let environment = EnvironmentMap::new(LinearImage::from_fn(32, 16, |x, _| {
+            let value = if x == 23 || x == 24 { 4.0 } else { 0.0 };
+            Rgba([value, value, value, 1.0])
+        }));
+        let material = PbrMaterial::new(Rgba([1.0, 1.0, 1.0, 1.0]), 1.0, 0.0);
+        let lights: [DirectionalLight; 0] = [];
+        let shade_towards = |normal: Vector3<f64>| {
+            let sample = SurfaceSample { normal: normal.normalize(), ..facing_sample() };
+            cook_torrance_ibl(&sample, &material, &lights, &environment, Vector3::new(0.0, 0.0, 1.0)).data[0]
+        };
+        assert!(shade_towards(Vector3::new(1.0, 0.0, 1.0)) > 3.0);
+        assert!(shade_towards(Vector3::new(0.0, 0.0, 1.0)) < 0.1);
+    }
+
+    #[test]
+    fn maps_should_scale_the_factors_and_emission_should_add_up() {
+        let mut material = PbrMaterial::new(Rgba([1.0, 1.0, 1.0, 1.0]), 1.0, 1.0);
+        material.set_metallic_roughness_map(Texture::from_linear_image(LinearImage::from_pixel(1, 1, Rgba([0.0, 0.5, 0.25, 1.0]))));
+        material.emissive = Rgb([2.0, 0.0, 0.0]);
+        let inputs = material.inputs_at(Vector2::new(0.5, 0.5));
+        assert_eq!((inputs.roughness, inputs.metallic), (0.5, 0.25));
+        let lights: [DirectionalLight; 0] = [];
+        let color = cook_torrance(&facing_sample(), &material, &lights, Rgb([0.0, 0.0, 0.0]), Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(color, Rgba([2.0, 0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn gltf_materials_should_keep_their_factors_and_maps() {
+        let json = r#"{
+            "asset": {"version": "2.0"},
+            "images": [{"uri": "metal_rough.png"}, {"uri": "occlusion.png"}],
+            "textures": [{"source": 0}, {"source": 1}],
+            "materials": [{
+                "pbrMetallicRoughness": {
+                    "baseColorFactor": [1.0, 0.5, 0.25, 1.0],
+                    "metallicFactor": 0.5,
+                    "metallicRoughnessTexture": {"index": 0}
+                },
+                "occlusionTexture": {"index": 1, "strength": 0.5},
+                "emissiveFactor": [0.0, 2.0, 0.0]
+            }]
+        }"#;
+        let document = gltf::Gltf::from_slice(json.as_bytes()).unwrap().document;
+        let images = [
+            Data { pixels: vec![0, 51, 255], format: Format::R8G8B8, width: 1, height: 1 },
+            Data { pixels: vec![0, 0], format: Format::R16, width: 1, height: 1 },
+        ];
+        let material = PbrMaterial::from_gltf(document.materials().next().unwrap(), &images).unwrap();
+        let inputs = material.inputs_at(Vector2::new(0.5, 0.5));
+        assert_eq!((inputs.base_color, inputs.emissive), (Rgba([1.0, 0.5, 0.25, 1.0]), Rgb([0.0, 2.0, 0.0])));
+        assert_eq!((inputs.metallic, inputs.roughness, inputs.occlusion), (0.5, 0.2, 0.5));
+        assert!(PbrMaterial::from_gltf(document.materials().next().unwrap(), &images[..1]).is_err());
+    }
+}
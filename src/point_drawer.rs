@@ -0,0 +1,132 @@
+use blend_mode::BlendMode;
+use cgmath::Point3;
+use color::LinearImage;
+use image::Rgba;
+use z_buffer::{DepthState, PixelVisibility, ZBuffer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PointShape {
+    #[default]
+    Square,
+    Round,
+}
+
+/// How points are splatted. The default is a single pixel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointStyle {
+    /// Width of the splat in pixels.
+    pub size: f64,
+    pub shape: PointShape,
+}
+
+impl PointStyle {
+    pub fn new(size: f64, shape: PointShape) -> Self {
+        PointStyle { size, shape }
+    }
+}
+
+impl Default for PointStyle {
+    fn default() -> Self {
+        PointStyle::new(1.0, PointShape::Square)
+    }
+}
+
+/// Draws a point as a depth tested square or round splat centered on a pixel.
+pub struct PointDrawer<'a> {
+    center: Point3<u32>,
+    col: Rgba<f32>,
+    style: PointStyle,
+    blend_mode: BlendMode,
+    depth: DepthState,
+    buffer: &'a mut LinearImage,
+    z_buffer: &'a mut ZBuffer,
+}
+
+impl<'a> PointDrawer<'a> {
+    pub fn new(
+        center: Point3<u32>,
+        col: Rgba<f32>,
+        style: PointStyle,
+        buffer: &'a mut LinearImage,
+        z_buffer: &'a mut ZBuffer,
+    ) -> Self {
+        PointDrawer {
+            center,
+            col,
+            style,
+            blend_mode: BlendMode::default(),
+            depth: DepthState::default(),
+            buffer,
+            z_buffer,
+        }
+    }
+
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    pub fn set_depth_state(&mut self, depth: DepthState) {
+        self.depth = depth;
+    }
+
+    pub fn draw(&mut self) {
+        let radius = (self.style.size.max(1.0) - 1.0) / 2.0;
+        let reach = radius.ceil() as i64;
+        let (width, height) = (self.buffer.width() as i64, self.buffer.height() as i64);
+        for offset_x in -reach..=reach {
+            for offset_y in -reach..=reach {
+                let (x, y) = (self.center.x as i64 + offset_x, self.center.y as i64 + offset_y);
+                if x < 0 || y < 0 || x >= width || y >= height || !self.covers(offset_x, offset_y, radius) {
+                    continue;
+                }
+                let (x, y) = (x as u32, y as u32);
+                if self.z_buffer.update_with_state(x, y, self.center.z, self.depth) == PixelVisibility::Visible {
+                    self.buffer[(x, y)] = self.blend_mode.blend(self.col, self.buffer[(x, y)]);
+                }
+            }
+        }
+    }
+
+    fn covers(&self, offset_x: i64, offset_y: i64, radius: f64) -> bool {
+        let (offset_x, offset_y) = (offset_x as f64, offset_y as f64);
+        match self.style.shape {
+            PointShape::Square => offset_x.abs() <= radius && offset_y.abs() <= radius,
+            PointShape::Round => (offset_x * offset_x + offset_y * offset_y).sqrt() <= radius + 0.5,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn splat(center: Point3<u32>, style: PointStyle) -> LinearImage {
+        let mut buffer = LinearImage::new(7, 7);
+        let mut z_buffer = ZBuffer::new(7, 7);
+        PointDrawer::new(center, Rgba([1.0, 1.0, 1.0, 1.0]), style, &mut buffer, &mut z_buffer).draw();
+        buffer
+    }
+
+    #[test]
+    fn default_point_should_cover_a_single_pixel() {
+        let buffer = splat(Point3::new(3, 3, 0), PointStyle::default());
+        assert_eq!(buffer.pixels().filter(|p| p.data[3] > 0.0).count(), 1);
+        assert_eq!(buffer[(3, 3)], Rgba([1.0, 1.0, 1.0, 1.0]));
+    }
+
+    #[test]
+    fn round_point_should_leave_out_the_corners() {
+        let square = splat(Point3::new(3, 3, 0), PointStyle::new(5.0, PointShape::Square));
+        let round = splat(Point3::new(3, 3, 0), PointStyle::new(5.0, PointShape::Round));
+        assert_eq!(square.pixels().filter(|p| p.data[3] > 0.0).count(), 25);
+        assert_eq!(square[(1, 1)], Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(round[(1, 1)], Rgba([0.0, 0.0, 0.0, 0.0]));
+        assert_eq!(round[(1, 3)], Rgba([1.0, 1.0, 1.0, 1.0]));
+    }
+
+    #[test]
+    fn point_near_the_border_should_be_cut_off() {
+        let buffer = splat(Point3::new(0, 6, 0), PointStyle::new(3.0, PointShape::Square));
+        assert_eq!(buffer.pixels().filter(|p| p.data[3] > 0.0).count(), 4);
+    }
+}
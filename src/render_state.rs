@@ -0,0 +1,67 @@
+use blend_mode::BlendMode;
+use image::Rgba;
+use line_clipper::ClipPolicy;
+use line_style::LineStyle;
+use point_drawer::PointStyle;
+use stencil_buffer::StencilState;
+use z_buffer::DepthState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FillMode {
+    /// Only the inside of the triangle is filled, the edges are not stroked.
+    #[default]
+    Solid,
+    Wireframe,
+    Points,
+    /// Fills the triangle and shades the pixels near its edges with the given color in the same pass,
+    /// so the wireframe never fights the fill for the depth buffer.
+    SolidWithWireframe(Rgba<f32>),
+}
+
+/// Which triangles are skipped based on their winding in the image.
+/// Triangles whose vertices run counter-clockwise face the viewer.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CullMode {
+    #[default]
+    None,
+    Back,
+    Front,
+}
+
+/// Moves filled triangles away from the viewer by `factor` times their steepest depth slope
+/// plus `units` depth steps, so lines drawn on top of them do not z-fight. Negative values pull them closer.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PolygonOffset {
+    pub factor: f64,
+    pub units: f64,
+}
+
+impl PolygonOffset {
+    pub fn new(factor: f64, units: f64) -> Self {
+        PolygonOffset { factor, units }
+    }
+
+    pub fn depth_bias(&self, max_depth_slope: f64) -> f64 {
+        self.factor * max_depth_slope + self.units
+    }
+}
+
+/// Everything that controls how a draw call reaches the buffers, each part independent of the others.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RenderState {
+    pub fill_mode: FillMode,
+    pub cull_mode: CullMode,
+    pub depth: DepthState,
+    pub blend_mode: BlendMode,
+    pub polygon_offset: PolygonOffset,
+    pub line_style: LineStyle,
+    pub point_style: PointStyle,
+    pub clip_policy: ClipPolicy,
+    pub stencil: StencilState,
+}
+
+impl RenderState {
+    pub fn with_fill_mode(fill_mode: FillMode) -> Self {
+        RenderState { fill_mode, ..RenderState::default() }
+    }
+}
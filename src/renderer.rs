@@ -1,78 +1,733 @@
-use draw_mode::DrawMode;
+use a_buffer::ABuffer;
+use anti_aliasing::AntiAliasing;
+use background::{Background, BackgroundCamera};
+use blend_mode::BlendMode;
+use cgmath::{Matrix4, Vector3};
+use color::{IntoLinear, LinearImage};
+use dithering::{dither, Dithering, Palette};
+use fog::Fog;
+use g_buffer::{GBuffer, GBufferChannel, SurfaceSample, SurfaceVertex};
+use id_buffer::{IdBuffer, PrimitiveId};
 use image::ImageBuffer;
-use image::Rgb;
+use image::Pixel;
+use image::Rgba;
 use image::RgbImage;
+use image::RgbaImage;
+use line_clipper::ClipPolicy;
+use line_drawer::LineDrawer;
+use line_style::LineStyle;
+use output_format::{flip_vertical_in_place, write_image, OutputFormat};
+use point_drawer::{PointDrawer, PointStyle};
+use render_state::{FillMode, RenderState};
 use renderer_error::RendererError;
+use skinning::SkinnedMesh;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem;
+use std::path::Path;
+use ssao::{compute_ssao, SsaoSettings};
+use stencil_buffer::{StencilBuffer, StencilState};
+use terminal::{write_terminal, TerminalStyle};
+use tone_mapping::{tone_map_encoded, ToneMapping};
+use toon::{find_edges, OutlineSettings};
 use triangle_drawer::TriangleDrawer;
+use vertex_coordinate_mapper::{FramebufferOrigin, VertexCoordinateMapper};
 use wavefront_obj::obj::Vertex;
-use z_buffer::ZBuffer;
+use z_buffer::{ZBuffer, MAX_DEPTH};
 
 #[derive(Debug, Clone)]
 pub struct Renderer {
-    buffer: RgbImage,
+    buffer: LinearImage,
     z_buffer: ZBuffer,
+    anti_aliasing: AntiAliasing,
+    origin: FramebufferOrigin,
+    render_state: RenderState,
+    exposure: f32,
+    tone_mapping: ToneMapping,
+    dithering: Dithering,
+    palette: Palette,
+    a_buffer: Option<ABuffer>,
+    g_buffer: Option<GBuffer>,
+    id_buffer: Option<IdBuffer>,
+    primitive_id: PrimitiveId,
+    ssao: Option<SsaoSettings>,
+    stencil_buffer: StencilBuffer,
 }
 
 impl Renderer {
     pub fn new(width: u32, height: u32) -> Self {
+        Renderer::with_anti_aliasing(width, height, AntiAliasing::None)
+    }
+
+    /// Creates a renderer whose `unpack` yields a `width` x `height` image with the given anti-aliasing.
+    /// Supersampling allocates the internal buffers at the scaled resolution.
+    pub fn with_anti_aliasing(width: u32, height: u32, anti_aliasing: AntiAliasing) -> Self {
+        let factor = anti_aliasing.supersampling_factor();
         Renderer {
-            buffer: ImageBuffer::new(width, height),
-            z_buffer: ZBuffer::new(width, height),
+            buffer: ImageBuffer::new(width * factor, height * factor),
+            z_buffer: ZBuffer::new(width * factor, height * factor),
+            stencil_buffer: StencilBuffer::new(width * factor, height * factor),
+            anti_aliasing,
+            origin: FramebufferOrigin::default(),
+            render_state: RenderState::default(),
+            exposure: 0.0,
+            tone_mapping: ToneMapping::default(),
+            dithering: Dithering::default(),
+            palette: Palette::default(),
+            a_buffer: None,
+            g_buffer: None,
+            id_buffer: None,
+            primitive_id: PrimitiveId::default(),
+            ssao: None,
         }
     }
 
+    /// Starts from an sRGB encoded image.
     pub fn from_buffer(buffer: RgbImage) -> Self {
+        let (width, height) = buffer.dimensions();
+        Renderer::from_linear_buffer(LinearImage::from_fn(width, height, |x, y| buffer[(x, y)].into_linear()))
+    }
+
+    /// Starts from an sRGB encoded image with straight alpha.
+    pub fn from_rgba_buffer(buffer: RgbaImage) -> Self {
+        let (width, height) = buffer.dimensions();
+        Renderer::from_linear_buffer(LinearImage::from_fn(width, height, |x, y| buffer[(x, y)].into_linear()))
+    }
+
+    pub fn from_linear_buffer(buffer: LinearImage) -> Self {
         let (width, height) = buffer.dimensions();
         Renderer {
             buffer,
             z_buffer: ZBuffer::new(width, height),
+            stencil_buffer: StencilBuffer::new(width, height),
+            anti_aliasing: AntiAliasing::None,
+            origin: FramebufferOrigin::default(),
+            render_state: RenderState::default(),
+            exposure: 0.0,
+            tone_mapping: ToneMapping::default(),
+            dithering: Dithering::default(),
+            palette: Palette::default(),
+            a_buffer: None,
+            g_buffer: None,
+            id_buffer: None,
+            primitive_id: PrimitiveId::default(),
+            ssao: None,
         }
     }
 
-    pub fn clear_to_color(&mut self, color: Rgb<u8>) {
+    /// Clears to an `Rgb` color, or to an `Rgba` one for a (partially) transparent background.
+    pub fn clear_to_color<P: IntoLinear>(&mut self, color: P) {
+        let color = color.into_linear();
         for pixel in self.buffer.pixels_mut() {
             *pixel = color;
         }
     }
 
-    pub fn draw_triangle_2d(
+    /// Clears to `background` as seen by `camera`, pixel by pixel, instead of to a single color.
+    pub fn clear_to_background(&mut self, background: &Background, camera: &BackgroundCamera) {
+        let (width, height) = self.buffer.dimensions();
+        let aspect_ratio = width as f64 / height as f64;
+        let origin = self.origin;
+        for (x, y, pixel) in self.buffer.enumerate_pixels_mut() {
+            let (normalized_x, normalized_y) = to_normalized(x, y, width, height, origin);
+            let direction = camera.direction(normalized_x, normalized_y, aspect_ratio);
+            *pixel = background.color(direction, (normalized_y + 1.0) / 2.0);
+        }
+    }
+
+    /// Sets which row of the buffers is at the top of the screen. With `TopLeft` the buffers can be
+    /// written out as they are. Only affects subsequent draw calls, so set it before drawing.
+    pub fn set_framebuffer_origin(&mut self, origin: FramebufferOrigin) {
+        self.origin = origin;
+    }
+
+    /// Brightens (positive) or darkens (negative) the image by this many stops before tone mapping.
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure;
+    }
+
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.tone_mapping = tone_mapping;
+    }
+
+    /// Dithers the output when it is reduced to 8 bits, hiding the banding of dark gradients.
+    pub fn set_dithering(&mut self, dithering: Dithering) {
+        self.dithering = dithering;
+    }
+
+    /// Reduces the output to fewer colors. FXAA blends between palette colors, so is best left off then.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Sets the state used by the `draw_*_2d` calls, which only pick the fill mode themselves.
+    pub fn set_render_state(&mut self, render_state: RenderState) {
+        self.render_state = render_state;
+    }
+
+    pub fn get_render_state(&self) -> &RenderState {
+        &self.render_state
+    }
+
+    /// Sets how triangle outlines are stroked by subsequent draw calls.
+    pub fn set_line_style(&mut self, line_style: LineStyle) {
+        self.render_state.line_style = line_style;
+    }
+
+    /// Sets how points are splatted by subsequent draw calls.
+    pub fn set_point_style(&mut self, point_style: PointStyle) {
+        self.render_state.point_style = point_style;
+    }
+
+    /// Sets whether lines reaching outside the image are clipped or reported as errors.
+    pub fn set_clip_policy(&mut self, clip_policy: ClipPolicy) {
+        self.render_state.clip_policy = clip_policy;
+    }
+
+    /// Sets how subsequent draw calls combine their colors with the buffer.
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.render_state.blend_mode = blend_mode;
+    }
+
+    /// Sets the stencil test and ops applied to the filled pixels of subsequent draw calls.
+    pub fn set_stencil_state(&mut self, stencil_state: StencilState) {
+        self.render_state.stencil = stencil_state;
+    }
+
+    /// Enables the G-buffer that `draw_surface_triangle` writes to, at the resolution of the color buffer,
+    /// so one sample per subsample with supersampling.
+    pub fn set_deferred(&mut self, enabled: bool) {
+        self.g_buffer = if enabled {
+            let (width, height) = self.buffer.dimensions();
+            Some(GBuffer::new(width, height))
+        } else {
+            None
+        };
+    }
+
+    pub fn get_g_buffer_reference(&self) -> Option<&GBuffer> {
+        self.g_buffer.as_ref()
+    }
+
+    pub fn clear_g_buffer(&mut self) {
+        if let Some(ref mut g_buffer) = self.g_buffer {
+            g_buffer.clear();
+        }
+    }
+
+    /// Enables screen space ambient occlusion for `shade_g_buffer`. The radius and blur are in pixels of
+    /// the internal buffers, so scale them with the supersampling factor.
+    pub fn set_ssao(&mut self, ssao: Option<SsaoSettings>) {
+        self.ssao = ssao;
+    }
+
+    /// Enables the ID buffer that filled triangles record their `PrimitiveId` in, for `pick`.
+    pub fn set_picking(&mut self, enabled: bool) {
+        self.id_buffer = if enabled {
+            let (width, height) = self.buffer.dimensions();
+            Some(IdBuffer::new(width, height))
+        } else {
+            None
+        };
+    }
+
+    /// Sets the ID recorded for the triangles drawn from now on.
+    pub fn set_primitive_id(&mut self, id: PrimitiveId) {
+        self.primitive_id = id;
+    }
+
+    pub fn get_id_buffer_reference(&self) -> Option<&IdBuffer> {
+        self.id_buffer.as_ref()
+    }
+
+    pub fn clear_id_buffer(&mut self) {
+        if let Some(ref mut id_buffer) = self.id_buffer {
+            id_buffer.clear();
+        }
+    }
+
+    /// The object ID, triangle index and barycentric weights of the triangle covering pixel `x`, `y` of
+    /// the unpacked image. With supersampling the sample closest to the pixel center is used.
+    pub fn pick(&self, x: u32, y: u32) -> Option<(u32, u32, Vector3<f64>)> {
+        let factor = self.anti_aliasing.supersampling_factor();
+        let id_buffer = self.id_buffer.as_ref()?;
+        let hit = id_buffer.get(x * factor + factor / 2, y * factor + factor / 2)?;
+        Some((hit.id.object_id, hit.id.triangle_index, hit.barycentrics))
+    }
+
+    pub fn clear_stencil(&mut self, value: u8) {
+        self.stencil_buffer.clear(value);
+    }
+
+    pub fn get_stencil_reference(&self) -> &StencilBuffer {
+        &self.stencil_buffer
+    }
+
+    /// When enabled, translucent filled triangles are kept aside and composited in depth order when
+    /// the renderer is resolved, so they no longer have to be drawn back to front.
+    pub fn set_order_independent_transparency(&mut self, enabled: bool) {
+        self.a_buffer = if enabled {
+            let (width, height) = self.buffer.dimensions();
+            Some(ABuffer::new(width, height))
+        } else {
+            None
+        };
+    }
+
+    /// Draws a triangle the way `render_state` describes, independently of the renderer's own state.
+    /// With order-independent transparency on, translucent solid triangles are only collected here.
+    pub fn draw_triangle<P: IntoLinear>(
         &mut self,
         vertex_a: &Vertex,
         vertex_b: &Vertex,
         vertex_c: &Vertex,
-        col: Rgb<u8>,
+        col: P,
+        render_state: &RenderState,
     ) -> Result<(), RendererError> {
+        let col = col.into_linear();
         let mut drawer =
-            TriangleDrawer::from_vertices(vertex_a, vertex_b, vertex_c, &mut self.buffer, &mut self.z_buffer)?;
-        drawer.draw(DrawMode::Wireframe, col);
+            TriangleDrawer::from_vertices_with_origin(
+                vertex_a,
+                vertex_b,
+                vertex_c,
+                self.origin,
+                &mut self.buffer,
+                &mut self.z_buffer,
+            )?;
+        if render_state.stencil.is_enabled() {
+            drawer.set_stencil_buffer(&mut self.stencil_buffer);
+        }
+        if let Some(ref mut id_buffer) = self.id_buffer {
+            drawer.set_id_buffer(id_buffer, self.primitive_id);
+        }
+        match self.a_buffer {
+            Some(ref mut a_buffer) if col.data[3] < 1.0 && render_state.fill_mode == FillMode::Solid => {
+                drawer.collect_fragments(col, render_state, a_buffer);
+                Ok(())
+            }
+            _ => drawer.draw(col, render_state),
+        }
+    }
+
+    /// Geometry pass of deferred rendering: writes the surface attributes of the closest triangles into
+    /// the G-buffer, leaving the color buffer to `shade_g_buffer`.
+    pub fn draw_surface_triangle<P: IntoLinear>(
+        &mut self,
+        vertices: &[SurfaceVertex; 3],
+        albedo: P,
+        material_id: u32,
+        render_state: &RenderState,
+    ) -> Result<(), RendererError> {
+        let g_buffer = match self.g_buffer {
+            Some(ref mut g_buffer) => g_buffer,
+            None => return Err(RendererError::DeferredRenderingDisabled),
+        };
+        let mut drawer = TriangleDrawer::from_vertices_with_origin(
+            &vertices[0].position,
+            &vertices[1].position,
+            &vertices[2].position,
+            self.origin,
+            &mut self.buffer,
+            &mut self.z_buffer,
+        )?;
+        if render_state.stencil.is_enabled() {
+            drawer.set_stencil_buffer(&mut self.stencil_buffer);
+        }
+        if let Some(ref mut id_buffer) = self.id_buffer {
+            drawer.set_id_buffer(id_buffer, self.primitive_id);
+        }
+        drawer.write_surfaces(vertices, albedo.into_linear(), material_id, render_state, g_buffer);
         Ok(())
     }
 
-    pub fn draw_filled_triangle_2d(
+    /// The vertex stage for skinned meshes: poses `mesh` with `joint_matrices`, takes it into normalized
+    /// device coordinates with `transform` and draws its triangles into the G-buffer like
+    /// `draw_surface_triangle`. Their material ID is `material_id` plus the index of their material in
+    /// `mesh.materials`. Without deferred rendering they are drawn unlit in `albedo` instead, like
+    /// `draw_triangle`.
+    pub fn draw_skinned_mesh<P: IntoLinear>(
+        &mut self,
+        mesh: &SkinnedMesh,
+        joint_matrices: &[Matrix4<f64>],
+        transform: &Matrix4<f64>,
+        albedo: P,
+        material_id: u32,
+        render_state: &RenderState,
+    ) -> Result<(), RendererError> {
+        let albedo = albedo.into_linear();
+        let vertices = mesh.posed_vertices(joint_matrices, transform);
+        for (index, triangle) in mesh.triangles.iter().enumerate() {
+            let [a, b, c] = triangle.map(|index| vertices[index]);
+            if self.g_buffer.is_some() {
+                let material = mesh.triangle_materials.get(index).cloned().unwrap_or(0) as u32;
+                self.draw_surface_triangle(&[a, b, c], albedo, material_id + material, render_state)?;
+            } else {
+                self.draw_triangle(&a.position, &b.position, &c.position, albedo, render_state)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lighting pass of deferred rendering: blends `shader`'s color for every pixel of the G-buffer a
+    /// surface was drawn to into the color buffer, with the render state's blend mode.
+    /// With SSAO enabled the samples come with their ambient occlusion filled in.
+    pub fn shade_g_buffer<F: Fn(&SurfaceSample) -> Rgba<f32>>(&mut self, shader: F) -> Result<(), RendererError> {
+        let g_buffer = match self.g_buffer {
+            Some(ref g_buffer) => g_buffer,
+            None => return Err(RendererError::DeferredRenderingDisabled),
+        };
+        let occlusion = self
+            .ssao
+            .map(|settings| compute_ssao(&self.z_buffer, Some(g_buffer), self.origin, &settings));
+        let blend_mode = self.render_state.blend_mode;
+        for (x, y, pixel) in self.buffer.enumerate_pixels_mut() {
+            if let Some(sample) = g_buffer.get(x, y) {
+                let mut sample = *sample;
+                if let Some(ref occlusion) = occlusion {
+                    sample.ambient_occlusion = occlusion[(x, y)].data[0];
+                }
+                *pixel = blend_mode.blend(shader(&sample), *pixel);
+            }
+        }
+        Ok(())
+    }
+
+    /// Post effect fading what has been drawn towards the fog color by its depth. Height fog takes every
+    /// pixel back to world space to find its height. Pixels nothing was drawn to count as being at the far
+    /// plane, unless `Fog::fog_background` is off.
+    pub fn apply_fog(&mut self, fog: &Fog) {
+        let (width, height) = self.buffer.dimensions();
+        let origin = self.origin;
+        for (x, y, pixel) in self.buffer.enumerate_pixels_mut() {
+            let depth = self.z_buffer.get_depth(x, y);
+            if depth == 0 && !fog.fog_background {
+                continue;
+            }
+            let distance = 2.0 * (1.0 - depth as f64 / MAX_DEPTH as f64);
+            let (ndc_x, ndc_y) = to_normalized(x, y, width, height, origin);
+            let ndc = Vector3::new(ndc_x, ndc_y, 1.0 - distance);
+            let amount = fog.amount(distance, fog.height.map_or(ndc_y, |height_fog| height_fog.height_at(ndc)));
+            for (value, target) in pixel.data.iter_mut().zip(fog.color.data.iter()) {
+                *value += (target - *value) * amount;
+            }
+        }
+    }
+
+    /// Post effect drawing silhouette lines, and crease lines too when deferred rendering is enabled,
+    /// for cel shaded renders. Use it after shading, as it only looks at the depth and G-buffers.
+    pub fn draw_outlines(&mut self, settings: &OutlineSettings) {
+        let width = settings.width * self.anti_aliasing.supersampling_factor();
+        let edges = find_edges(&self.z_buffer, self.g_buffer.as_ref(), settings, width);
+        let blend_mode = self.render_state.blend_mode;
+        for (x, y, pixel) in self.buffer.enumerate_pixels_mut() {
+            if edges[(x, y)].data[0] > 0 {
+                *pixel = blend_mode.blend(settings.color, *pixel);
+            }
+        }
+    }
+
+    pub fn draw_triangle_2d<P: IntoLinear>(
+        &mut self,
+        vertex_a: &Vertex,
+        vertex_b: &Vertex,
+        vertex_c: &Vertex,
+        col: P,
+    ) -> Result<(), RendererError> {
+        let render_state = RenderState { fill_mode: FillMode::Wireframe, ..self.render_state };
+        self.draw_triangle(vertex_a, vertex_b, vertex_c, col, &render_state)
+    }
+
+    pub fn draw_filled_triangle_2d<P: IntoLinear>(
         &mut self,
         vertex_a: &Vertex,
         vertex_b: &Vertex,
         vertex_c: &Vertex,
-        col: Rgb<u8>,
+        col: P,
     ) -> Result<(), RendererError> {
+        let render_state = RenderState { fill_mode: FillMode::Solid, ..self.render_state };
+        self.draw_triangle(vertex_a, vertex_b, vertex_c, col, &render_state)
+    }
+
+    /// Draws a filled triangle with its edges in `wire_col`, stroked according to the line style.
+    /// Unlike filling and then outlining, the edges cannot z-fight with the fill.
+    pub fn draw_filled_triangle_with_wireframe_2d<P: IntoLinear, Q: IntoLinear>(
+        &mut self,
+        vertex_a: &Vertex,
+        vertex_b: &Vertex,
+        vertex_c: &Vertex,
+        col: P,
+        wire_col: Q,
+    ) -> Result<(), RendererError> {
+        let fill_mode = FillMode::SolidWithWireframe(wire_col.into_linear());
+        let render_state = RenderState { fill_mode, ..self.render_state };
+        self.draw_triangle(vertex_a, vertex_b, vertex_c, col, &render_state)
+    }
+
+    /// Splats a single point with the render state's point style. The fill mode is ignored.
+    pub fn draw_point<P: IntoLinear>(
+        &mut self,
+        vertex: &Vertex,
+        col: P,
+        render_state: &RenderState,
+    ) -> Result<(), RendererError> {
+        let (width, height) = self.buffer.dimensions();
+        let mapper = VertexCoordinateMapper::with_origin(width, height, self.origin);
+        let center = mapper.map_vertex_coords_to_pixel_coords(vertex)?;
         let mut drawer =
-            TriangleDrawer::from_vertices(vertex_a, vertex_b, vertex_c, &mut self.buffer, &mut self.z_buffer)?;
-        drawer.draw(DrawMode::Normal, col);
+            PointDrawer::new(center, col.into_linear(), render_state.point_style, &mut self.buffer, &mut self.z_buffer);
+        drawer.set_blend_mode(render_state.blend_mode);
+        drawer.set_depth_state(render_state.depth);
+        drawer.draw();
         Ok(())
     }
 
-    pub fn get_buffer_reference(&self) -> &RgbImage {
+    /// Draws a line with the render state's line style. The fill mode is ignored.
+    pub fn draw_line<P: IntoLinear>(
+        &mut self,
+        vertex_a: &Vertex,
+        vertex_b: &Vertex,
+        col: P,
+        render_state: &RenderState,
+    ) -> Result<(), RendererError> {
+        let (width, height) = self.buffer.dimensions();
+        let mapper = VertexCoordinateMapper::with_origin(width, height, self.origin);
+        let start = mapper.map_vertex_coords_to_pixel_coords(vertex_a)?;
+        let end = mapper.map_vertex_coords_to_pixel_coords(vertex_b)?;
+        let mut drawer =
+            LineDrawer::with_style(start, end, col.into_linear(), render_state.line_style, &mut self.buffer, &mut self.z_buffer);
+        drawer.set_clip_policy(render_state.clip_policy);
+        drawer.set_blend_mode(render_state.blend_mode);
+        drawer.set_depth_state(render_state.depth);
+        drawer.draw_line()
+    }
+
+    pub fn draw_point_2d<P: IntoLinear>(&mut self, vertex: &Vertex, col: P) -> Result<(), RendererError> {
+        let render_state = self.render_state;
+        self.draw_point(vertex, col, &render_state)
+    }
+
+    /// Draws every vertex of a point cloud in the same color.
+    pub fn draw_point_cloud_2d<P: IntoLinear>(
+        &mut self,
+        vertices: &[Vertex],
+        col: P,
+    ) -> Result<(), RendererError> {
+        let render_state = self.render_state;
+        let col = col.into_linear();
+        for vertex in vertices {
+            self.draw_point(vertex, col, &render_state)?;
+        }
+        Ok(())
+    }
+
+    pub fn draw_line_2d<P: IntoLinear>(
+        &mut self,
+        vertex_a: &Vertex,
+        vertex_b: &Vertex,
+        col: P,
+    ) -> Result<(), RendererError> {
+        let render_state = self.render_state;
+        self.draw_line(vertex_a, vertex_b, col, &render_state)
+    }
+
+    /// Composites the translucent fragments collected so far onto the buffer.
+    pub fn resolve_transparency(&mut self) {
+        if let Some(ref mut a_buffer) = self.a_buffer {
+            a_buffer.resolve(&mut self.buffer, &self.z_buffer, self.render_state.blend_mode);
+        }
+    }
+
+    /// The linear color buffer, at the supersampled resolution if there is one.
+    pub fn get_buffer_reference(&self) -> &LinearImage {
         &self.buffer
     }
 
+    /// Saves the resolved image to `path`, in `format` or else the format matching the file extension.
+    /// With a bottom left origin the image is flipped, so the top of the render always ends up at the top.
+    pub fn save<Q: AsRef<Path>>(&self, path: Q, format: Option<OutputFormat>) -> Result<(), RendererError> {
+        let format = match format {
+            Some(format) => format,
+            None => OutputFormat::from_path(&path)?,
+        };
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer, format)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Saves one channel of the G-buffer, aligned with the image `save` writes. With supersampling the
+    /// sample closest to each pixel center is used. Float formats get the raw values of `GBuffer::channel`,
+    /// the others `GBuffer::channel_preview`.
+    pub fn save_g_buffer_channel<Q: AsRef<Path>>(
+        &self,
+        path: Q,
+        channel: GBufferChannel,
+        format: Option<OutputFormat>,
+    ) -> Result<(), RendererError> {
+        let g_buffer = match self.g_buffer {
+            Some(ref g_buffer) => g_buffer,
+            None => return Err(RendererError::DeferredRenderingDisabled),
+        };
+        let format = match format {
+            Some(format) => format,
+            None => OutputFormat::from_path(&path)?,
+        };
+        let factor = self.anti_aliasing.supersampling_factor();
+        let g_buffer = if factor > 1 { Cow::Owned(g_buffer.downsample(factor)) } else { Cow::Borrowed(g_buffer) };
+        let z_buffer = self.resolved_z_buffer();
+        let mut writer = BufWriter::new(File::create(path)?);
+        let (preview, values) = (g_buffer.channel_preview(channel), g_buffer.channel(channel));
+        write_image(&mut writer, preview, &values, &z_buffer, self.origin, format)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Saves the ID buffer as a false color segmentation image aligned with the image `save` writes, with
+    /// one ID per pixel as `pick` returns it. Float formats get the raw object IDs and triangle indices instead.
+    pub fn save_segmentation<Q: AsRef<Path>>(&self, path: Q, format: Option<OutputFormat>) -> Result<(), RendererError> {
+        let id_buffer = match self.id_buffer {
+            Some(ref id_buffer) => id_buffer,
+            None => return Err(RendererError::PickingDisabled),
+        };
+        let format = match format {
+            Some(format) => format,
+            None => OutputFormat::from_path(&path)?,
+        };
+        let factor = self.anti_aliasing.supersampling_factor();
+        let id_buffer = if factor > 1 { Cow::Owned(id_buffer.downsample(factor)) } else { Cow::Borrowed(id_buffer) };
+        let z_buffer = self.resolved_z_buffer();
+        let mut writer = BufWriter::new(File::create(path)?);
+        let (false_color, values) = (id_buffer.to_false_color(), id_buffer.to_linear_image());
+        write_image(&mut writer, false_color, &values, &z_buffer, self.origin, format)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// The final 8 bit image, tone mapped and resolved, with its first row at the top whatever the origin.
+    pub fn to_image(&self) -> RgbaImage {
+        let (linear_buffer, _) = self.resolved_buffers();
+        let mut buffer = self.to_display(&linear_buffer);
+        if self.origin == FramebufferOrigin::BottomLeft {
+            flip_vertical_in_place(&mut buffer);
+        }
+        buffer
+    }
+
+    /// Like `save`, but writes to any sink, such as standard output for piping into other tools.
+    pub fn write_to<W: Write>(&self, writer: &mut W, format: OutputFormat) -> Result<(), RendererError> {
+        let (linear_buffer, z_buffer) = self.resolved_buffers();
+        let buffer = self.to_display(&linear_buffer);
+        write_image(writer, buffer, &linear_buffer, &z_buffer, self.origin, format)
+    }
+
+    /// Previews the render in a terminal, `columns` characters wide, instead of as an image file.
+    pub fn write_terminal<W: Write>(&self, writer: &mut W, columns: u32, style: TerminalStyle) -> Result<(), RendererError> {
+        write_terminal(writer, &self.to_image(), columns, style)
+    }
+
+    /// Resolves the anti-aliasing and returns the final image, with the alpha channel dropped, and depth buffer.
+    /// Both keep the rows in the order of the framebuffer origin.
     pub fn unpack(self) -> (RgbImage, ZBuffer) {
-        (self.buffer, self.z_buffer)
+        let (buffer, z_buffer) = self.unpack_rgba();
+        let (width, height) = buffer.dimensions();
+        (RgbImage::from_fn(width, height, |x, y| buffer[(x, y)].to_rgb()), z_buffer)
+    }
+
+    /// Like `unpack`, but keeps the alpha channel so the image can be composited onto something else.
+    pub fn unpack_rgba(mut self) -> (RgbaImage, ZBuffer) {
+        let (linear_buffer, z_buffer) = self.take_resolved_buffers();
+        (self.to_display(&linear_buffer), z_buffer)
+    }
+
+    /// Resolves transparency and supersampling, returning the linear image from before exposure and
+    /// tone mapping are applied. Transparent fragments are composited into the result but stay collected.
+    pub fn unpack_linear(&self) -> (LinearImage, ZBuffer) {
+        let (buffer, z_buffer) = self.resolved_buffers();
+        (buffer.into_owned(), z_buffer.into_owned())
+    }
+
+    /// The color and depth buffers with transparency and supersampling resolved, borrowed when there is
+    /// nothing to resolve.
+    fn resolved_buffers(&self) -> (Cow<'_, LinearImage>, Cow<'_, ZBuffer>) {
+        let mut buffer = Cow::Borrowed(&self.buffer);
+        if let Some(a_buffer) = self.a_buffer.as_ref().filter(|a_buffer| a_buffer.fragment_count() > 0) {
+            a_buffer.composite(buffer.to_mut(), &self.z_buffer, self.render_state.blend_mode);
+        }
+        if self.anti_aliasing.supersampling_factor() > 1 {
+            buffer = Cow::Owned(self.anti_aliasing.resolve_samples(&buffer));
+        }
+        (buffer, self.resolved_z_buffer())
+    }
+
+    fn resolved_z_buffer(&self) -> Cow<'_, ZBuffer> {
+        match self.anti_aliasing.supersampling_factor() {
+            1 => Cow::Borrowed(&self.z_buffer),
+            factor => Cow::Owned(self.z_buffer.downsample(factor)),
+        }
+    }
+
+    fn take_resolved_buffers(&mut self) -> (LinearImage, ZBuffer) {
+        self.resolve_transparency();
+        let buffer = mem::replace(&mut self.buffer, LinearImage::new(0, 0));
+        let z_buffer = mem::replace(&mut self.z_buffer, ZBuffer::new(0, 0));
+        let factor = self.anti_aliasing.supersampling_factor();
+        if factor == 1 {
+            return (buffer, z_buffer);
+        }
+        (self.anti_aliasing.resolve_samples(&buffer), z_buffer.downsample(factor))
+    }
+
+    /// Exposes, tone maps and dithers a resolved linear buffer into the final 8 bit image.
+    fn to_display(&self, linear_buffer: &LinearImage) -> RgbaImage {
+        let encoded = tone_map_encoded(linear_buffer, self.exposure, self.tone_mapping);
+        let buffer = dither(&encoded, self.dithering, &self.palette);
+        match self.anti_aliasing {
+            AntiAliasing::Fxaa => self.anti_aliasing.post_process(&buffer),
+            _ => buffer,
+        }
+    }
+}
+
+/// Normalized device coordinates of a pixel, the inverse of `VertexCoordinateMapper`.
+fn to_normalized(x: u32, y: u32, width: u32, height: u32, origin: FramebufferOrigin) -> (f64, f64) {
+    let to_normalized = |pixel: u32, size: u32| pixel as f64 * 2.0 / (size.max(2) - 1) as f64 - 1.0;
+    match origin {
+        FramebufferOrigin::BottomLeft => (to_normalized(x, width), to_normalized(y, height)),
+        FramebufferOrigin::TopLeft => (to_normalized(x, width), -to_normalized(y, height)),
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{ImageBuffer, Renderer, Rgb, RgbImage, Vertex};
+    use super::{AntiAliasing, BlendMode, ImageBuffer, Pixel, Renderer, RgbImage, Vertex};
+    use anti_aliasing::DownsampleFilter;
+    use background::{Background, BackgroundCamera};
+    use cgmath::{Matrix4, Rad, SquareMatrix, Vector2, Vector3};
+    use color::{to_srgb, LinearImage};
+    use environment::EnvironmentMap;
+    use fog::{Fog, FogMode, HeightFog};
+    use g_buffer::{GBufferChannel, SurfaceVertex};
+    use image::{Rgb, Rgba};
+    use id_buffer::PrimitiveId;
+    use keyframe::Transform;
+    use lighting::{lambert, DirectionalLight};
+    use renderer_error::RendererError;
+    use line_style::LineStyle;
+    use output_format::OutputFormat;
+    use vertex_coordinate_mapper::FramebufferOrigin;
+    use point_drawer::{PointShape, PointStyle};
+    use render_state::{CullMode, PolygonOffset, RenderState};
+    use skinning::{Node, SkinWeights, Skeleton, SkinnedMesh};
+    use std::f64::consts::FRAC_PI_2;
+    use stencil_buffer::{StencilFunction, StencilState};
+    use terminal::TerminalStyle;
+    use toon::OutlineSettings;
+    use z_buffer::DepthState;
+
+    fn pixel(renderer: &Renderer, x: u32, y: u32) -> Rgb<u8> {
+        to_srgb(renderer.buffer[(x, y)]).to_rgb()
+    }
 
     #[test]
     fn should_be_able_to_create_renderer_from_dimensions() {
@@ -89,10 +744,100 @@ mod test {
     fn should_be_able_to_clear_with_renderer() {
         let mut renderer = Renderer::new(2, 2);
         renderer.clear_to_color(Rgb([5, 5, 5]));
-        assert_eq!(renderer.buffer[(0, 0)], Rgb([5, 5, 5]));
-        assert_eq!(renderer.buffer[(0, 1)], Rgb([5, 5, 5]));
-        assert_eq!(renderer.buffer[(1, 0)], Rgb([5, 5, 5]));
-        assert_eq!(renderer.buffer[(1, 1)], Rgb([5, 5, 5]));
+        assert_eq!(pixel(&renderer, 0, 0), Rgb([5, 5, 5]));
+        assert_eq!(pixel(&renderer, 0, 1), Rgb([5, 5, 5]));
+        assert_eq!(pixel(&renderer, 1, 0), Rgb([5, 5, 5]));
+        assert_eq!(pixel(&renderer, 1, 1), Rgb([5, 5, 5]));
+    }
+
+    #[test]
+    fn environment_background_should_be_upright() {
+        // Sky above the horizon, ground below it.
+        let environment = EnvironmentMap::new(LinearImage::from_fn(32, 16, |_, y| {
+            let value = if y < 8 { 1.0 } else { 0.0 };
+            Rgba([value, value, value, 1.0])
+        }));
+        for &(origin, sky_row) in [(FramebufferOrigin::BottomLeft, 2), (FramebufferOrigin::TopLeft, 0)].iter() {
+            let mut renderer = Renderer::new(3, 3);
+            renderer.set_framebuffer_origin(origin);
+            renderer.clear_to_background(&Background::Environment(&environment), &BackgroundCamera::new(1.0));
+            assert_eq!(pixel(&renderer, 1, sky_row), Rgb([255, 255, 255]));
+            assert_eq!(pixel(&renderer, 1, 2 - sky_row), Rgb([0, 0, 0]));
+        }
+    }
+
+    #[test]
+    fn fog_should_hide_distant_pixels() {
+        let mut renderer = Renderer::new(4, 4);
+        renderer.clear_to_color(Rgb([0, 0, 0]));
+        let triangle_at = |z| [Vertex { x: -1.0, y: -1.0, z }, Vertex { x: 1.0, y: 1.0, z }, Vertex { x: 1.0, y: -1.0, z }];
+        let near = triangle_at(1.0);
+        let result = renderer.draw_filled_triangle_2d(&near[0], &near[1], &near[2], Rgb([255, 0, 0]));
+        assert_eq!(Ok(()), result);
+        renderer.apply_fog(&Fog::new(FogMode::Linear { start: 0.5, end: 1.5 }, Rgb([1.0, 1.0, 1.0])));
+        assert_eq!(pixel(&renderer, 3, 0), Rgb([255, 0, 0]));
+        assert_eq!(pixel(&renderer, 0, 3), Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn height_fog_should_follow_world_height() {
+        // Looking straight down, so the world height of a pixel comes from its depth and not its row.
+        let mut height_fog = HeightFog::new(0.0, 50.0);
+        height_fog.set_inverse_view_projection(Matrix4::from_angle_x(Rad(FRAC_PI_2)));
+        let mut fog = Fog::new(FogMode::Exponential { density: 1.0 }, Rgb([1.0, 1.0, 1.0]));
+        fog.set_height(Some(height_fog));
+        let mut renderer = Renderer::new(3, 3);
+        renderer.clear_to_color(Rgb([0, 0, 0]));
+        let floor = [Vertex { x: -1.0, y: -1.0, z: 0.5 }, Vertex { x: 1.0, y: 1.0, z: 0.5 }, Vertex { x: 1.0, y: -1.0, z: 0.5 }];
+        assert_eq!(Ok(()), renderer.draw_filled_triangle_2d(&floor[0], &floor[1], &floor[2], Rgb([0, 0, 0])));
+        renderer.apply_fog(&fog);
+        // The floor lies below the fog's base and the far plane high above it, whatever the row.
+        assert_ne!(pixel(&renderer, 2, 0), Rgb([0, 0, 0]));
+        assert_eq!((pixel(&renderer, 0, 1), pixel(&renderer, 0, 2)), (Rgb([0, 0, 0]), Rgb([0, 0, 0])));
+
+        fog.set_height(None);
+        fog.set_fog_background(false);
+        renderer.clear_to_color(Rgb([0, 0, 0]));
+        renderer.apply_fog(&fog);
+        assert_ne!(pixel(&renderer, 2, 0), Rgb([0, 0, 0]));
+        assert_eq!(pixel(&renderer, 0, 2), Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn outlines_should_trace_the_silhouette() {
+        let mut renderer = Renderer::new(8, 8);
+        renderer.clear_to_color(Rgb([255, 255, 255]));
+        let quad = [
+            Vertex { x: -0.5, y: -0.5, z: 0.0 },
+            Vertex { x: 0.5, y: -0.5, z: 0.0 },
+            Vertex { x: 0.5, y: 0.5, z: 0.0 },
+            Vertex { x: -0.5, y: 0.5, z: 0.0 },
+        ];
+        for &(a, b, c) in [(0, 1, 2), (0, 2, 3)].iter() {
+            let result = renderer.draw_filled_triangle_2d(&quad[a], &quad[b], &quad[c], Rgb([255, 0, 0]));
+            assert_eq!(Ok(()), result);
+        }
+        renderer.draw_outlines(&OutlineSettings::new(Rgba([0.0, 0.0, 0.0, 1.0]), 1));
+        assert_eq!(pixel(&renderer, 1, 3), Rgb([0, 0, 0]));
+        assert_eq!(pixel(&renderer, 3, 3), Rgb([255, 0, 0]));
+        assert_eq!(pixel(&renderer, 0, 3), Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn terminal_preview_should_put_the_top_row_first() {
+        let mut renderer = Renderer::new(2, 4);
+        renderer.clear_to_color(Rgb([0, 0, 0]));
+        for &y in [1.0, 1.0 / 3.0].iter() {
+            let result = renderer.draw_line_2d(&Vertex { x: -1.0, y, z: 0.0 }, &Vertex { x: 1.0, y, z: 0.0 }, Rgb([255, 255, 255]));
+            assert_eq!(Ok(()), result);
+        }
+        let mut output = Vec::new();
+        renderer.write_terminal(&mut output, 2, TerminalStyle::Ascii).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let ramp = " .:-=+*#%@";
+        assert!(ramp.find(&lines[0][..1]) > ramp.find(&lines[1][..1]), "{:?}", lines);
     }
 
     #[test]
@@ -100,7 +845,7 @@ mod test {
         let renderer = Renderer::new(2, 2);
         let buffer_ref = renderer.get_buffer_reference();
         for pixel_ref in buffer_ref.pixels() {
-            assert_eq!(Rgb([0, 0, 0]), *pixel_ref);
+            assert_eq!(Rgba([0.0, 0.0, 0.0, 0.0]), *pixel_ref);
         }
         assert_eq!(2, buffer_ref.height());
         assert_eq!(2, buffer_ref.width());
@@ -109,7 +854,7 @@ mod test {
     #[test]
     fn should_be_able_to_unpack_renderer() {
         let renderer = Renderer::new(2, 2);
-        let buffer = renderer.unpack();
+        let (buffer, _z_buffer) = renderer.unpack();
         for pixel_ref in buffer.pixels() {
             assert_eq!(Rgb([0, 0, 0]), *pixel_ref);
         }
@@ -117,6 +862,18 @@ mod test {
         assert_eq!(2, buffer.width());
     }
 
+    #[test]
+    fn supersampled_renderer_should_unpack_to_requested_size() {
+        let anti_aliasing = AntiAliasing::Supersampling { factor: 3, filter: DownsampleFilter::Box };
+        let mut renderer = Renderer::with_anti_aliasing(2, 2, anti_aliasing);
+        assert_eq!(renderer.get_buffer_reference().dimensions(), (6, 6));
+        renderer.clear_to_color(Rgb([9, 9, 9]));
+        let (buffer, z_buffer) = renderer.unpack();
+        assert_eq!(buffer.dimensions(), (2, 2));
+        assert_eq!(buffer[(1, 1)], Rgb([9, 9, 9]));
+        assert_eq!(z_buffer.unpack().dimensions(), (2, 2));
+    }
+
     #[test]
     fn should_be_able_to_draw_triangle() {
         let mut renderer = Renderer::new(3, 3);
@@ -137,11 +894,25 @@ mod test {
         };
         let result = renderer.draw_triangle_2d(&vertex_a, &vertex_b, &vertex_c, Rgb([1, 1, 1]));
         assert_eq!(Ok(()), result);
-        assert_eq!(renderer.buffer[(0, 0)], Rgb([1, 1, 1]));
-        assert_eq!(renderer.buffer[(1, 2)], Rgb([1, 1, 1]));
-        assert_eq!(renderer.buffer[(2, 0)], Rgb([1, 1, 1]));
-        assert_eq!(renderer.buffer[(1, 1)], Rgb([1, 1, 1]));
-        assert_eq!(renderer.buffer[(1, 0)], Rgb([1, 1, 1]));
+        assert_eq!(pixel(&renderer, 0, 0), Rgb([1, 1, 1]));
+        assert_eq!(pixel(&renderer, 1, 2), Rgb([1, 1, 1]));
+        assert_eq!(pixel(&renderer, 2, 0), Rgb([1, 1, 1]));
+        assert_eq!(pixel(&renderer, 1, 1), Rgb([1, 1, 1]));
+        assert_eq!(pixel(&renderer, 1, 0), Rgb([1, 1, 1]));
+    }
+
+    #[test]
+    fn should_be_able_to_draw_triangle_with_wide_outline() {
+        let mut renderer = Renderer::new(9, 9);
+        renderer.set_line_style(LineStyle { width: 3.0, ..LineStyle::default() });
+        let vertex_a = Vertex { x: -1.0, y: -1.0, z: 0.0 };
+        let vertex_b = Vertex { x: 1.0, y: -1.0, z: 0.0 };
+        let vertex_c = Vertex { x: -1.0, y: 1.0, z: 0.0 };
+        let result = renderer.draw_triangle_2d(&vertex_a, &vertex_b, &vertex_c, Rgb([1, 1, 1]));
+        assert_eq!(Ok(()), result);
+        assert_eq!(pixel(&renderer, 4, 1), Rgb([1, 1, 1]));
+        assert_eq!(pixel(&renderer, 1, 4), Rgb([1, 1, 1]));
+        assert_eq!(pixel(&renderer, 2, 2), Rgb([0, 0, 0]));
     }
 
     #[test]
@@ -176,26 +947,336 @@ mod test {
     }
 
 
+    #[test]
+    fn wireframe_over_solid_should_mark_edges_and_keep_the_inside_filled() {
+        let mut renderer = Renderer::new(9, 9);
+        let vertex_a = Vertex { x: -1.0, y: -1.0, z: 0.0 };
+        let vertex_b = Vertex { x: 1.0, y: -1.0, z: 0.0 };
+        let vertex_c = Vertex { x: -1.0, y: 1.0, z: 0.0 };
+        let result = renderer.draw_filled_triangle_with_wireframe_2d(
+            &vertex_a,
+            &vertex_b,
+            &vertex_c,
+            Rgb([1, 1, 1]),
+            Rgb([9, 9, 9]),
+        );
+        assert_eq!(Ok(()), result);
+        assert_eq!(pixel(&renderer, 4, 0), Rgb([9, 9, 9]));
+        assert_eq!(pixel(&renderer, 0, 4), Rgb([9, 9, 9]));
+        assert_eq!(pixel(&renderer, 4, 4), Rgb([9, 9, 9]));
+        assert_eq!(pixel(&renderer, 2, 2), Rgb([1, 1, 1]));
+        assert_eq!(pixel(&renderer, 8, 8), Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn transparent_background_should_survive_unpacking() {
+        let mut renderer = Renderer::new(4, 4);
+        let bottom_left = Vertex { x: -1.0, y: -1.0, z: 0.0 };
+        let top_right = Vertex { x: 1.0, y: 1.0, z: 0.0 };
+        let bottom_right = Vertex { x: 1.0, y: -1.0, z: 0.0 };
+        renderer.clear_to_color(Rgba([0, 0, 0, 0]));
+        let result = renderer.draw_filled_triangle_2d(&bottom_left, &top_right, &bottom_right, Rgba([200, 0, 0, 128]));
+        assert_eq!(Ok(()), result);
+        let (buffer, _z_buffer) = renderer.unpack_rgba();
+        assert_eq!(buffer[(3, 0)], Rgba([200, 0, 0, 128]));
+        assert_eq!(buffer[(0, 3)], Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn additive_blending_should_accumulate_overlapping_triangles() {
+        let mut renderer = Renderer::new(4, 4);
+        renderer.set_blend_mode(BlendMode::Additive);
+        let bottom_left = Vertex { x: -1.0, y: -1.0, z: 0.0 };
+        let top_right = Vertex { x: 1.0, y: 1.0, z: 0.5 };
+        let bottom_right = Vertex { x: 1.0, y: -1.0, z: 0.0 };
+        renderer.clear_to_color(Rgb([0.25_f32, 0.25, 0.25]));
+        for _ in 0..2 {
+            let col = Rgb([0.125_f32, 0.25, 0.5]);
+            let result = renderer.draw_filled_triangle_2d(&bottom_left, &top_right, &bottom_right, col);
+            assert_eq!(Ok(()), result);
+        }
+        assert_eq!(renderer.buffer[(3, 0)], Rgba([0.5, 0.75, 1.25, 1.0]));
+    }
+
+    #[test]
+    fn sloped_triangle_depth_should_follow_its_corners() {
+        let mut renderer = Renderer::new(5, 5);
+        let result = renderer.draw_filled_triangle_2d(
+            &Vertex { x: -1.0, y: -1.0, z: -1.0 },
+            &Vertex { x: 1.0, y: -1.0, z: 1.0 },
+            &Vertex { x: -1.0, y: 1.0, z: 0.0 },
+            Rgb([255, 255, 255]),
+        );
+        assert_eq!(Ok(()), result);
+        let (_, z_buffer) = renderer.unpack();
+        assert_eq!(z_buffer.get_depth(0, 0), 0);
+        assert!(z_buffer.get_depth(4, 0) > z_buffer.get_depth(0, 4));
+        assert!(z_buffer.get_depth(0, 4) > z_buffer.get_depth(0, 0));
+    }
+
+    #[test]
+    fn deferred_pass_should_keep_the_closest_surface_and_light_it() {
+        let mut renderer = Renderer::new(4, 4);
+        let surface_at = |z, normal| {
+            let vertex = |x, y, u| SurfaceVertex::new(Vertex { x, y, z }, normal, Vector2::new(u, 0.0));
+            [vertex(-1.0, -1.0, 0.0), vertex(1.0, 1.0, 1.0), vertex(1.0, -1.0, 1.0)]
+        };
+        let facing = Vector3::new(0.0, 0.0, 1.0);
+        let state = RenderState::default();
+        let result = renderer.draw_surface_triangle(&surface_at(0.0, facing), Rgb([255, 255, 255]), 1, &state);
+        assert_eq!(Err(RendererError::DeferredRenderingDisabled), result);
+
+        renderer.set_deferred(true);
+        let far = renderer.draw_surface_triangle(&surface_at(-0.5, facing), Rgb([255, 0, 0]), 1, &state);
+        let near = renderer.draw_surface_triangle(&surface_at(0.5, facing * 2.0), Rgb([0, 0, 255]), 2, &state);
+        assert_eq!((Ok(()), Ok(())), (far, near));
+        {
+            let g_buffer = renderer.get_g_buffer_reference().unwrap();
+            let sample = g_buffer.get(3, 0).unwrap();
+            assert_eq!((sample.material_id, sample.normal, sample.uv.x), (2, facing, 1.0));
+            assert_eq!(g_buffer.get(0, 3), None);
+            assert_eq!(g_buffer.channel_preview(GBufferChannel::MaterialId)[(3, 0)], Rgba([2, 0, 0, 255]));
+        }
+
+        let lights = [DirectionalLight::new(facing, Rgb([0.5, 0.5, 0.5]))];
+        assert_eq!(Ok(()), renderer.shade_g_buffer(|sample| lambert(sample, &lights, Rgb([0.0, 0.0, 0.0]))));
+        assert_eq!(renderer.buffer[(3, 0)], Rgba([0.0, 0.0, 0.5, 1.0]));
+        assert_eq!(renderer.buffer[(0, 3)], Rgba([0.0, 0.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn skinned_mesh_should_follow_its_joint() {
+        let mut renderer = Renderer::new(8, 8);
+        renderer.set_deferred(true);
+        let vertex = |x, y| SurfaceVertex::new(Vertex { x, y, z: 0.0 }, Vector3::new(0.0, 0.0, 1.0), Vector2::new(0.0, 0.0));
+        let root = Node { name: "root".to_string(), parent: None, transform: Transform::default() };
+        let mesh = SkinnedMesh::new(
+            vec![vertex(-0.5, -0.5), vertex(0.0, -0.5), vertex(0.0, 0.5), vertex(-0.5, 0.5)],
+            vec![SkinWeights::new([0, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]); 4],
+            vec![[0, 1, 2], [0, 2, 3]],
+            Skeleton::new(vec![root], vec![0], vec![Matrix4::identity()]),
+        );
+        let mut pose = mesh.skeleton.rest_pose();
+        pose[0].translation = Vector3::new(0.5, 0.0, 0.0);
+        let joint_matrices = mesh.skeleton.joint_matrices(&pose);
+        let result = renderer.draw_skinned_mesh(&mesh, &joint_matrices, &Matrix4::identity(), Rgb([255, 255, 255]), 1, &RenderState::default());
+        assert_eq!(Ok(()), result);
+        {
+            let g_buffer = renderer.get_g_buffer_reference().unwrap();
+            assert!(g_buffer.get(4, 4).is_some());
+            assert_eq!(g_buffer.get(2, 4), None);
+        }
+
+        let mut renderer = Renderer::new(8, 8);
+        renderer.clear_to_color(Rgb([0, 0, 0]));
+        let result = renderer.draw_skinned_mesh(&mesh, &joint_matrices, &Matrix4::identity(), Rgb([255, 255, 255]), 1, &RenderState::default());
+        assert_eq!(Ok(()), result);
+        assert_eq!((pixel(&renderer, 4, 4), pixel(&renderer, 2, 4)), (Rgb([255, 255, 255]), Rgb([0, 0, 0])));
+    }
+
+    #[test]
+    fn pick_should_report_the_closest_triangle() {
+        let mut renderer = Renderer::new(4, 4);
+        renderer.set_picking(true);
+        let triangle_at = |z| [Vertex { x: -1.0, y: -1.0, z }, Vertex { x: 1.0, y: 1.0, z }, Vertex { x: 1.0, y: -1.0, z }];
+        for &(object_id, z) in [(3, 0.5), (4, -0.5)].iter() {
+            let vertices = triangle_at(z);
+            renderer.set_primitive_id(PrimitiveId::new(object_id, 7));
+            let result = renderer.draw_filled_triangle_2d(&vertices[0], &vertices[1], &vertices[2], Rgb([255, 255, 255]));
+            assert_eq!(Ok(()), result);
+        }
+        let (object_id, triangle_index, barycentrics) = renderer.pick(3, 0).unwrap();
+        assert_eq!((object_id, triangle_index), (3, 7));
+        assert!((barycentrics.x + barycentrics.y + barycentrics.z - 1.0).abs() < 1e-9);
+        assert_eq!(renderer.pick(0, 3), None);
+        assert_eq!(renderer.pick(9, 9), None);
+    }
+
+    #[test]
+    fn order_independent_transparency_should_not_depend_on_draw_order() {
+        let render = |near_first: bool| {
+            let mut renderer = Renderer::new(4, 4);
+            renderer.set_order_independent_transparency(true);
+            renderer.clear_to_color(Rgb([0, 0, 0]));
+            let triangle_at = |z| [Vertex { x: -1.0, y: -1.0, z }, Vertex { x: 1.0, y: 1.0, z }, Vertex { x: 1.0, y: -1.0, z }];
+            let (far, near) = (triangle_at(-0.5), triangle_at(0.5));
+            let mut layers = [(far, Rgba([255, 0, 0, 128])), (near, Rgba([0, 0, 255, 128]))];
+            if near_first {
+                layers.reverse();
+            }
+            for &(ref vertices, col) in layers.iter() {
+                let result = renderer.draw_filled_triangle_2d(&vertices[0], &vertices[1], &vertices[2], col);
+                assert_eq!(Ok(()), result);
+            }
+            renderer.unpack().0
+        };
+        let in_order = render(false);
+        assert_eq!(in_order[(3, 0)], Rgb([137, 0, 188]));
+        assert_eq!(in_order.into_raw(), render(true).into_raw());
+    }
+
+    #[test]
+    fn writing_should_leave_transparent_fragments_for_later() {
+        let mut renderer = Renderer::new(4, 4);
+        renderer.set_order_independent_transparency(true);
+        renderer.clear_to_color(Rgb([0, 0, 0]));
+        let triangle = [Vertex { x: -1.0, y: -1.0, z: 0.0 }, Vertex { x: 1.0, y: 1.0, z: 0.0 }, Vertex { x: 1.0, y: -1.0, z: 0.0 }];
+        let result = renderer.draw_filled_triangle_2d(&triangle[0], &triangle[1], &triangle[2], Rgba([0, 0, 255, 128]));
+        assert_eq!(Ok(()), result);
+        let buffer_before = renderer.buffer.clone().into_raw();
+        let fragments_before = renderer.a_buffer.as_ref().unwrap().fragment_count();
+        let mut output = Vec::new();
+        renderer.write_to(&mut output, OutputFormat::Ppm).unwrap();
+        // The bottom right pixel comes last.
+        assert_eq!(output[output.len() - 3..], [0, 0, 188]);
+        assert_eq!(to_srgb(renderer.unpack_linear().0[(3, 0)]).to_rgb(), Rgb([0, 0, 188]));
+        assert_eq!(renderer.buffer.clone().into_raw(), buffer_before);
+        assert_eq!(renderer.a_buffer.as_ref().unwrap().fragment_count(), fragments_before);
+        assert!(fragments_before > 0);
+        assert_eq!(renderer.unpack().0[(3, 0)], Rgb([0, 0, 188]));
+    }
+
+    #[test]
+    fn stencil_should_mask_silhouette_outline() {
+        let mut renderer = Renderer::new(9, 9);
+        let triangle_at_scale = |scale: f64| {
+            [
+                Vertex { x: -scale, y: -scale, z: 0.0 },
+                Vertex { x: scale, y: -scale, z: 0.0 },
+                Vertex { x: 0.0, y: scale, z: 0.0 },
+            ]
+        };
+        let object = triangle_at_scale(0.5);
+        let outline = triangle_at_scale(1.0);
+        renderer.set_stencil_state(StencilState::write(1));
+        let result = renderer.draw_filled_triangle_2d(&object[0], &object[1], &object[2], Rgb([1, 1, 1]));
+        assert_eq!(Ok(()), result);
+        renderer.set_stencil_state(StencilState::test(StencilFunction::NotEqual, 1));
+        let result = renderer.draw_filled_triangle_2d(&outline[0], &outline[1], &outline[2], Rgb([9, 9, 9]));
+        assert_eq!(Ok(()), result);
+        assert_eq!(renderer.get_stencil_reference().get(4, 4), 1);
+        assert_eq!(pixel(&renderer, 4, 4), Rgb([1, 1, 1]));
+        assert_eq!(pixel(&renderer, 4, 7), Rgb([9, 9, 9]));
+        assert_eq!(pixel(&renderer, 0, 0), Rgb([9, 9, 9]));
+    }
+
+    #[test]
+    fn stencil_should_mask_order_independent_transparency() {
+        let mut renderer = Renderer::new(4, 4);
+        renderer.set_order_independent_transparency(true);
+        renderer.clear_to_color(Rgb([0, 0, 0]));
+        let triangle = [Vertex { x: -1.0, y: -1.0, z: 0.0 }, Vertex { x: 1.0, y: 1.0, z: 0.0 }, Vertex { x: 1.0, y: -1.0, z: 0.0 }];
+        renderer.set_stencil_state(StencilState::test(StencilFunction::Equal, 1));
+        let result = renderer.draw_filled_triangle_2d(&triangle[0], &triangle[1], &triangle[2], Rgba([255, 0, 0, 128]));
+        assert_eq!(Ok(()), result);
+        renderer.set_stencil_state(StencilState::write(2));
+        let result = renderer.draw_filled_triangle_2d(&triangle[0], &triangle[1], &triangle[2], Rgba([0, 0, 255, 128]));
+        assert_eq!(Ok(()), result);
+        assert_eq!(renderer.get_stencil_reference().get(3, 0), 2);
+        assert_eq!(renderer.unpack().0[(3, 0)], Rgb([0, 0, 188]));
+    }
+
+    #[test]
+    fn back_facing_triangles_should_be_culled() {
+        let mut renderer = Renderer::new(4, 4);
+        let render_state = RenderState { cull_mode: CullMode::Back, ..RenderState::default() };
+        let vertex_a = Vertex { x: -1.0, y: -1.0, z: 0.0 };
+        let vertex_b = Vertex { x: 1.0, y: -1.0, z: 0.0 };
+        let vertex_c = Vertex { x: 1.0, y: 1.0, z: 0.0 };
+        let result = renderer.draw_triangle(&vertex_c, &vertex_b, &vertex_a, Rgb([1, 1, 1]), &render_state);
+        assert_eq!(Ok(()), result);
+        assert_eq!(pixel(&renderer, 3, 0), Rgb([0, 0, 0]));
+        let result = renderer.draw_triangle(&vertex_a, &vertex_b, &vertex_c, Rgb([1, 1, 1]), &render_state);
+        assert_eq!(Ok(()), result);
+        assert_eq!(pixel(&renderer, 3, 0), Rgb([1, 1, 1]));
+    }
+
+    #[test]
+    fn polygon_offset_and_depth_state_should_control_visibility() {
+        let mut renderer = Renderer::new(4, 4);
+        let triangle = [
+            Vertex { x: -1.0, y: -1.0, z: 0.0 },
+            Vertex { x: 1.0, y: -1.0, z: 0.0 },
+            Vertex { x: 1.0, y: 1.0, z: 0.0 },
+        ];
+        let pushed_back = RenderState { polygon_offset: PolygonOffset::new(0.0, 10.0), ..RenderState::default() };
+        renderer.draw_triangle(&triangle[0], &triangle[1], &triangle[2], Rgb([1, 1, 1]), &pushed_back).unwrap();
+        renderer.draw_filled_triangle_2d(&triangle[0], &triangle[1], &triangle[2], Rgb([2, 2, 2])).unwrap();
+        renderer.draw_triangle(&triangle[0], &triangle[1], &triangle[2], Rgb([3, 3, 3]), &pushed_back).unwrap();
+        assert_eq!(pixel(&renderer, 3, 0), Rgb([2, 2, 2]));
+        let no_depth_test = RenderState { depth: DepthState { test: false, write: false }, ..pushed_back };
+        renderer.draw_triangle(&triangle[0], &triangle[1], &triangle[2], Rgb([4, 4, 4]), &no_depth_test).unwrap();
+        assert_eq!(pixel(&renderer, 3, 0), Rgb([4, 4, 4]));
+    }
+
+    #[test]
+    fn should_be_able_to_draw_points_and_lines() {
+        let mut renderer = Renderer::new(9, 9);
+        renderer.set_point_style(PointStyle::new(3.0, PointShape::Square));
+        let cloud = [Vertex { x: -1.0, y: -1.0, z: 0.0 }, Vertex { x: 0.0, y: 0.0, z: 0.0 }];
+        assert_eq!(Ok(()), renderer.draw_point_cloud_2d(&cloud, Rgb([1, 1, 1])));
+        assert_eq!(pixel(&renderer, 0, 0), Rgb([1, 1, 1]));
+        assert_eq!(pixel(&renderer, 5, 5), Rgb([1, 1, 1]));
+        assert_eq!(pixel(&renderer, 6, 6), Rgb([0, 0, 0]));
+        let (top_left, top_right) = (Vertex { x: -1.0, y: 1.0, z: 0.0 }, Vertex { x: 1.0, y: 1.0, z: 0.0 });
+        let result = renderer.draw_line_2d(&top_left, &top_right, Rgb([2, 2, 2]));
+        assert_eq!(Ok(()), result);
+        assert_eq!(pixel(&renderer, 4, 8), Rgb([2, 2, 2]));
+    }
+
+    #[test]
+    fn written_image_should_have_the_top_row_first() {
+        let mut renderer = Renderer::new(2, 2);
+        renderer.draw_point_2d(&Vertex { x: -1.0, y: 1.0, z: 0.0 }, Rgb([9, 9, 9])).unwrap();
+        let mut output = Vec::new();
+        renderer.write_to(&mut output, OutputFormat::Pgm).unwrap();
+        assert_eq!(output, b"P5\n2 2\n255\n\x09\x00\x00\x00".to_vec());
+    }
+
+    #[test]
+    fn top_left_origin_should_not_need_flipping() {
+        let mut renderer = Renderer::new(2, 2);
+        renderer.set_framebuffer_origin(FramebufferOrigin::TopLeft);
+        renderer.draw_point_2d(&Vertex { x: -1.0, y: 1.0, z: 0.0 }, Rgb([9, 9, 9])).unwrap();
+        assert_eq!(pixel(&renderer, 0, 0), Rgb([9, 9, 9]));
+        let mut output = Vec::new();
+        renderer.write_to(&mut output, OutputFormat::Pgm).unwrap();
+        assert_eq!(output, b"P5\n2 2\n255\n\x09\x00\x00\x00".to_vec());
+    }
+
+    #[test]
+    fn top_left_origin_should_keep_front_faces() {
+        let mut renderer = Renderer::new(4, 4);
+        renderer.set_framebuffer_origin(FramebufferOrigin::TopLeft);
+        let render_state = RenderState { cull_mode: CullMode::Back, ..RenderState::default() };
+        let vertex_a = Vertex { x: -1.0, y: -1.0, z: 0.0 };
+        let vertex_b = Vertex { x: 1.0, y: -1.0, z: 0.0 };
+        let vertex_c = Vertex { x: 1.0, y: 1.0, z: 0.0 };
+        let result = renderer.draw_triangle(&vertex_a, &vertex_b, &vertex_c, Rgb([1, 1, 1]), &render_state);
+        assert_eq!(Ok(()), result);
+        assert_eq!(pixel(&renderer, 3, 3), Rgb([1, 1, 1]));
+    }
+
     fn bottom_floor_should_be_filled(renderer: &Renderer) {
-        assert_eq!(renderer.buffer[(0, 0)], Rgb([1, 1, 1]));
-        assert_eq!(renderer.buffer[(1, 0)], Rgb([1, 1, 1]));
-        assert_eq!(renderer.buffer[(2, 0)], Rgb([1, 1, 1]));
-        assert_eq!(renderer.buffer[(3, 1)], Rgb([1, 1, 1]));
+        assert_eq!(pixel(renderer, 0, 0), Rgb([1, 1, 1]));
+        assert_eq!(pixel(renderer, 1, 0), Rgb([1, 1, 1]));
+        assert_eq!(pixel(renderer, 2, 0), Rgb([1, 1, 1]));
+        assert_eq!(pixel(renderer, 3, 1), Rgb([1, 1, 1]));
     }
 
     fn right_wall_should_be_filled(renderer: &Renderer) {
-        assert_eq!(renderer.buffer[(3, 0)], Rgb([1, 1, 1]));
-        assert_eq!(renderer.buffer[(3, 1)], Rgb([1, 1, 1]));
-        assert_eq!(renderer.buffer[(3, 2)], Rgb([1, 1, 1]));
-        assert_eq!(renderer.buffer[(3, 3)], Rgb([1, 1, 1]));
+        assert_eq!(pixel(renderer, 3, 0), Rgb([1, 1, 1]));
+        assert_eq!(pixel(renderer, 3, 1), Rgb([1, 1, 1]));
+        assert_eq!(pixel(renderer, 3, 2), Rgb([1, 1, 1]));
+        assert_eq!(pixel(renderer, 3, 3), Rgb([1, 1, 1]));
     }
 
     fn slope_should_be_filled(renderer: &Renderer) {
-        assert_eq!(renderer.buffer[(1, 1)], Rgb([1, 1, 1]));
-        assert_eq!(renderer.buffer[(2, 2)], Rgb([1, 1, 1]));
+        assert_eq!(pixel(renderer, 1, 1), Rgb([1, 1, 1]));
+        assert_eq!(pixel(renderer, 2, 2), Rgb([1, 1, 1]));
     }
 
     fn middle_point_should_be_filled(renderer: &Renderer) {
-        assert_eq!(renderer.buffer[(2, 1)], Rgb([1, 1, 1]));
+        assert_eq!(pixel(renderer, 2, 1), Rgb([1, 1, 1]));
     }
 }
\ No newline at end of file
@@ -1,9 +1,46 @@
 use cgmath::Point2;
+use image::ImageError;
+use std::io;
 use wavefront_obj::obj::Vertex;
 
 
 #[derive(Debug, PartialEq)]
 pub enum RendererError {
+    /// Image width and height, followed by the pixel that fell outside of them.
     PixelOutOfImageBounds(u32, u32, Point2<u32>),
     NotInNormalizedDeviceCoords(Vertex),
+    /// The file extension that no `OutputFormat` matches.
+    UnsupportedOutputFormat(String),
+    /// Image width and height that the output format cannot store.
+    ImageTooLarge(u32, u32),
+    /// Index, width and height of an animation frame whose size differs from the first frame's.
+    FrameSizeMismatch(usize, u32, u32),
+    /// Writing the output failed, with the message of the underlying `io::Error`.
+    Io(String),
+    /// Reading an image, such as a texture, failed, with the message of the underlying `ImageError`.
+    Image(String),
+    /// A G-buffer call was made without enabling deferred rendering first.
+    DeferredRenderingDisabled,
+    /// An ID buffer call was made without enabling picking first.
+    PickingDisabled,
+    /// Reading a glTF file failed, or it lacks what was asked of it.
+    Gltf(String),
+}
+
+impl From<io::Error> for RendererError {
+    fn from(error: io::Error) -> Self {
+        RendererError::Io(error.to_string())
+    }
+}
+
+impl From<ImageError> for RendererError {
+    fn from(error: ImageError) -> Self {
+        RendererError::Image(error.to_string())
+    }
+}
+
+impl From<gltf::Error> for RendererError {
+    fn from(error: gltf::Error) -> Self {
+        RendererError::Gltf(error.to_string())
+    }
 }
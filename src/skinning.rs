@@ -0,0 +1,457 @@
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, Quaternion, SquareMatrix, Vector2, Vector3, Vector4, Zero};
+use g_buffer::SurfaceVertex;
+use gltf::animation::util::ReadOutputs;
+use gltf::animation::Interpolation as GltfInterpolation;
+use gltf::{buffer, Document};
+use image::Rgba;
+use keyframe::{AnimationClip, Channel, Interpolation, Keyframes, Transform};
+use pbr::PbrMaterial;
+use renderer_error::RendererError;
+use std::path::Path;
+use wavefront_obj::obj::Vertex;
+
+/// The most joints a vertex can follow.
+pub const MAX_INFLUENCES: usize = 4;
+
+/// The joints a vertex follows, as indices into `Skeleton::joints`, and how strongly.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SkinWeights {
+    pub joints: [u16; MAX_INFLUENCES],
+    pub weights: [f32; MAX_INFLUENCES],
+}
+
+impl SkinWeights {
+    /// Normalizes the weights to sum to one, unless they are all zero, which leaves the vertex unskinned.
+    pub fn new(joints: [u16; MAX_INFLUENCES], weights: [f32; MAX_INFLUENCES]) -> Self {
+        let total: f32 = weights.iter().sum();
+        let weights = if total > 0.0 { weights.map(|weight| weight / total) } else { weights };
+        SkinWeights { joints, weights }
+    }
+}
+
+/// A node of a skeleton's hierarchy, jointed or not.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub name: String,
+    pub parent: Option<usize>,
+    /// The rest transform relative to the parent.
+    pub transform: Transform,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Skeleton {
+    pub nodes: Vec<Node>,
+    /// The nodes that vertices can be weighted to, in the order `SkinWeights` refers to them.
+    pub joints: Vec<usize>,
+    /// Per joint, the matrix that takes the bind pose of the mesh into the joint's space.
+    pub inverse_bind_matrices: Vec<Matrix4<f64>>,
+}
+
+impl Skeleton {
+    pub fn new(nodes: Vec<Node>, joints: Vec<usize>, inverse_bind_matrices: Vec<Matrix4<f64>>) -> Self {
+        Skeleton { nodes, joints, inverse_bind_matrices }
+    }
+
+    /// The transforms of every node at rest, to be changed by `AnimationClip::apply`.
+    pub fn rest_pose(&self) -> Vec<Transform> {
+        self.nodes.iter().map(|node| node.transform).collect()
+    }
+
+    /// Model space matrix of every node in `pose`.
+    pub fn world_matrices(&self, pose: &[Transform]) -> Vec<Matrix4<f64>> {
+        let mut matrices = vec![None; self.nodes.len()];
+        for index in 0..self.nodes.len() {
+            self.world_matrix(index, pose, &mut matrices);
+        }
+        matrices.into_iter().map(|matrix| matrix.unwrap_or_else(Matrix4::identity)).collect()
+    }
+
+    fn world_matrix(&self, index: usize, pose: &[Transform], matrices: &mut [Option<Matrix4<f64>>]) -> Matrix4<f64> {
+        if let Some(matrix) = matrices[index] {
+            return matrix;
+        }
+        let local = pose.get(index).unwrap_or(&self.nodes[index].transform).matrix();
+        let matrix = match self.nodes[index].parent {
+            Some(parent) => self.world_matrix(parent, pose, matrices) * local,
+            None => local,
+        };
+        matrices[index] = Some(matrix);
+        matrix
+    }
+
+    /// Per joint, the matrix that moves a bind pose vertex along with the joint in `pose`.
+    pub fn joint_matrices(&self, pose: &[Transform]) -> Vec<Matrix4<f64>> {
+        let world = self.world_matrices(pose);
+        self.joints
+            .iter()
+            .enumerate()
+            .map(|(joint, &node)| {
+                let inverse_bind = self.inverse_bind_matrices.get(joint).cloned().unwrap_or_else(Matrix4::identity);
+                world[node] * inverse_bind
+            })
+            .collect()
+    }
+
+    /// The joint matrices of `clip` at `time`, starting from the rest pose.
+    pub fn animated_joint_matrices(&self, clip: &AnimationClip, time: f64) -> Vec<Matrix4<f64>> {
+        let mut pose = self.rest_pose();
+        clip.apply(time, &mut pose);
+        self.joint_matrices(&pose)
+    }
+}
+
+/// Linear blend skinning: moves `vertex` by the weighted sum of its joints' matrices. Normals go through
+/// the inverse transpose of the blend, so they stay perpendicular under non-uniform scaling.
+pub fn skin_vertex(vertex: &SurfaceVertex, weights: &SkinWeights, joint_matrices: &[Matrix4<f64>]) -> SurfaceVertex {
+    let mut blend = Matrix4::zero();
+    for (&joint, &weight) in weights.joints.iter().zip(weights.weights.iter()) {
+        if let Some(matrix) = joint_matrices.get(joint as usize).filter(|_| weight > 0.0) {
+            blend += matrix * weight as f64;
+        }
+    }
+    if blend == Matrix4::zero() {
+        return *vertex;
+    }
+    transform_vertex(vertex, &blend)
+}
+
+/// `vertex` under `matrix`, divided by w, with its normal under the inverse transpose.
+fn transform_vertex(vertex: &SurfaceVertex, matrix: &Matrix4<f64>) -> SurfaceVertex {
+    let position = matrix * Vector4::new(vertex.position.x, vertex.position.y, vertex.position.z, 1.0);
+    let position = position.truncate() / position.w;
+    let linear = Matrix3::from_cols(matrix.x.truncate(), matrix.y.truncate(), matrix.z.truncate());
+    let normal = match linear.invert() {
+        Some(inverse) => inverse.transpose() * vertex.normal,
+        None => linear * vertex.normal,
+    };
+    SurfaceVertex::new(Vertex { x: position.x, y: position.y, z: position.z }, normal, vertex.uv)
+}
+
+/// A triangle mesh bound to a skeleton, with the animations that move it.
+#[derive(Debug, Clone, Default)]
+pub struct SkinnedMesh {
+    /// The bind pose in model space, rather than normalized device coordinates.
+    pub vertices: Vec<SurfaceVertex>,
+    /// One for every vertex.
+    pub weights: Vec<SkinWeights>,
+    pub triangles: Vec<[usize; 3]>,
+    /// Per triangle, the index of its material in `materials`. Triangles past the end use the first.
+    pub triangle_materials: Vec<usize>,
+    pub materials: Vec<PbrMaterial>,
+    pub skeleton: Skeleton,
+    pub animations: Vec<AnimationClip>,
+}
+
+impl SkinnedMesh {
+    pub fn new(vertices: Vec<SurfaceVertex>, weights: Vec<SkinWeights>, triangles: Vec<[usize; 3]>, skeleton: Skeleton) -> Self {
+        SkinnedMesh { vertices, weights, triangles, skeleton, ..SkinnedMesh::default() }
+    }
+
+    /// Loads the first skinned mesh of a .gltf or .glb file, its materials, its skeleton and every
+    /// animation of the file. Cubic spline animations are sampled at their keyframes and played back linearly.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, RendererError> {
+        let (document, buffers, images) = gltf::import(path)?;
+        SkinnedMesh::from_gltf(&document, &buffers, &images)
+    }
+
+    /// Like `open`, for a .gltf or .glb file in memory. Buffers and images must be embedded in it.
+    pub fn from_slice(data: &[u8]) -> Result<Self, RendererError> {
+        let (document, buffers, images) = gltf::import_slice(data)?;
+        SkinnedMesh::from_gltf(&document, &buffers, &images)
+    }
+
+    fn from_gltf(document: &Document, buffers: &[buffer::Data], images: &[gltf::image::Data]) -> Result<Self, RendererError> {
+        let buffer_data = |buffer: gltf::Buffer| buffers.get(buffer.index()).map(|data| &data.0[..]);
+        let (mesh, skin) = document
+            .nodes()
+            .find_map(|node| node.mesh().zip(node.skin()))
+            .ok_or_else(|| gltf_error("there is no skinned mesh"))?;
+        let mut skinned_mesh = SkinnedMesh::default();
+        for material in document.materials() {
+            skinned_mesh.materials.push(PbrMaterial::from_gltf(material, images)?);
+        }
+        // glTF's default material, for primitives without one.
+        let default_material = skinned_mesh.materials.len();
+        for primitive in mesh.primitives().filter(|primitive| primitive.mode() == gltf::mesh::Mode::Triangles) {
+            let reader = primitive.reader(buffer_data);
+            let positions: Vec<[f32; 3]> = match reader.read_positions() {
+                Some(positions) => positions.collect(),
+                None => continue,
+            };
+            let first = skinned_mesh.vertices.len();
+            let count = positions.len();
+            let normals: Vec<[f32; 3]> = reader.read_normals().map(|normals| normals.collect()).unwrap_or_default();
+            let uvs = reader.read_tex_coords(0).map(|uvs| uvs.into_f32().collect()).unwrap_or_else(|| vec![[0.0; 2]; count]);
+            let joints = reader.read_joints(0).map(|joints| joints.into_u16().collect()).unwrap_or_else(|| vec![[0; 4]; count]);
+            let weights = reader.read_weights(0).map(|weights| weights.into_f32().collect()).unwrap_or_else(|| vec![[0.0; 4]; count]);
+            for (index, &[x, y, z]) in positions.iter().enumerate() {
+                let normal = normals.get(index).map_or(Vector3::zero(), |&[x, y, z]| Vector3::new(x as f64, y as f64, z as f64));
+                // glTF puts the origin of texture coordinates at the top left, OBJ at the bottom left.
+                let uv = uvs.get(index).map_or(Vector2::new(0.0, 0.0), |&[u, v]| Vector2::new(u as f64, 1.0 - v as f64));
+                let position = Vertex { x: x as f64, y: y as f64, z: z as f64 };
+                skinned_mesh.vertices.push(SurfaceVertex::new(position, normal, uv));
+                let influences = (joints.get(index).cloned().unwrap_or_default(), weights.get(index).cloned().unwrap_or_default());
+                skinned_mesh.weights.push(SkinWeights::new(influences.0, influences.1));
+            }
+            let indices: Vec<usize> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().map(|index| first + index as usize).collect(),
+                None => (first..first + count).collect(),
+            };
+            if indices.iter().any(|&index| index >= first + count) {
+                return Err(gltf_error("a triangle refers to a missing vertex"));
+            }
+            let triangles = indices.chunks(3).filter(|triangle| triangle.len() == 3);
+            skinned_mesh.triangles.extend(triangles.map(|triangle| [triangle[0], triangle[1], triangle[2]]));
+            let material = primitive.material().index().unwrap_or(default_material);
+            skinned_mesh.triangle_materials.resize(skinned_mesh.triangles.len(), material);
+            if normals.is_empty() {
+                skinned_mesh.compute_normals(first);
+            }
+        }
+        if skinned_mesh.triangle_materials.contains(&default_material) {
+            skinned_mesh.materials.push(PbrMaterial::new(Rgba([1.0, 1.0, 1.0, 1.0]), 1.0, 1.0));
+        }
+
+        let mut nodes: Vec<Node> = document
+            .nodes()
+            .map(|node| {
+                let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+                Node {
+                    name: node.name().unwrap_or("").to_string(),
+                    parent: None,
+                    transform: Transform::new(to_vector(translation), Quaternion::new(w, x, y, z).cast().unwrap(), to_vector(scale)),
+                }
+            })
+            .collect();
+        for node in document.nodes() {
+            for child in node.children() {
+                nodes[child.index()].parent = Some(node.index());
+            }
+        }
+        let inverse_bind_matrices = match skin.reader(buffer_data).read_inverse_bind_matrices() {
+            Some(matrices) => matrices.map(|matrix| Matrix4::from(matrix).cast().unwrap()).collect(),
+            None => Vec::new(),
+        };
+        let joints = skin.joints().map(|joint| joint.index()).collect();
+        skinned_mesh.skeleton = Skeleton::new(nodes, joints, inverse_bind_matrices);
+
+        for animation in document.animations() {
+            let mut channels = Vec::new();
+            for channel in animation.channels() {
+                let reader = channel.reader(buffer_data);
+                let (inputs, outputs) = match (reader.read_inputs(), reader.read_outputs()) {
+                    (Some(inputs), Some(outputs)) => (inputs, outputs),
+                    _ => continue,
+                };
+                let times: Vec<f64> = inputs.map(|time| time as f64).collect();
+                let (interpolation, stride, offset) = match channel.sampler().interpolation() {
+                    GltfInterpolation::Step => (Interpolation::Step, 1, 0),
+                    GltfInterpolation::Linear => (Interpolation::Linear, 1, 0),
+                    // Every keyframe comes as in tangent, value and out tangent.
+                    GltfInterpolation::CubicSpline => (Interpolation::Linear, 3, 1),
+                };
+                let keyframes = match outputs {
+                    ReadOutputs::Translations(values) => {
+                        Keyframes::Translation(values.skip(offset).step_by(stride).map(to_vector).collect())
+                    }
+                    ReadOutputs::Scales(values) => Keyframes::Scale(values.skip(offset).step_by(stride).map(to_vector).collect()),
+                    ReadOutputs::Rotations(values) => Keyframes::Rotation(
+                        values
+                            .into_f32()
+                            .skip(offset)
+                            .step_by(stride)
+                            .map(|[x, y, z, w]| Quaternion::new(w as f64, x as f64, y as f64, z as f64))
+                            .collect(),
+                    ),
+                    ReadOutputs::MorphTargetWeights(_) => continue,
+                };
+                channels.push(Channel::new(channel.target().node().index(), times, keyframes, interpolation));
+            }
+            skinned_mesh.animations.push(AnimationClip::new(animation.name().unwrap_or(""), channels));
+        }
+        Ok(skinned_mesh)
+    }
+
+    /// Area weighted vertex normals for the vertices from `first` on, for meshes that come without any.
+    fn compute_normals(&mut self, first: usize) {
+        let position = |vertex: &SurfaceVertex| Vector3::new(vertex.position.x, vertex.position.y, vertex.position.z);
+        for triangle in self.triangles.iter().filter(|triangle| triangle.iter().all(|&index| index >= first)) {
+            let [a, b, c] = triangle.map(|index| position(&self.vertices[index]));
+            let normal = (b - a).cross(c - a);
+            for &index in triangle {
+                self.vertices[index].normal += normal;
+            }
+        }
+        for vertex in &mut self.vertices[first..] {
+            if vertex.normal != Vector3::zero() {
+                vertex.normal = vertex.normal.normalize();
+            }
+        }
+    }
+
+    /// The vertex stage: skins every vertex with `joint_matrices`, then takes it into normalized device
+    /// coordinates with `transform`, dividing by w. Normals follow the inverse transpose of both, which
+    /// keeps them exact under affine transforms such as an orthographic view.
+    pub fn posed_vertices(&self, joint_matrices: &[Matrix4<f64>], transform: &Matrix4<f64>) -> Vec<SurfaceVertex> {
+        self.vertices
+            .iter()
+            .zip(self.weights.iter().chain(std::iter::repeat(&SkinWeights::default())))
+            .map(|(vertex, weights)| transform_vertex(&skin_vertex(vertex, weights, joint_matrices), transform))
+            .collect()
+    }
+}
+
+fn to_vector([x, y, z]: [f32; 3]) -> Vector3<f64> {
+    Vector3::new(x as f64, y as f64, z as f64)
+}
+
+fn gltf_error(message: &str) -> RendererError {
+    RendererError::Gltf(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Rad, Rotation3};
+    use std::f64::consts::PI;
+
+    fn vertex(x: f64, y: f64) -> SurfaceVertex {
+        SurfaceVertex::new(Vertex { x, y, z: 0.0 }, Vector3::new(1.0, 0.0, 0.0), Vector2::new(0.0, 0.0))
+    }
+
+    /// A two bone arm along x: the shoulder at the origin, the elbow at x = 1.
+    fn arm() -> Skeleton {
+        let shoulder = Node { name: "shoulder".to_string(), parent: None, transform: Transform::default() };
+        let elbow = Node {
+            name: "elbow".to_string(),
+            parent: Some(0),
+            transform: Transform { translation: Vector3::new(1.0, 0.0, 0.0), ..Transform::default() },
+        };
+        let inverse_bind_matrices = vec![Matrix4::identity(), Matrix4::from_translation(Vector3::new(-1.0, 0.0, 0.0))];
+        Skeleton::new(vec![shoulder, elbow], vec![0, 1], inverse_bind_matrices)
+    }
+
+    #[test]
+    fn rest_pose_should_leave_the_mesh_in_place() {
+        let skeleton = arm();
+        let matrices = skeleton.joint_matrices(&skeleton.rest_pose());
+        let skinned = skin_vertex(&vertex(2.0, 0.5), &SkinWeights::new([0, 1, 0, 0], [0.3, 0.7, 0.0, 0.0]), &matrices);
+        assert!((Vector3::new(skinned.position.x, skinned.position.y, skinned.position.z) - Vector3::new(2.0, 0.5, 0.0)).magnitude() < 1e-9);
+    }
+
+    #[test]
+    fn bent_elbow_should_blend_the_joints() {
+        let skeleton = arm();
+        let mut pose = skeleton.rest_pose();
+        pose[1].rotation = Quaternion::from_angle_z(Rad(PI / 2.0));
+        let matrices = skeleton.joint_matrices(&pose);
+        let hand = skin_vertex(&vertex(2.0, 0.0), &SkinWeights::new([1, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]), &matrices);
+        assert!((hand.position.x - 1.0).abs() < 1e-9 && (hand.position.y - 1.0).abs() < 1e-9);
+        assert!((hand.normal - Vector3::new(0.0, 1.0, 0.0)).magnitude() < 1e-9);
+        // Halfway between the two joints the elbow folds onto the average of both.
+        let elbow = skin_vertex(&vertex(2.0, 0.0), &SkinWeights::new([0, 1, 0, 0], [2.0, 2.0, 0.0, 0.0]), &matrices);
+        assert!((elbow.position.x - 1.5).abs() < 1e-9 && (elbow.position.y - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn embedded_gltf_should_import_with_its_animation() {
+        // One triangle weighted to a single joint that moves up by one over a second.
+        let mut data = Vec::new();
+        for value in &[0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&[0u8; 12]);
+        for value in &[1.0f32, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in &[0.0f32, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        let encoded = encode_base64(&data);
+        let json = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "buffers": [{{"byteLength": {}, "uri": "data:application/octet-stream;base64,{}"}}],
+                "bufferViews": [
+                    {{"buffer": 0, "byteOffset": 0, "byteLength": 36}},
+                    {{"buffer": 0, "byteOffset": 36, "byteLength": 12}},
+                    {{"buffer": 0, "byteOffset": 48, "byteLength": 48}},
+                    {{"buffer": 0, "byteOffset": 96, "byteLength": 8}},
+                    {{"buffer": 0, "byteOffset": 104, "byteLength": 24}}
+                ],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0]}},
+                    {{"bufferView": 1, "componentType": 5121, "count": 3, "type": "VEC4"}},
+                    {{"bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC4"}},
+                    {{"bufferView": 3, "componentType": 5126, "count": 2, "type": "SCALAR", "min": [0], "max": [1]}},
+                    {{"bufferView": 4, "componentType": 5126, "count": 2, "type": "VEC3"}}
+                ],
+                "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0, "JOINTS_0": 1, "WEIGHTS_0": 2}}}}]}}],
+                "nodes": [{{"mesh": 0, "skin": 0}}, {{"name": "root", "children": [2]}}, {{"name": "bone"}}],
+                "skins": [{{"joints": [2]}}],
+                "animations": [{{
+                    "name": "rise",
+                    "samplers": [{{"input": 3, "output": 4}}],
+                    "channels": [{{"sampler": 0, "target": {{"node": 2, "path": "translation"}}}}]
+                }}],
+                "scenes": [{{"nodes": [0, 1]}}]
+            }}"#,
+            data.len(),
+            encoded
+        );
+        let mesh = SkinnedMesh::from_slice(json.as_bytes()).unwrap();
+
+        assert_eq!(mesh.triangles, vec![[0, 1, 2]]);
+        assert_eq!((mesh.triangle_materials.clone(), mesh.materials.len()), (vec![0], 1));
+        assert_eq!(mesh.skeleton.joints, vec![2]);
+        assert_eq!(mesh.skeleton.nodes[2].parent, Some(1));
+        assert_eq!(mesh.animations[0].name, "rise");
+        assert!((mesh.vertices[0].normal - Vector3::new(0.0, 0.0, 1.0)).magnitude() < 1e-9);
+        let matrices = mesh.skeleton.animated_joint_matrices(&mesh.animations[0], 0.5);
+        let posed = mesh.posed_vertices(&matrices, &Matrix4::identity());
+        assert_eq!((posed[1].position.x, posed[1].position.y), (1.0, 0.5));
+    }
+
+    #[test]
+    fn indices_past_the_vertices_should_be_an_error() {
+        let mut data = Vec::new();
+        for value in &[0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&[0, 1, 9, 0]);
+        let json = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "buffers": [{{"byteLength": {}, "uri": "data:application/octet-stream;base64,{}"}}],
+                "bufferViews": [
+                    {{"buffer": 0, "byteOffset": 0, "byteLength": 36}},
+                    {{"buffer": 0, "byteOffset": 36, "byteLength": 3}}
+                ],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0]}},
+                    {{"bufferView": 1, "componentType": 5121, "count": 3, "type": "SCALAR"}}
+                ],
+                "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}, "indices": 1}}]}}],
+                "nodes": [{{"mesh": 0, "skin": 0}}, {{"name": "bone"}}],
+                "skins": [{{"joints": [1]}}],
+                "scenes": [{{"nodes": [0, 1]}}]
+            }}"#,
+            data.len(),
+            encode_base64(&data)
+        );
+        let result = SkinnedMesh::from_slice(json.as_bytes());
+        assert_eq!(result.err(), Some(RendererError::Gltf("a triangle refers to a missing vertex".to_string())));
+    }
+
+    fn encode_base64(data: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut encoded = String::new();
+        for chunk in data.chunks(3) {
+            let bits = chunk.iter().enumerate().fold(0u32, |bits, (index, &byte)| bits | (byte as u32) << (16 - 8 * index));
+            for index in 0..4 {
+                encoded.push(if index <= chunk.len() { ALPHABET[(bits >> (18 - 6 * index) & 63) as usize] as char } else { '=' });
+            }
+        }
+        encoded
+    }
+}
@@ -0,0 +1,205 @@
+use cgmath::{InnerSpace, Vector3};
+use g_buffer::GBuffer;
+use image::{ImageBuffer, Luma};
+use std::f64::consts::PI;
+use vertex_coordinate_mapper::FramebufferOrigin;
+use z_buffer::{ZBuffer, MAX_DEPTH};
+
+/// How much ambient light reaches each pixel, from 0 (none) to 1 (unoccluded).
+pub type OcclusionMap = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SsaoSettings {
+    /// Radius in pixels of the hemisphere sampled above every pixel.
+    pub radius: f64,
+    pub sample_count: u32,
+    /// Radius in pixels of the blur that smooths out the noise of the per pixel kernel rotation, 0 for none.
+    pub blur_radius: u32,
+    /// How dark a fully occluded pixel gets, 1 for black.
+    pub intensity: f32,
+    /// Depth in pixels a sample has to be behind the scene to count, which keeps slopes from shadowing themselves.
+    pub bias: f64,
+}
+
+impl SsaoSettings {
+    pub fn new(radius: f64, sample_count: u32, blur_radius: u32) -> Self {
+        SsaoSettings { radius, sample_count, blur_radius, ..SsaoSettings::default() }
+    }
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        SsaoSettings { radius: 8.0, sample_count: 16, blur_radius: 2, intensity: 1.0, bias: 1.0 }
+    }
+}
+
+/// Screen space ambient occlusion: samples a hemisphere around every pixel's surface and counts the
+/// samples that end up behind the depth buffer. Normals come from `g_buffer` if there is one, in which
+/// case they have to be in the same space as the vertex positions, or are reconstructed from the depths.
+/// Pixels without a surface, at depth 0 without a G-buffer, are left unoccluded.
+pub fn compute_ssao(
+    z_buffer: &ZBuffer,
+    g_buffer: Option<&GBuffer>,
+    origin: FramebufferOrigin,
+    settings: &SsaoSettings,
+) -> OcclusionMap {
+    let (width, height) = z_buffer.dimensions();
+    let depth_scale = (width.max(2) - 1) as f64 / MAX_DEPTH as f64;
+    let is_covered = |x: u32, y: u32| match g_buffer {
+        Some(g_buffer) => g_buffer.get(x, y).is_some(),
+        None => z_buffer.get_depth(x, y) > 0,
+    };
+    let depth_at = |x: u32, y: u32| z_buffer.get_depth(x, y) as f64 * depth_scale;
+    let kernel = hemisphere_kernel(settings.sample_count.max(1));
+    let occlusion = OcclusionMap::from_fn(width, height, |x, y| {
+        if !is_covered(x, y) {
+            return Luma([1.0]);
+        }
+        let center = Vector3::new(x as f64, y as f64, depth_at(x, y));
+        let normal = match g_buffer.and_then(|g_buffer| g_buffer.get(x, y)) {
+            Some(sample) if sample.normal.magnitude2() > 0.0 => match origin {
+                FramebufferOrigin::BottomLeft => sample.normal,
+                FramebufferOrigin::TopLeft => Vector3::new(sample.normal.x, -sample.normal.y, sample.normal.z),
+            },
+            _ => reconstruct_normal(x, y, width, height, &is_covered, &depth_at),
+        };
+        let (tangent, bitangent) = rotated_basis(normal, rotation_angle(x, y));
+        let mut occluded = 0.0;
+        for offset in kernel.iter() {
+            let sample = center + (tangent * offset.x + bitangent * offset.y + normal * offset.z) * settings.radius;
+            let (sample_x, sample_y) = (sample.x.round(), sample.y.round());
+            if sample_x < 0.0 || sample_y < 0.0 || sample_x >= width as f64 || sample_y >= height as f64 {
+                continue;
+            }
+            let (sample_x, sample_y) = (sample_x as u32, sample_y as u32);
+            if !is_covered(sample_x, sample_y) {
+                continue;
+            }
+            let scene_depth = depth_at(sample_x, sample_y);
+            if scene_depth >= sample.z + settings.bias {
+                // Surfaces far in front of this one are separate objects, not creases, so they count less.
+                occluded += (settings.radius / (scene_depth - center.z).abs()).min(1.0);
+            }
+        }
+        let visibility = 1.0 - settings.intensity * (occluded / kernel.len() as f64) as f32;
+        Luma([visibility.clamp(0.0, 1.0)])
+    });
+    blur(&occlusion, settings.blur_radius, &is_covered)
+}
+
+/// Directions in the unit hemisphere around +z, denser towards the center so close occluders count more.
+fn hemisphere_kernel(sample_count: u32) -> Vec<Vector3<f64>> {
+    (0..sample_count)
+        .map(|index| {
+            let spread = (index as f64 + 0.5) / sample_count as f64;
+            let angle = 2.0 * PI * (index as f64 * 0.618_033_988_7).fract();
+            let radius = spread.sqrt();
+            let direction = Vector3::new(radius * angle.cos(), radius * angle.sin(), (1.0 - spread).sqrt().max(0.1));
+            let scale = ((index as f64 + 1.0) * 0.754_877_7).fract();
+            direction.normalize() * (0.1 + 0.9 * scale * scale)
+        })
+        .collect()
+}
+
+/// Interleaved gradient noise, which the blur evens out within a few pixels.
+fn rotation_angle(x: u32, y: u32) -> f64 {
+    2.0 * PI * (52.982_918 * (0.067_110_56 * x as f64 + 0.005_837_15 * y as f64).fract()).fract()
+}
+
+fn rotated_basis(normal: Vector3<f64>, angle: f64) -> (Vector3<f64>, Vector3<f64>) {
+    let mut direction = Vector3::new(angle.cos(), angle.sin(), 0.0);
+    if (direction - normal * direction.dot(normal)).magnitude2() < 1e-6 {
+        direction = Vector3::new(-angle.sin(), angle.cos(), 0.0);
+    }
+    let tangent = (direction - normal * direction.dot(normal)).normalize();
+    (tangent, normal.cross(tangent))
+}
+
+/// Normal of the depth buffer's surface, taking the flatter side at each axis so edges do not bend it.
+fn reconstruct_normal<C, D>(x: u32, y: u32, width: u32, height: u32, is_covered: &C, depth_at: &D) -> Vector3<f64>
+where
+    C: Fn(u32, u32) -> bool,
+    D: Fn(u32, u32) -> f64,
+{
+    let center = depth_at(x, y);
+    let slope = |previous: Option<(u32, u32)>, next: Option<(u32, u32)>| {
+        let backward = previous.filter(|&(x, y)| is_covered(x, y)).map(|(x, y)| center - depth_at(x, y));
+        let forward = next.filter(|&(x, y)| is_covered(x, y)).map(|(x, y)| depth_at(x, y) - center);
+        match (backward, forward) {
+            (Some(backward), Some(forward)) if backward.abs() < forward.abs() => backward,
+            (_, Some(forward)) => forward,
+            (Some(backward), None) => backward,
+            (None, None) => 0.0,
+        }
+    };
+    let slope_x = slope(x.checked_sub(1).map(|x| (x, y)), Some((x + 1, y)).filter(|&(x, _)| x < width));
+    let slope_y = slope(y.checked_sub(1).map(|y| (x, y)), Some((x, y + 1)).filter(|&(_, y)| y < height));
+    Vector3::new(-slope_x, -slope_y, 1.0).normalize()
+}
+
+fn blur<C: Fn(u32, u32) -> bool>(occlusion: &OcclusionMap, radius: u32, is_covered: &C) -> OcclusionMap {
+    if radius == 0 {
+        return occlusion.clone();
+    }
+    let (width, height) = occlusion.dimensions();
+    OcclusionMap::from_fn(width, height, |x, y| {
+        if !is_covered(x, y) {
+            return Luma([1.0]);
+        }
+        let (mut total, mut count) = (0.0, 0);
+        for sample_y in y.saturating_sub(radius)..(y + radius + 1).min(height) {
+            for sample_x in x.saturating_sub(radius)..(x + radius + 1).min(width) {
+                if is_covered(sample_x, sample_y) {
+                    total += occlusion[(sample_x, sample_y)].data[0];
+                    count += 1;
+                }
+            }
+        }
+        Luma([total / count as f32])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A floor at depth 0.25 with a wall rising towards the viewer along its right half.
+    fn crease() -> ZBuffer {
+        let mut z_buffer = ZBuffer::new(32, 8);
+        for x in 0..32 {
+            for y in 0..8 {
+                let height = if x < 16 { 0.0 } else { (x - 15) as f64 * 2.0 };
+                let depth = (0.25 * MAX_DEPTH as f64 + height / 31.0 * MAX_DEPTH as f64) as u32;
+                z_buffer.update_buffer(x, y, depth);
+            }
+        }
+        z_buffer
+    }
+
+    #[test]
+    fn flat_surface_should_stay_unoccluded() {
+        let mut z_buffer = ZBuffer::new(16, 16);
+        for x in 0..16 {
+            for y in 0..16 {
+                z_buffer.update_buffer(x, y, MAX_DEPTH / 2);
+            }
+        }
+        let occlusion = compute_ssao(&z_buffer, None, FramebufferOrigin::BottomLeft, &SsaoSettings::default());
+        assert!(occlusion.pixels().all(|pixel| pixel.data[0] == 1.0));
+    }
+
+    #[test]
+    fn floor_next_to_a_wall_should_darken() {
+        let settings = SsaoSettings::new(6.0, 32, 1);
+        let occlusion = compute_ssao(&crease(), None, FramebufferOrigin::BottomLeft, &settings);
+        let (open, corner) = (occlusion[(2, 4)].data[0], occlusion[(14, 4)].data[0]);
+        assert_eq!(open, 1.0);
+        assert!(corner < 0.95, "corner visibility {}", corner);
+    }
+
+    #[test]
+    fn empty_pixels_should_be_skipped() {
+        let occlusion = compute_ssao(&ZBuffer::new(4, 4), None, FramebufferOrigin::BottomLeft, &SsaoSettings::default());
+        assert!(occlusion.pixels().all(|pixel| pixel.data[0] == 1.0));
+    }
+}
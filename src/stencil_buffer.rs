@@ -0,0 +1,159 @@
+use image::ImageBuffer;
+use image::Luma;
+
+/// Comparison between the masked reference value and the masked stored stencil value.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum StencilFunction {
+    #[default]
+    Always,
+    Never,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// What happens to the stored stencil value after a test.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum StencilOp {
+    #[default]
+    Keep,
+    Replace,
+    /// Increments, saturating at 255.
+    Increment,
+    /// Decrements, saturating at 0.
+    Decrement,
+    Invert,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StencilState {
+    pub function: StencilFunction,
+    pub reference: u8,
+    /// Applied to both the reference and the stored value before comparing them.
+    pub mask: u8,
+    /// Applied when the stencil test fails.
+    pub fail: StencilOp,
+    /// Applied when the stencil test passes but the depth test fails.
+    pub depth_fail: StencilOp,
+    /// Applied when both tests pass.
+    pub pass: StencilOp,
+}
+
+impl StencilState {
+    /// Always passes and writes `reference` wherever something is drawn.
+    pub fn write(reference: u8) -> Self {
+        StencilState { reference, pass: StencilOp::Replace, ..StencilState::default() }
+    }
+
+    /// Only draws where `function` holds between `reference` and the stored value, leaving it untouched.
+    pub fn test(function: StencilFunction, reference: u8) -> Self {
+        StencilState { function, reference, ..StencilState::default() }
+    }
+
+    /// The default state neither rejects nor modifies anything, so the stencil buffer can be skipped.
+    pub fn is_enabled(&self) -> bool {
+        *self != StencilState::default()
+    }
+}
+
+impl Default for StencilState {
+    fn default() -> Self {
+        StencilState {
+            function: StencilFunction::Always,
+            reference: 0,
+            mask: u8::MAX,
+            fail: StencilOp::Keep,
+            depth_fail: StencilOp::Keep,
+            pass: StencilOp::Keep,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StencilBuffer {
+    stencil_buffer: ImageBuffer<Luma<u8>, Vec<u8>>,
+}
+
+impl StencilBuffer {
+    pub fn new(width: u32, height: u32) -> StencilBuffer {
+        StencilBuffer {
+            stencil_buffer: ImageBuffer::new(width, height),
+        }
+    }
+
+    pub fn clear(&mut self, value: u8) {
+        for pixel in self.stencil_buffer.pixels_mut() {
+            *pixel = Luma([value]);
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> u8 {
+        self.stencil_buffer[(x, y)].data[0]
+    }
+
+    pub fn test(&self, x: u32, y: u32, state: &StencilState) -> bool {
+        let reference = state.reference & state.mask;
+        let value = self.get(x, y) & state.mask;
+        match state.function {
+            StencilFunction::Always => true,
+            StencilFunction::Never => false,
+            StencilFunction::Equal => reference == value,
+            StencilFunction::NotEqual => reference != value,
+            StencilFunction::Less => reference < value,
+            StencilFunction::LessOrEqual => reference <= value,
+            StencilFunction::Greater => reference > value,
+            StencilFunction::GreaterOrEqual => reference >= value,
+        }
+    }
+
+    pub fn apply(&mut self, x: u32, y: u32, state: &StencilState, op: StencilOp) {
+        let value = self.get(x, y);
+        let updated = match op {
+            StencilOp::Keep => value,
+            StencilOp::Replace => state.reference,
+            StencilOp::Increment => value.saturating_add(1),
+            StencilOp::Decrement => value.saturating_sub(1),
+            StencilOp::Invert => !value,
+        };
+        self.stencil_buffer[(x, y)] = Luma([updated]);
+    }
+
+    pub fn unpack(self) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+        self.stencil_buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn functions_should_compare_masked_reference_with_stored_value() {
+        let mut stencil_buffer = StencilBuffer::new(1, 1);
+        stencil_buffer.clear(0b0110);
+        assert!(stencil_buffer.test(0, 0, &StencilState::test(StencilFunction::Equal, 0b0110)));
+        assert!(stencil_buffer.test(0, 0, &StencilState::test(StencilFunction::Less, 0b0001)));
+        assert!(!stencil_buffer.test(0, 0, &StencilState::test(StencilFunction::NotEqual, 0b0110)));
+        let masked = StencilState { mask: 0b0010, ..StencilState::test(StencilFunction::Equal, 0b1010) };
+        assert!(stencil_buffer.test(0, 0, &masked));
+    }
+
+    #[test]
+    fn ops_should_update_stored_value() {
+        let mut stencil_buffer = StencilBuffer::new(1, 1);
+        let state = StencilState::write(7);
+        stencil_buffer.apply(0, 0, &state, StencilOp::Decrement);
+        assert_eq!(stencil_buffer.get(0, 0), 0);
+        stencil_buffer.apply(0, 0, &state, StencilOp::Replace);
+        assert_eq!(stencil_buffer.get(0, 0), 7);
+        stencil_buffer.apply(0, 0, &state, StencilOp::Increment);
+        assert_eq!(stencil_buffer.get(0, 0), 8);
+        stencil_buffer.apply(0, 0, &state, StencilOp::Invert);
+        assert_eq!(stencil_buffer.get(0, 0), 247);
+        stencil_buffer.apply(0, 0, &state, StencilOp::Keep);
+        assert_eq!(stencil_buffer.get(0, 0), 247);
+    }
+}
@@ -0,0 +1,365 @@
+use blend_mode::BlendMode;
+use cgmath::{InnerSpace, Point3, Vector2};
+use color::LinearImage;
+use image::Rgba;
+use line_style::{LineCap, LineJoin, LineStyle};
+use std::collections::HashMap;
+use z_buffer::{DepthState, PixelVisibility, ZBuffer};
+
+/// Miter joins longer than this many half widths are drawn as bevels instead.
+const MITER_LIMIT: f64 = 4.0;
+/// Segment ends meeting at a join reach this far past the joint so the pieces overlap without seams.
+const JOINT_OVERLAP: f64 = 0.5;
+
+/// Strokes a polyline with a `LineStyle`: wide lines, caps at the open ends and joins between segments.
+/// Coverage of every piece is combined per pixel before blending, so overlaps are not blended twice.
+pub struct StrokeDrawer<'a> {
+    points: Vec<Point3<f64>>,
+    closed: bool,
+    col: Rgba<f32>,
+    style: LineStyle,
+    blend_mode: BlendMode,
+    depth: DepthState,
+    buffer: &'a mut LinearImage,
+    z_buffer: &'a mut ZBuffer,
+}
+
+#[derive(Clone, Copy)]
+struct Fragment {
+    coverage: f64,
+    z: f64,
+}
+
+impl<'a> StrokeDrawer<'a> {
+    pub fn new(
+        points: &[Point3<u32>],
+        closed: bool,
+        col: Rgba<f32>,
+        style: LineStyle,
+        buffer: &'a mut LinearImage,
+        z_buffer: &'a mut ZBuffer,
+    ) -> Self {
+        StrokeDrawer {
+            points: points
+                .iter()
+                .map(|p| Point3::new(p.x as f64, p.y as f64, p.z as f64))
+                .collect(),
+            closed,
+            col,
+            style,
+            blend_mode: BlendMode::default(),
+            depth: DepthState::default(),
+            buffer,
+            z_buffer,
+        }
+    }
+
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    pub fn set_depth_state(&mut self, depth: DepthState) {
+        self.depth = depth;
+    }
+
+    pub fn draw(&mut self) {
+        let mut fragments = HashMap::new();
+        self.rasterize_segments(&mut fragments);
+        self.rasterize_joins(&mut fragments);
+        for (&(x, y), fragment) in fragments.iter() {
+            let coverage = if self.style.anti_aliased {
+                fragment.coverage
+            } else if fragment.coverage >= 0.5 {
+                1.0
+            } else {
+                0.0
+            };
+            let point = Point3::new(x, y, fragment.z.round() as u32);
+            plot_coverage(self.buffer, self.z_buffer, point, self.col, coverage, self.blend_mode, self.depth);
+        }
+    }
+
+    fn segment_count(&self) -> usize {
+        match self.points.len() {
+            0 => 0,
+            1 => 1,
+            len if self.closed => len,
+            len => len - 1,
+        }
+    }
+
+    fn rasterize_segments(&self, fragments: &mut HashMap<(u32, u32), Fragment>) {
+        let half_width = self.style.half_width();
+        let segment_count = self.segment_count();
+        for index in 0..segment_count {
+            let start = self.points[index];
+            let end = self.points[(index + 1) % self.points.len()];
+            let is_first = index == 0 && !self.closed;
+            let is_last = index + 1 == segment_count && !self.closed;
+            let start_cap = if is_first { Some(self.style.cap) } else { None };
+            let end_cap = if is_last { Some(self.style.cap) } else { None };
+            let reach = half_width * 2.0_f64.sqrt() + 1.0;
+            let bounds = self.clamped_bounds(&[start, end], reach);
+            for_each_pixel(bounds, |x, y| {
+                let coverage = segment_coverage(x, y, start, end, half_width, start_cap, end_cap);
+                let along = along_segment(x, y, start, end);
+                (coverage, start.z + (end.z - start.z) * along)
+            }, fragments);
+        }
+    }
+
+    fn rasterize_joins(&self, fragments: &mut HashMap<(u32, u32), Fragment>) {
+        let len = self.points.len();
+        if len < 3 && !(self.closed && len == 2) {
+            return;
+        }
+        let joints = if self.closed { 0..len } else { 1..len - 1 };
+        let half_width = self.style.half_width();
+        for index in joints {
+            let previous = self.points[(index + len - 1) % len];
+            let joint = self.points[index];
+            let next = self.points[(index + 1) % len];
+            let incoming = Vector2::new(joint.x - previous.x, joint.y - previous.y);
+            let outgoing = Vector2::new(next.x - joint.x, next.y - joint.y);
+            if incoming.magnitude() == 0.0 || outgoing.magnitude() == 0.0 {
+                continue;
+            }
+            let polygon = join_polygon(joint, incoming.normalize(), outgoing.normalize(), half_width, self.style.join);
+            let bounds = self.clamped_bounds(&[joint], half_width * MITER_LIMIT + 1.0);
+            for_each_pixel(bounds, |x, y| {
+                let coverage = match polygon {
+                    Some(ref polygon) => convex_polygon_coverage(x, y, polygon),
+                    None => disc_coverage(x - joint.x, y - joint.y, half_width),
+                };
+                (coverage, joint.z)
+            }, fragments);
+        }
+    }
+
+    fn clamped_bounds(&self, points: &[Point3<f64>], reach: f64) -> Option<(u32, u32, u32, u32)> {
+        let (width, height) = self.buffer.dimensions();
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let min_x = points.iter().fold(f64::INFINITY, |acc, p| acc.min(p.x)) - reach;
+        let min_y = points.iter().fold(f64::INFINITY, |acc, p| acc.min(p.y)) - reach;
+        let max_x = points.iter().fold(f64::NEG_INFINITY, |acc, p| acc.max(p.x)) + reach;
+        let max_y = points.iter().fold(f64::NEG_INFINITY, |acc, p| acc.max(p.y)) + reach;
+        if max_x < 0.0 || max_y < 0.0 {
+            return None;
+        }
+        Some((
+            min_x.max(0.0).floor() as u32,
+            min_y.max(0.0).floor() as u32,
+            (max_x.ceil() as u32).min(width - 1),
+            (max_y.ceil() as u32).min(height - 1),
+        ))
+    }
+}
+
+/// Blends `col` over the pixel weighted by `coverage`. Mostly covered pixels write depth,
+/// faint edge pixels are only depth tested so they do not hide what is drawn behind them later.
+pub fn plot_coverage(
+    buffer: &mut LinearImage,
+    z_buffer: &mut ZBuffer,
+    point: Point3<u32>,
+    col: Rgba<f32>,
+    coverage: f64,
+    blend_mode: BlendMode,
+    depth: DepthState,
+) {
+    if coverage <= 0.0 {
+        return;
+    }
+    let (x, y) = (point.x, point.y);
+    let visibility = if coverage >= 0.5 {
+        z_buffer.update_with_state(x, y, point.z, depth)
+    } else if depth.test {
+        z_buffer.test_depth(x, y, point.z)
+    } else {
+        PixelVisibility::Visible
+    };
+    if visibility == PixelVisibility::Visible {
+        buffer[(x, y)] = blend_mode.blend_with_coverage(col, buffer[(x, y)], coverage);
+    }
+}
+
+fn for_each_pixel<F: Fn(f64, f64) -> (f64, f64)>(
+    bounds: Option<(u32, u32, u32, u32)>,
+    coverage_at: F,
+    fragments: &mut HashMap<(u32, u32), Fragment>,
+) {
+    let (min_x, min_y, max_x, max_y) = match bounds {
+        Some(bounds) => bounds,
+        None => return,
+    };
+    for x in min_x..=max_x {
+        for y in min_y..=max_y {
+            let (coverage, z) = coverage_at(x as f64, y as f64);
+            if coverage <= 0.0 {
+                continue;
+            }
+            let fragment = fragments.entry((x, y)).or_insert(Fragment { coverage: 0.0, z });
+            if coverage > fragment.coverage {
+                *fragment = Fragment { coverage, z };
+            }
+        }
+    }
+}
+
+fn along_segment(x: f64, y: f64, start: Point3<f64>, end: Point3<f64>) -> f64 {
+    let direction = Vector2::new(end.x - start.x, end.y - start.y);
+    let length_squared = direction.magnitude2();
+    if length_squared == 0.0 {
+        return 0.0;
+    }
+    (Vector2::new(x - start.x, y - start.y).dot(direction) / length_squared).clamp(0.0, 1.0)
+}
+
+/// Coverage of a pixel centre by a segment; a `None` cap marks an end that meets a join.
+fn segment_coverage(
+    x: f64,
+    y: f64,
+    start: Point3<f64>,
+    end: Point3<f64>,
+    half_width: f64,
+    start_cap: Option<LineCap>,
+    end_cap: Option<LineCap>,
+) -> f64 {
+    let direction = Vector2::new(end.x - start.x, end.y - start.y);
+    let length = direction.magnitude();
+    let offset = Vector2::new(x - start.x, y - start.y);
+    if length == 0.0 {
+        return match start_cap {
+            Some(LineCap::Round) => disc_coverage(offset.x, offset.y, half_width),
+            _ => edge_coverage(half_width - offset.x.abs()) * edge_coverage(half_width - offset.y.abs()),
+        };
+    }
+    let unit = direction / length;
+    let along = offset.dot(unit);
+    let across = (offset.x * unit.y - offset.y * unit.x).abs();
+    if along < 0.0 && start_cap == Some(LineCap::Round) {
+        return disc_coverage(offset.x, offset.y, half_width);
+    }
+    if along > length && end_cap == Some(LineCap::Round) {
+        return disc_coverage(x - end.x, y - end.y, half_width);
+    }
+    let extension = |cap: Option<LineCap>| match cap {
+        Some(LineCap::Butt) => 0.0,
+        Some(LineCap::Square) | Some(LineCap::Round) => half_width,
+        None => JOINT_OVERLAP,
+    };
+    let inside_along = (along + extension(start_cap)).min(length + extension(end_cap) - along);
+    edge_coverage(inside_along) * edge_coverage(half_width - across)
+}
+
+fn join_polygon(
+    joint: Point3<f64>,
+    incoming: Vector2<f64>,
+    outgoing: Vector2<f64>,
+    half_width: f64,
+    join: LineJoin,
+) -> Option<Vec<Vector2<f64>>> {
+    if join == LineJoin::Round {
+        return None;
+    }
+    let turn = incoming.x * outgoing.y - incoming.y * outgoing.x;
+    let outer_side = if turn > 0.0 { -1.0 } else { 1.0 };
+    let outer_normal = |u: Vector2<f64>| Vector2::new(-u.y, u.x) * outer_side;
+    let (incoming_normal, outgoing_normal) = (outer_normal(incoming), outer_normal(outgoing));
+    let center = Vector2::new(joint.x, joint.y);
+    let incoming_corner = center + incoming_normal * half_width;
+    let outgoing_corner = center + outgoing_normal * half_width;
+    let bisector = incoming_normal + outgoing_normal;
+    if join == LineJoin::Miter && bisector.magnitude() > 0.0 {
+        let bisector = bisector.normalize();
+        let miter_length = half_width / bisector.dot(incoming_normal);
+        if miter_length <= half_width * MITER_LIMIT {
+            return Some(vec![center, incoming_corner, center + bisector * miter_length, outgoing_corner]);
+        }
+    }
+    Some(vec![center, incoming_corner, outgoing_corner])
+}
+
+fn convex_polygon_coverage(x: f64, y: f64, polygon: &[Vector2<f64>]) -> f64 {
+    let len = polygon.len();
+    let doubled_area: f64 = (0..len)
+        .map(|i| {
+            let (p, q) = (polygon[i], polygon[(i + 1) % len]);
+            p.x * q.y - q.x * p.y
+        })
+        .sum();
+    if doubled_area == 0.0 {
+        return 0.0;
+    }
+    let orientation = doubled_area.signum();
+    let mut inside = f64::INFINITY;
+    for i in 0..len {
+        let (p, q) = (polygon[i], polygon[(i + 1) % len]);
+        let edge = q - p;
+        let edge_length = edge.magnitude();
+        if edge_length == 0.0 {
+            continue;
+        }
+        let distance = orientation * (edge.x * (y - p.y) - edge.y * (x - p.x)) / edge_length;
+        inside = inside.min(distance);
+    }
+    edge_coverage(inside)
+}
+
+fn disc_coverage(dx: f64, dy: f64, radius: f64) -> f64 {
+    edge_coverage(radius - (dx * dx + dy * dy).sqrt())
+}
+
+/// Approximates the area of a pixel inside an edge from the signed distance of its centre.
+fn edge_coverage(inside_distance: f64) -> f64 {
+    (inside_distance + 0.5).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stroke(points: &[Point3<u32>], closed: bool, style: LineStyle) -> LinearImage {
+        let mut buffer = LinearImage::new(12, 12);
+        let mut z_buffer = ZBuffer::new(12, 12);
+        StrokeDrawer::new(points, closed, Rgba([1.0, 1.0, 1.0, 1.0]), style, &mut buffer, &mut z_buffer).draw();
+        buffer
+    }
+
+    #[test]
+    fn wide_line_should_cover_its_width() {
+        let style = LineStyle::new(3.0, false, LineCap::Butt, LineJoin::Miter);
+        let buffer = stroke(&[Point3::new(2, 5, 0), Point3::new(9, 5, 0)], false, style);
+        assert_eq!(buffer[(5, 4)], Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(buffer[(5, 5)], Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(buffer[(5, 6)], Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(buffer[(5, 7)], Rgba([0.0, 0.0, 0.0, 0.0]));
+        assert_eq!(buffer[(1, 5)], Rgba([0.0, 0.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn square_cap_should_extend_past_the_end_point() {
+        let style = LineStyle::new(3.0, false, LineCap::Square, LineJoin::Miter);
+        let buffer = stroke(&[Point3::new(2, 5, 0), Point3::new(9, 5, 0)], false, style);
+        assert_eq!(buffer[(1, 5)], Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(buffer[(10, 5)], Rgba([1.0, 1.0, 1.0, 1.0]));
+    }
+
+    #[test]
+    fn anti_aliased_edges_should_be_partially_covered() {
+        let style = LineStyle::new(2.0, true, LineCap::Round, LineJoin::Round);
+        let buffer = stroke(&[Point3::new(1, 1, 0), Point3::new(10, 7, 0)], false, style);
+        let partial = buffer.pixels().filter(|p| p.data[3] > 0.0 && p.data[3] < 1.0).count();
+        assert!(partial > 0);
+    }
+
+    #[test]
+    fn miter_join_should_fill_outer_corner() {
+        let style = LineStyle::new(6.0, false, LineCap::Butt, LineJoin::Miter);
+        let bevel = LineStyle { join: LineJoin::Bevel, ..style };
+        let points = [Point3::new(2, 4, 0), Point3::new(8, 4, 0), Point3::new(8, 11, 0)];
+        assert_eq!(stroke(&points, false, style)[(10, 2)], Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(stroke(&points, false, bevel)[(10, 2)], Rgba([0.0, 0.0, 0.0, 0.0]));
+    }
+}
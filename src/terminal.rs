@@ -0,0 +1,130 @@
+use image::{imageops, FilterType, Rgba, RgbaImage};
+use renderer_error::RendererError;
+use std::io::Write;
+
+/// How `write_terminal` turns pixels into characters.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TerminalStyle {
+    /// Two pixels per character, the upper half block in 24 bit ANSI colors, for terminals with truecolor.
+    #[default]
+    HalfBlocks,
+    /// One character per pixel from a luminance ramp, without escape codes, for any terminal or log.
+    Ascii,
+}
+
+const ASCII_RAMP: &[u8] = b" .:-=+*#%@";
+/// Terminal characters are about twice as tall as they are wide.
+const CHARACTER_ASPECT_RATIO: f64 = 2.0;
+
+/// Pixels less than half opaque are left to the terminal's own background.
+fn is_transparent(pixel: &Rgba<u8>) -> bool {
+    pixel.data[3] < 128
+}
+
+/// Writes `image`, with its first row at the top, as `columns` characters wide text. The rows follow
+/// from the aspect ratio of the image.
+pub fn write_terminal<W: Write>(
+    writer: &mut W,
+    image: &RgbaImage,
+    columns: u32,
+    style: TerminalStyle,
+) -> Result<(), RendererError> {
+    let (width, height) = image.dimensions();
+    let columns = columns.max(1);
+    let rows = ((columns as f64 * height as f64 / width.max(1) as f64 / CHARACTER_ASPECT_RATIO).round() as u32).max(1);
+    match style {
+        TerminalStyle::HalfBlocks => {
+            let image = imageops::resize(image, columns, rows * 2, FilterType::Triangle);
+            write_half_blocks(writer, &image)
+        }
+        TerminalStyle::Ascii => {
+            let image = imageops::resize(image, columns, rows, FilterType::Triangle);
+            write_ascii(writer, &image)
+        }
+    }
+}
+
+fn write_half_blocks<W: Write>(writer: &mut W, image: &RgbaImage) -> Result<(), RendererError> {
+    let (width, height) = image.dimensions();
+    let mut line = String::new();
+    for y in (0..height).step_by(2) {
+        line.clear();
+        // Only emit the codes that change from one character to the next.
+        let (mut foreground, mut background) = (None, None);
+        for x in 0..width {
+            let top = image[(x, y)];
+            let bottom = if y + 1 < height { image[(x, y + 1)] } else { Rgba([0, 0, 0, 0]) };
+            let (character, wanted_foreground, wanted_background) = match (is_transparent(&top), is_transparent(&bottom)) {
+                (true, true) => (' ', foreground, None),
+                (true, false) => ('▄', Some(bottom), None),
+                (false, true) => ('▀', Some(top), None),
+                (false, false) => ('▀', Some(top), Some(bottom)),
+            };
+            if wanted_background.is_none() && background.is_some() {
+                line.push_str("\x1b[49m");
+                background = None;
+            }
+            if let Some(color) = wanted_background.filter(|&color| Some(color) != background) {
+                line.push_str(&format!("\x1b[48;2;{};{};{}m", color.data[0], color.data[1], color.data[2]));
+                background = Some(color);
+            }
+            if let Some(color) = wanted_foreground.filter(|&color| Some(color) != foreground) {
+                line.push_str(&format!("\x1b[38;2;{};{};{}m", color.data[0], color.data[1], color.data[2]));
+                foreground = Some(color);
+            }
+            line.push(character);
+        }
+        line.push_str("\x1b[0m\n");
+        writer.write_all(line.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_ascii<W: Write>(writer: &mut W, image: &RgbaImage) -> Result<(), RendererError> {
+    let (width, height) = image.dimensions();
+    let mut line = Vec::with_capacity(width as usize + 1);
+    for y in 0..height {
+        line.clear();
+        for x in 0..width {
+            let [red, green, blue, alpha] = image[(x, y)].data;
+            let luma = (0.2126 * red as f32 + 0.7152 * green as f32 + 0.0722 * blue as f32) * alpha as f32 / (255.0 * 255.0);
+            let index = (luma * (ASCII_RAMP.len() - 1) as f32).round() as usize;
+            line.push(ASCII_RAMP[index.min(ASCII_RAMP.len() - 1)]);
+        }
+        line.push(b'\n');
+        writer.write_all(&line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(image: &RgbaImage, columns: u32, style: TerminalStyle) -> String {
+        let mut output = Vec::new();
+        write_terminal(&mut output, image, columns, style).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn half_blocks_should_pack_two_rows_into_one() {
+        let image = RgbaImage::from_fn(2, 2, |x, y| match (x, y) {
+            (0, 0) => Rgba([255, 0, 0, 255]),
+            (0, _) => Rgba([0, 0, 255, 255]),
+            _ => Rgba([0, 0, 0, 0]),
+        });
+        let text = render(&image, 2, TerminalStyle::HalfBlocks);
+        assert_eq!(text, "\x1b[48;2;0;0;255m\x1b[38;2;255;0;0m▀\x1b[49m \x1b[0m\n");
+    }
+
+    #[test]
+    fn ascii_should_follow_the_luminance() {
+        let image = RgbaImage::from_fn(3, 2, |x, _| match x {
+            0 => Rgba([0, 0, 0, 255]),
+            1 => Rgba([255, 255, 255, 255]),
+            _ => Rgba([255, 255, 255, 0]),
+        });
+        assert_eq!(render(&image, 3, TerminalStyle::Ascii), " @ \n");
+    }
+}
@@ -0,0 +1,96 @@
+use color::{IntoLinear, LinearImage};
+use image::{self, DynamicImage, Rgba};
+use renderer_error::RendererError;
+use std::path::Path;
+
+/// An image decoded to linear light once on load, so sampling and filtering happen on linear values.
+#[derive(Debug, Clone)]
+pub struct Texture {
+    image: LinearImage,
+}
+
+impl Texture {
+    /// Takes the 8 bit channels of `image` to be sRGB encoded.
+    pub fn from_image(image: &DynamicImage) -> Self {
+        let image = image.to_rgba();
+        let (width, height) = image.dimensions();
+        Texture { image: LinearImage::from_fn(width, height, |x, y| image[(x, y)].into_linear()) }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, RendererError> {
+        Ok(Texture::from_image(&image::open(path)?))
+    }
+
+    /// Takes the channels of `image` to hold linear data, such as roughness or metalness, rather than color.
+    pub fn from_data_image(image: &DynamicImage) -> Self {
+        let image = image.to_rgba();
+        let (width, height) = image.dimensions();
+        Texture {
+            image: LinearImage::from_fn(width, height, |x, y| {
+                let [red, green, blue, alpha] = image[(x, y)].data;
+                Rgba([red as f32 / 255.0, green as f32 / 255.0, blue as f32 / 255.0, alpha as f32 / 255.0])
+            }),
+        }
+    }
+
+    pub fn open_data<P: AsRef<Path>>(path: P) -> Result<Self, RendererError> {
+        Ok(Texture::from_data_image(&image::open(path)?))
+    }
+
+    pub fn from_linear_image(image: LinearImage) -> Self {
+        Texture { image }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    /// Bilinearly filtered color at texture coordinates `u` and `v`, with `v` pointing up as in OBJ files.
+    /// Coordinates outside of [0, 1] wrap around.
+    pub fn sample(&self, u: f64, v: f64) -> Rgba<f32> {
+        let (width, height) = self.image.dimensions();
+        let x = u.rem_euclid(1.0) * width as f64 - 0.5;
+        let y = (1.0 - v).rem_euclid(1.0) * height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = ((x - x0) as f32, (y - y0) as f32);
+        let texel = |x: f64, y: f64| {
+            self.image[(
+                (x as i64).rem_euclid(width as i64) as u32,
+                (y as i64).rem_euclid(height as i64) as u32,
+            )]
+        };
+        let (top_left, top_right) = (texel(x0, y0), texel(x0 + 1.0, y0));
+        let (bottom_left, bottom_right) = (texel(x0, y0 + 1.0), texel(x0 + 1.0, y0 + 1.0));
+        let mut color = [0.0; 4];
+        for (channel, value) in color.iter_mut().enumerate() {
+            let top = top_left.data[channel] * (1.0 - tx) + top_right.data[channel] * tx;
+            let bottom = bottom_left.data[channel] * (1.0 - tx) + bottom_right.data[channel] * tx;
+            *value = top * (1.0 - ty) + bottom * ty;
+        }
+        Rgba(color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    #[test]
+    fn texture_should_be_decoded_to_linear_on_load() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, image::Rgb([128, 255, 0])));
+        let color = Texture::from_image(&image).sample(0.5, 0.5);
+        assert!((color.data[0] - 0.2158).abs() < 0.001);
+        assert_eq!(&color.data[1..], &[1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn sampling_should_blend_neighbouring_texels_and_wrap() {
+        let mut image = LinearImage::new(2, 1);
+        image[(0, 0)] = Rgba([1.0, 1.0, 1.0, 1.0]);
+        let texture = Texture::from_linear_image(image);
+        assert_eq!(texture.sample(0.25, 0.5), Rgba([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(texture.sample(0.5, 0.5), Rgba([0.5, 0.5, 0.5, 0.5]));
+        assert_eq!(texture.sample(1.0, 0.5), Rgba([0.5, 0.5, 0.5, 0.5]));
+    }
+}
@@ -0,0 +1,65 @@
+use color::{encode_srgb, LinearImage};
+use dithering::{dither, Dithering, Palette};
+use image::{Rgba, RgbaImage};
+
+/// How linear colors, which may be brighter than 1, are compressed into the displayable range.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToneMapping {
+    /// Cuts everything above 1 off, leaving the displayable range untouched.
+    #[default]
+    Clamp,
+    /// `x / (1 + x)`, compresses highlights smoothly but washes out the image a little.
+    Reinhard,
+    /// Krzysztof Narkowicz's fit of the ACES filmic curve, with more contrast than Reinhard.
+    AcesFilmic,
+}
+
+impl ToneMapping {
+    pub fn map(&self, value: f32) -> f32 {
+        let value = value.max(0.0);
+        match *self {
+            ToneMapping::Clamp => value.min(1.0),
+            ToneMapping::Reinhard => value / (1.0 + value),
+            ToneMapping::AcesFilmic => {
+                let mapped = (value * (2.51 * value + 0.03)) / (value * (2.43 * value + 0.59) + 0.14);
+                mapped.clamp(0.0, 1.0)
+            }
+        }
+    }
+}
+
+/// Scales the colors by `2^exposure`, tone maps and encodes them as 8 bit sRGB. Alpha stays linear.
+pub fn tone_map(image: &LinearImage, exposure: f32, tone_mapping: ToneMapping) -> RgbaImage {
+    dither(&tone_map_encoded(image, exposure, tone_mapping), Dithering::None, &Palette::default())
+}
+
+/// Like `tone_map`, but leaves the sRGB encoded values as floats in [0, 1] so they can be dithered.
+pub fn tone_map_encoded(image: &LinearImage, exposure: f32, tone_mapping: ToneMapping) -> LinearImage {
+    let gain = exposure.exp2();
+    LinearImage::from_fn(image.width(), image.height(), |x, y| {
+        let [red, green, blue, alpha] = image[(x, y)].data;
+        let map = |value: f32| encode_srgb(tone_mapping.map(value * gain));
+        Rgba([map(red), map(green), map(blue), alpha.clamp(0.0, 1.0)])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators_should_keep_highlights_in_range() {
+        for operator in [ToneMapping::Clamp, ToneMapping::Reinhard, ToneMapping::AcesFilmic].iter() {
+            assert!(operator.map(100.0) <= 1.0);
+            assert!(operator.map(2.0) > operator.map(0.5));
+        }
+        assert_eq!(ToneMapping::Reinhard.map(1.0), 0.5);
+    }
+
+    #[test]
+    fn exposure_should_scale_in_stops() {
+        let image = LinearImage::from_pixel(1, 1, Rgba([0.25, 0.5, 2.0, 1.0]));
+        let mapped = tone_map(&image, 1.0, ToneMapping::Clamp);
+        assert_eq!(mapped[(0, 0)], Rgba([188, 255, 255, 255]));
+    }
+}
@@ -0,0 +1,198 @@
+use cgmath::InnerSpace;
+use g_buffer::{GBuffer, SurfaceSample};
+use image::{GrayImage, Luma, Rgb, Rgba};
+use lighting::DirectionalLight;
+use texture::Texture;
+use z_buffer::{ZBuffer, MAX_DEPTH};
+
+/// Turns the diffuse intensity of a light, from 0 to 1, into the flat bands of cel shading.
+#[derive(Debug, Clone)]
+pub enum ToonRamp {
+    /// This many evenly spaced bands from unlit to fully lit, at least 2.
+    Steps(u32),
+    /// Looked up left to right by the intensity along the middle row, so its colors can tint the bands.
+    Texture(Texture),
+}
+
+impl ToonRamp {
+    pub fn band(&self, intensity: f32) -> [f32; 3] {
+        let intensity = intensity.clamp(0.0, 1.0);
+        match *self {
+            ToonRamp::Steps(steps) => {
+                let steps = steps.max(2) as f32;
+                let band = (intensity * steps).floor().min(steps - 1.0) / (steps - 1.0);
+                [band, band, band]
+            }
+            ToonRamp::Texture(ref texture) => {
+                let (width, _) = texture.dimensions();
+                // Keep to the texel centers at the ends, so the lookup does not wrap around.
+                let half_texel = 0.5 / width as f64;
+                let u = half_texel + intensity as f64 * (1.0 - 2.0 * half_texel);
+                let [red, green, blue, _] = texture.sample(u, 0.5).data;
+                [red, green, blue]
+            }
+        }
+    }
+}
+
+/// Cel shading of a G-buffer sample, for use with `Renderer::shade_g_buffer`: like `lambert`, but every
+/// light's intensity goes through `ramp`.
+pub fn toon(sample: &SurfaceSample, lights: &[DirectionalLight], ambient: Rgb<f32>, ramp: &ToonRamp) -> Rgba<f32> {
+    let mut light = ambient.data;
+    for channel in light.iter_mut() {
+        *channel *= sample.ambient_occlusion;
+    }
+    for source in lights {
+        let band = ramp.band(sample.normal.dot(source.direction) as f32);
+        for ((total, channel), value) in light.iter_mut().zip(source.color.data.iter()).zip(band.iter()) {
+            *total += channel * value;
+        }
+    }
+    let albedo = sample.albedo.data;
+    Rgba([albedo[0] * light[0], albedo[1] * light[1], albedo[2] * light[2], albedo[3]])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutlineSettings {
+    /// Linear color of the lines.
+    pub color: Rgba<f32>,
+    /// Line width in output pixels.
+    pub width: u32,
+    /// Depth difference between neighbouring pixels, in normalized device coordinates, that makes a silhouette.
+    pub depth_threshold: f64,
+    /// Angle in radians between neighbouring normals of the G-buffer that makes a crease, if there is a G-buffer.
+    pub crease_angle: f64,
+}
+
+impl OutlineSettings {
+    pub fn new(color: Rgba<f32>, width: u32) -> Self {
+        OutlineSettings { color, width, ..OutlineSettings::default() }
+    }
+}
+
+impl Default for OutlineSettings {
+    fn default() -> Self {
+        OutlineSettings { color: Rgba([0.0, 0.0, 0.0, 1.0]), width: 1, depth_threshold: 0.05, crease_angle: 1.0 }
+    }
+}
+
+/// Marks silhouettes, where a surface ends or passes in front of a more distant one, and creases, where
+/// the normals of the G-buffer turn sharply. Silhouettes are drawn on the closer surface. `width` is in
+/// pixels of the buffers.
+pub fn find_edges(z_buffer: &ZBuffer, g_buffer: Option<&GBuffer>, settings: &OutlineSettings, width: u32) -> GrayImage {
+    let (buffer_width, buffer_height) = z_buffer.dimensions();
+    let is_covered = |x: u32, y: u32| match g_buffer {
+        Some(g_buffer) => g_buffer.get(x, y).is_some(),
+        None => z_buffer.get_depth(x, y) > 0,
+    };
+    let depth_threshold = settings.depth_threshold / 2.0 * MAX_DEPTH as f64;
+    let min_cosine = settings.crease_angle.cos();
+    let edges = GrayImage::from_fn(buffer_width, buffer_height, |x, y| {
+        if !is_covered(x, y) {
+            return Luma([0]);
+        }
+        let depth = z_buffer.get_depth(x, y) as f64;
+        let neighbours = [
+            x.checked_sub(1).map(|x| (x, y)),
+            y.checked_sub(1).map(|y| (x, y)),
+            Some((x + 1, y)).filter(|&(x, _)| x < buffer_width),
+            Some((x, y + 1)).filter(|&(_, y)| y < buffer_height),
+        ];
+        for &(neighbour_x, neighbour_y) in neighbours.iter().flatten() {
+            if !is_covered(neighbour_x, neighbour_y)
+                || depth - z_buffer.get_depth(neighbour_x, neighbour_y) as f64 > depth_threshold
+            {
+                return Luma([255]);
+            }
+        }
+        // Creases are only checked towards the right and bottom, so that they come out a single pixel wide.
+        if let Some(sample) = g_buffer.and_then(|g_buffer| g_buffer.get(x, y)) {
+            for &(neighbour_x, neighbour_y) in neighbours[2..].iter().flatten() {
+                let neighbour = g_buffer.and_then(|g_buffer| g_buffer.get(neighbour_x, neighbour_y));
+                if neighbour.is_some_and(|neighbour| neighbour.normal.dot(sample.normal) < min_cosine) {
+                    return Luma([255]);
+                }
+            }
+        }
+        Luma([0])
+    });
+    thicken(&edges, width)
+}
+
+fn thicken(edges: &GrayImage, width: u32) -> GrayImage {
+    if width <= 1 {
+        return edges.clone();
+    }
+    let (buffer_width, buffer_height) = edges.dimensions();
+    let (before, after) = ((width - 1) / 2, width / 2);
+    GrayImage::from_fn(buffer_width, buffer_height, |x, y| {
+        for neighbour_y in y.saturating_sub(after)..(y + before + 1).min(buffer_height) {
+            for neighbour_x in x.saturating_sub(after)..(x + before + 1).min(buffer_width) {
+                if edges[(neighbour_x, neighbour_y)].data[0] > 0 {
+                    return Luma([255]);
+                }
+            }
+        }
+        Luma([0])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Vector2, Vector3};
+    use color::LinearImage;
+
+    fn sample(normal: Vector3<f64>) -> SurfaceSample {
+        SurfaceSample {
+            albedo: Rgba([1.0, 1.0, 1.0, 1.0]),
+            normal,
+            uv: Vector2::new(0.0, 0.0),
+            depth: MAX_DEPTH / 2,
+            material_id: 0,
+            ambient_occlusion: 1.0,
+        }
+    }
+
+    #[test]
+    fn steps_should_quantize_the_light() {
+        let lights = [DirectionalLight::new(Vector3::new(0.0, 0.0, 1.0), Rgb([1.0, 1.0, 1.0]))];
+        let shade = |normal| toon(&sample(normal), &lights, Rgb([0.0, 0.0, 0.0]), &ToonRamp::Steps(3)).data[0];
+        assert_eq!(shade(Vector3::new(0.0, 0.0, 1.0)), 1.0);
+        assert_eq!(shade(Vector3::new(0.0, 0.8, 0.6)), 0.5);
+        assert_eq!(shade(Vector3::new(0.0, 0.96, 0.28)), 0.0);
+        assert_eq!(shade(Vector3::new(0.0, 0.0, -1.0)), 0.0);
+    }
+
+    #[test]
+    fn ramp_textures_should_tint_the_bands() {
+        let ramp = ToonRamp::Texture(Texture::from_linear_image(LinearImage::from_fn(2, 1, |x, _| {
+            if x == 0 { Rgba([0.0, 0.0, 0.5, 1.0]) } else { Rgba([1.0, 0.9, 0.8, 1.0]) }
+        })));
+        assert_eq!(ramp.band(0.0), [0.0, 0.0, 0.5]);
+        assert_eq!(ramp.band(1.0), [1.0, 0.9, 0.8]);
+    }
+
+    #[test]
+    fn silhouettes_and_creases_should_be_found() {
+        // A surface on the left half, folded along the middle of its upper half.
+        let mut z_buffer = ZBuffer::new(6, 4);
+        let mut g_buffer = GBuffer::new(6, 4);
+        for x in 0..3 {
+            for y in 0..4 {
+                z_buffer.update_buffer(x, y, MAX_DEPTH / 2);
+                let normal = if x == 2 && y < 2 { Vector3::new(1.0, 0.0, 0.0) } else { Vector3::new(0.0, 0.0, 1.0) };
+                g_buffer.set(x, y, sample(normal));
+            }
+        }
+        let settings = OutlineSettings::default();
+        let edges = find_edges(&z_buffer, Some(&g_buffer), &settings, 1);
+        let row = |y| (0..6).map(|x| edges[(x, y)].data[0] > 0).collect::<Vec<_>>();
+        assert_eq!(row(0), [false, true, true, false, false, false]);
+        assert_eq!(row(3), [false, false, true, false, false, false]);
+        let without_creases = find_edges(&z_buffer, None, &settings, 1);
+        assert!(without_creases[(2, 0)].data[0] > 0 && without_creases[(1, 0)].data[0] == 0);
+        let thick = find_edges(&z_buffer, None, &settings, 3);
+        assert!(thick[(1, 3)].data[0] > 0 && thick[(3, 3)].data[0] > 0 && thick[(0, 3)].data[0] == 0);
+    }
+}
@@ -39,14 +39,56 @@ impl<S: BaseNum + PartialOrd> Triangle<S> {
 
 impl Triangle<u32> {
     pub fn get_z_of_inside_point(&self, p: Point2<u32>) -> u32 {
+        let weights = self.get_vertex_weights(p);
+        (weights.x * self.a.z as f64 + weights.y * self.b.z as f64 + weights.z * self.c.z as f64) as u32
+    }
+
+    /// How much each of `a`, `b` and `c` contributes to point `p`, for interpolating vertex attributes.
+    pub fn get_vertex_weights(&self, p: Point2<u32>) -> Vector3<f64> {
         let bary_coords = self.get_bary_coords(Point3::new(p.x, p.y, 0));
-        (bary_coords.x * self.a.z as f64 + bary_coords.y * self.b.z as f64 + (1.0 - (bary_coords.x + bary_coords.y)) * self.c.z as f64) as u32
+        Vector3::new(1.0 - (bary_coords.x + bary_coords.y), bary_coords.x, bary_coords.y)
+    }
+
+    /// Twice the signed screen space area, positive when `a`, `b` and `c` run counter-clockwise.
+    pub fn get_signed_area(&self) -> f64 {
+        let (ab_x, ab_y) = (self.b.x as f64 - self.a.x as f64, self.b.y as f64 - self.a.y as f64);
+        let (ac_x, ac_y) = (self.c.x as f64 - self.a.x as f64, self.c.y as f64 - self.a.y as f64);
+        ab_x * ac_y - ab_y * ac_x
+    }
+
+    /// The largest change in depth for a one pixel step along x or y.
+    pub fn get_max_depth_slope(&self) -> f64 {
+        let ab_vec = self.b.cast::<f64>().unwrap() - self.a.cast::<f64>().unwrap();
+        let ac_vec = self.c.cast::<f64>().unwrap() - self.a.cast::<f64>().unwrap();
+        let normal = ab_vec.cross(ac_vec);
+        if normal.z == 0.0 {
+            return 0.0;
+        }
+        (normal.x / normal.z).abs().max((normal.y / normal.z).abs())
+    }
+
+    /// Screen space distance in pixels from `p` to the nearest of the three edges.
+    pub fn get_distance_to_closest_edge(&self, p: Point2<u32>) -> f64 {
+        let edges = [(self.a, self.b), (self.b, self.c), (self.c, self.a)];
+        edges
+            .iter()
+            .map(|&(start, end)| {
+                let (edge_x, edge_y) = (end.x as f64 - start.x as f64, end.y as f64 - start.y as f64);
+                let (offset_x, offset_y) = (p.x as f64 - start.x as f64, p.y as f64 - start.y as f64);
+                let length = (edge_x * edge_x + edge_y * edge_y).sqrt();
+                if length == 0.0 {
+                    (offset_x * offset_x + offset_y * offset_y).sqrt()
+                } else {
+                    (edge_x * offset_y - edge_y * offset_x).abs() / length
+                }
+            })
+            .fold(f64::INFINITY, f64::min)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Point3, Triangle};
+    use super::{Point2, Point3, Triangle, Vector3};
 
     #[test]
     fn should_be_able_to_create_triangle() {
@@ -75,6 +117,41 @@ mod tests {
         assert!(!triangle.is_inside_point(outside_point_up));
     }
 
+    #[test]
+    fn test_distance_to_closest_edge() {
+        let triangle = Triangle::new(Point3::new(0, 0, 0), Point3::new(10, 0, 0), Point3::new(0, 10, 0));
+        assert_eq!(triangle.get_distance_to_closest_edge(Point2::new(2, 3)), 2.0);
+        assert_eq!(triangle.get_distance_to_closest_edge(Point2::new(4, 1)), 1.0);
+        assert_eq!(triangle.get_distance_to_closest_edge(Point2::new(0, 5)), 0.0);
+    }
+
+    #[test]
+    fn test_vertex_weights_and_depth() {
+        let triangle = Triangle::new(Point3::new(0, 0, 0), Point3::new(4, 0, 400), Point3::new(0, 4, 800));
+        assert_eq!(triangle.get_vertex_weights(Point2::new(4, 0)), Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(triangle.get_vertex_weights(Point2::new(1, 2)), Vector3::new(0.25, 0.25, 0.5));
+        assert_eq!(triangle.get_z_of_inside_point(Point2::new(1, 2)), 500);
+    }
+
+    #[test]
+    fn depth_should_match_every_corner() {
+        // The depth used to weight the corners as a: x, b: y, c: 1 - x - y, which swapped them around.
+        let triangle = Triangle::new(Point3::new(0, 0, 100), Point3::new(8, 0, 200), Point3::new(0, 8, 300));
+        assert_eq!(triangle.get_z_of_inside_point(Point2::new(0, 0)), 100);
+        assert_eq!(triangle.get_z_of_inside_point(Point2::new(8, 0)), 200);
+        assert_eq!(triangle.get_z_of_inside_point(Point2::new(0, 8)), 300);
+        assert_eq!(triangle.get_z_of_inside_point(Point2::new(4, 0)), 150);
+    }
+
+    #[test]
+    fn test_winding_and_depth_slope() {
+        let triangle = Triangle::new(Point3::new(0, 0, 0), Point3::new(4, 0, 8), Point3::new(0, 2, 2));
+        assert_eq!(triangle.get_signed_area(), 8.0);
+        assert_eq!(triangle.get_max_depth_slope(), 2.0);
+        let reversed = Triangle::new(triangle.a, triangle.c, triangle.b);
+        assert_eq!(reversed.get_signed_area(), -8.0);
+    }
+
     #[test]
     fn test_get_bounding_box() {
         let triangle = Triangle::new(
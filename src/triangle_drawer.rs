@@ -1,18 +1,28 @@
-use cgmath::{Point2, Point3};
-use draw_mode::DrawMode;
-use image::{Rgb, RgbImage};
+use a_buffer::ABuffer;
+use blend_mode::BlendMode;
+use cgmath::{InnerSpace, Point2, Point3};
+use color::LinearImage;
+use g_buffer::{GBuffer, SurfaceSample, SurfaceVertex};
+use id_buffer::{IdBuffer, PickHit, PrimitiveId};
+use image::Rgba;
 use line_drawer::LineDrawer;
+use point_drawer::PointDrawer;
+use render_state::{CullMode, FillMode, RenderState};
 use renderer_error::RendererError;
+use stencil_buffer::StencilBuffer;
+use stroke::StrokeDrawer;
 use triangle::Triangle;
-use vertex_coordinate_mapper::VertexCoordinateMapper;
+use vertex_coordinate_mapper::{FramebufferOrigin, VertexCoordinateMapper};
 use wavefront_obj::obj::Vertex;
-use z_buffer::ZBuffer;
-use z_buffer::PixelVisibility;
+use z_buffer::{MAX_DEPTH, PixelVisibility, ZBuffer};
 
 pub struct TriangleDrawer<'a> {
     triangle: Triangle<u32>,
-    buffer: &'a mut RgbImage,
+    origin: FramebufferOrigin,
+    buffer: &'a mut LinearImage,
     z_buffer: &'a mut ZBuffer,
+    stencil_buffer: Option<&'a mut StencilBuffer>,
+    id_buffer: Option<(&'a mut IdBuffer, PrimitiveId)>,
 }
 
 impl<'a> TriangleDrawer<'a> {
@@ -20,11 +30,23 @@ impl<'a> TriangleDrawer<'a> {
         a: &Vertex,
         b: &Vertex,
         c: &Vertex,
-        buffer: &'a mut RgbImage,
+        buffer: &'a mut LinearImage,
         z_buffer: &'a mut ZBuffer,
     ) -> Result<Self, RendererError> {
-        let mapper = VertexCoordinateMapper::new(buffer.width(), buffer.height());
+        TriangleDrawer::from_vertices_with_origin(a, b, c, FramebufferOrigin::default(), buffer, z_buffer)
+    }
+
+    pub fn from_vertices_with_origin(
+        a: &Vertex,
+        b: &Vertex,
+        c: &Vertex,
+        origin: FramebufferOrigin,
+        buffer: &'a mut LinearImage,
+        z_buffer: &'a mut ZBuffer,
+    ) -> Result<Self, RendererError> {
+        let mapper = VertexCoordinateMapper::with_origin(buffer.width(), buffer.height(), origin);
         Ok(TriangleDrawer {
+            origin,
             triangle: Triangle::new(
                 mapper.map_vertex_coords_to_pixel_coords(a)?,
                 mapper.map_vertex_coords_to_pixel_coords(b)?,
@@ -32,56 +54,233 @@ impl<'a> TriangleDrawer<'a> {
             ),
             buffer,
             z_buffer,
+            stencil_buffer: None,
+            id_buffer: None,
         })
     }
 
-    pub fn draw(&mut self, draw_mode: DrawMode, col: Rgb<u8>) {
-        match draw_mode {
-            DrawMode::Normal => {
-                self.draw_outline(col);
-                self.fill_triangle(col);
+    /// Tests and updates `stencil_buffer` with the render state's stencil settings for every filled pixel.
+    /// Outlines and points are not stencilled.
+    pub fn set_stencil_buffer(&mut self, stencil_buffer: &'a mut StencilBuffer) {
+        self.stencil_buffer = Some(stencil_buffer);
+    }
+
+    /// Records `id` in `id_buffer` for every filled pixel that passes the depth test.
+    pub fn set_id_buffer(&mut self, id_buffer: &'a mut IdBuffer, id: PrimitiveId) {
+        self.id_buffer = Some((id_buffer, id));
+    }
+
+    pub fn draw(&mut self, col: Rgba<f32>, state: &RenderState) -> Result<(), RendererError> {
+        if self.is_culled(state.cull_mode) {
+            return Ok(());
+        }
+        match state.fill_mode {
+            FillMode::Solid => self.fill_triangle(col, state),
+            FillMode::Wireframe => self.draw_outline(col, state)?,
+            FillMode::Points => self.draw_points(col, state),
+            FillMode::SolidWithWireframe(wire_col) => self.fill_triangle_with_edges(col, wire_col, state),
+        }
+        Ok(())
+    }
+
+    /// Rasterizes the triangle into `a_buffer` instead of the color and depth buffers. The stencil test
+    /// and ops still run, against the depth of the opaque surfaces drawn so far.
+    pub fn collect_fragments(&mut self, col: Rgba<f32>, state: &RenderState, a_buffer: &mut ABuffer) {
+        if self.is_culled(state.cull_mode) {
+            return;
+        }
+        let depth_bias = self.depth_bias(state);
+        let bounding_box = self.triangle.get_bounding_box();
+        for x in bounding_box.min_x()..=bounding_box.max_x() {
+            for y in bounding_box.min_y()..=bounding_box.max_y() {
+                if self.triangle.is_inside_point(Point3::new(x, y, 0)) {
+                    let z = apply_bias(self.triangle.get_z_of_inside_point(Point2::new(x, y)), depth_bias);
+                    if self.test_stencil(x, y, z, state) {
+                        a_buffer.insert(x, y, col, z);
+                    }
+                }
             }
-            DrawMode::Wireframe => {
-                self.draw_outline(col);
+        }
+    }
+
+    /// Rasterizes the triangle into `g_buffer` instead of the color buffer, interpolating the normal
+    /// and uv of `vertices`, which must be the ones the drawer was made from.
+    pub fn write_surfaces(
+        &mut self,
+        vertices: &[SurfaceVertex; 3],
+        albedo: Rgba<f32>,
+        material_id: u32,
+        state: &RenderState,
+        g_buffer: &mut GBuffer,
+    ) {
+        if self.is_culled(state.cull_mode) {
+            return;
+        }
+        let depth_bias = self.depth_bias(state);
+        let bounding_box = self.triangle.get_bounding_box();
+        for x in bounding_box.min_x()..=bounding_box.max_x() {
+            for y in bounding_box.min_y()..=bounding_box.max_y() {
+                if !self.triangle.is_inside_point(Point3::new(x, y, 0)) {
+                    continue;
+                }
+                let z = apply_bias(self.triangle.get_z_of_inside_point(Point2::new(x, y)), depth_bias);
+                if self.test_fragment(x, y, z, state) == PixelVisibility::Hidden {
+                    continue;
+                }
+                let weights = self.triangle.get_vertex_weights(Point2::new(x, y));
+                let normal = vertices[0].normal * weights.x + vertices[1].normal * weights.y + vertices[2].normal * weights.z;
+                let uv = vertices[0].uv * weights.x + vertices[1].uv * weights.y + vertices[2].uv * weights.z;
+                let normal = if normal.magnitude2() > 0.0 { normal.normalize() } else { normal };
+                g_buffer.set(x, y, SurfaceSample { albedo, normal, uv, depth: z, material_id, ambient_occlusion: 1.0 });
+                self.record_id(x, y);
             }
         }
     }
 
-    fn draw_outline(&mut self, col: Rgb<u8>) {
-        LineDrawer::new(
-            self.triangle.a.clone(),
-            self.triangle.b.clone(),
-            col,
-            &mut self.buffer,
-            &mut self.z_buffer,
-        ).draw_line();
-        LineDrawer::new(
-            self.triangle.b.clone(),
-            self.triangle.c.clone(),
-            col,
-            &mut self.buffer,
-            &mut self.z_buffer,
-        ).draw_line();
-        LineDrawer::new(
-            self.triangle.c.clone(),
-            self.triangle.a.clone(),
-            col,
-            &mut self.buffer,
-            &mut self.z_buffer,
-        ).draw_line();
-    }
-
-    fn fill_triangle(&mut self, col: Rgb<u8>) {
+    fn is_culled(&self, cull_mode: CullMode) -> bool {
+        // Flipping the rows mirrors the triangle, which reverses its winding in the buffer.
+        let signed_area = match self.origin {
+            FramebufferOrigin::BottomLeft => self.triangle.get_signed_area(),
+            FramebufferOrigin::TopLeft => -self.triangle.get_signed_area(),
+        };
+        match cull_mode {
+            CullMode::None => false,
+            CullMode::Back => signed_area < 0.0,
+            CullMode::Front => signed_area > 0.0,
+        }
+    }
+
+    fn depth_bias(&self, state: &RenderState) -> f64 {
+        state.polygon_offset.depth_bias(self.triangle.get_max_depth_slope())
+    }
+
+    fn draw_outline(&mut self, col: Rgba<f32>, state: &RenderState) -> Result<(), RendererError> {
+        if !state.line_style.is_hairline() {
+            let corners = [self.triangle.a, self.triangle.b, self.triangle.c];
+            let mut stroke = StrokeDrawer::new(&corners, true, col, state.line_style, self.buffer, self.z_buffer);
+            stroke.set_blend_mode(state.blend_mode);
+            stroke.set_depth_state(state.depth);
+            stroke.draw();
+            return Ok(());
+        }
+        let edges = [
+            (self.triangle.a, self.triangle.b),
+            (self.triangle.b, self.triangle.c),
+            (self.triangle.c, self.triangle.a),
+        ];
+        for &(start, end) in edges.iter() {
+            let mut drawer = LineDrawer::with_style(start, end, col, state.line_style, self.buffer, self.z_buffer);
+            drawer.set_clip_policy(state.clip_policy);
+            drawer.set_blend_mode(state.blend_mode);
+            drawer.set_depth_state(state.depth);
+            drawer.draw_line()?;
+        }
+        Ok(())
+    }
+
+    fn draw_points(&mut self, col: Rgba<f32>, state: &RenderState) {
+        for &corner in [self.triangle.a, self.triangle.b, self.triangle.c].iter() {
+            let mut drawer = PointDrawer::new(corner, col, state.point_style, self.buffer, self.z_buffer);
+            drawer.set_blend_mode(state.blend_mode);
+            drawer.set_depth_state(state.depth);
+            drawer.draw();
+        }
+    }
+
+    /// Each triangle shades its own half of the edge width, so shared edges come out uniformly wide
+    /// while depth is only ever written by the fill.
+    fn fill_triangle_with_edges(&mut self, col: Rgba<f32>, wire_col: Rgba<f32>, state: &RenderState) {
+        let depth_bias = self.depth_bias(state);
+        let bounding_box = self.triangle.get_bounding_box();
+        let half_width = state.line_style.half_width();
+        for x in bounding_box.min_x()..=bounding_box.max_x() {
+            for y in bounding_box.min_y()..=bounding_box.max_y() {
+                if !self.triangle.is_inside_point(Point3::new(x, y, 0)) {
+                    continue;
+                }
+                let z = apply_bias(self.triangle.get_z_of_inside_point(Point2::new(x, y)), depth_bias);
+                if self.test_fragment(x, y, z, state) == PixelVisibility::Hidden {
+                    continue;
+                }
+                let distance = self.triangle.get_distance_to_closest_edge(Point2::new(x, y));
+                let coverage = if state.line_style.anti_aliased {
+                    (half_width + 0.5 - distance).clamp(0.0, 1.0)
+                } else if distance <= half_width {
+                    1.0
+                } else {
+                    0.0
+                };
+                let surface_col = BlendMode::SourceOver.blend_with_coverage(wire_col, col, coverage);
+                self.buffer[(x, y)] = state.blend_mode.blend(surface_col, self.buffer[(x, y)]);
+                self.record_id(x, y);
+            }
+        }
+    }
+
+    fn fill_triangle(&mut self, col: Rgba<f32>, state: &RenderState) {
+        let depth_bias = self.depth_bias(state);
         let bounding_box = self.triangle.get_bounding_box();
         for x in bounding_box.min_x()..=bounding_box.max_x() {
             for y in bounding_box.min_y()..=bounding_box.max_y() {
                 if self.triangle.is_inside_point(Point3::new(x, y, 0).clone()) {
-                    match self.z_buffer.update_buffer(x, y, self.triangle.get_z_of_inside_point(Point2::new(x, y))).unwrap() {
-                        PixelVisibility::Visible => {self.buffer[(x, y)] = col;},
+                    let z = apply_bias(self.triangle.get_z_of_inside_point(Point2::new(x, y)), depth_bias);
+                    match self.test_fragment(x, y, z, state) {
+                        PixelVisibility::Visible => {
+                            self.buffer[(x, y)] = state.blend_mode.blend(col, self.buffer[(x, y)]);
+                            self.record_id(x, y);
+                        }
                         PixelVisibility::Hidden => {},
                     }
                 }
             }
         }
     }
-}
\ No newline at end of file
+
+    fn record_id(&mut self, x: u32, y: u32) {
+        if let Some((ref mut id_buffer, id)) = self.id_buffer {
+            let barycentrics = self.triangle.get_vertex_weights(Point2::new(x, y));
+            id_buffer.set(x, y, PickHit { id, barycentrics });
+        }
+    }
+
+    /// Runs the stencil test and applies the matching stencil op without writing depth, for fragments
+    /// that are only collected. Returns whether the stencil test passed.
+    fn test_stencil(&mut self, x: u32, y: u32, z: u32, state: &RenderState) -> bool {
+        let stencil = state.stencil;
+        match self.stencil_buffer {
+            Some(ref mut stencil_buffer) if stencil.is_enabled() => {
+                if !stencil_buffer.test(x, y, &stencil) {
+                    stencil_buffer.apply(x, y, &stencil, stencil.fail);
+                    return false;
+                }
+                let visible = !state.depth.test || self.z_buffer.test_depth(x, y, z) == PixelVisibility::Visible;
+                stencil_buffer.apply(x, y, &stencil, if visible { stencil.pass } else { stencil.depth_fail });
+                true
+            }
+            _ => true,
+        }
+    }
+
+    /// Runs the stencil test, then the depth test, applying the matching stencil op.
+    fn test_fragment(&mut self, x: u32, y: u32, z: u32, state: &RenderState) -> PixelVisibility {
+        let stencil = state.stencil;
+        match self.stencil_buffer {
+            Some(ref mut stencil_buffer) if stencil.is_enabled() => {
+                if !stencil_buffer.test(x, y, &stencil) {
+                    stencil_buffer.apply(x, y, &stencil, stencil.fail);
+                    return PixelVisibility::Hidden;
+                }
+                let visibility = self.z_buffer.update_with_state(x, y, z, state.depth);
+                let op = if visibility == PixelVisibility::Visible { stencil.pass } else { stencil.depth_fail };
+                stencil_buffer.apply(x, y, &stencil, op);
+                visibility
+            }
+            _ => self.z_buffer.update_with_state(x, y, z, state.depth),
+        }
+    }
+}
+
+/// Larger depths are closer, so a positive bias pushes the depth away from the viewer.
+fn apply_bias(z: u32, depth_bias: f64) -> u32 {
+    (z as f64 - depth_bias).round().clamp(0.0, MAX_DEPTH as f64) as u32
+}
@@ -0,0 +1,75 @@
+use animated_output::{save_animation, AnimationFormat};
+use cgmath::{InnerSpace, Matrix3, Rad, Vector3};
+use image::RgbaImage;
+use renderer::Renderer;
+use renderer_error::RendererError;
+use std::f64::consts::PI;
+use std::path::Path;
+
+/// A full turn around `axis` in `frame_count` evenly spaced steps, the way assets are shown for review.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Turntable {
+    pub frame_count: u32,
+    /// The model turns counterclockwise around this axis, seen from its tip.
+    pub axis: Vector3<f64>,
+    /// How long every frame is shown, in milliseconds.
+    pub frame_delay: u32,
+}
+
+impl Turntable {
+    /// Turns around the vertical axis at 25 frames per second.
+    pub fn new(frame_count: u32) -> Self {
+        Turntable { frame_count, axis: Vector3::new(0.0, 1.0, 0.0), frame_delay: 40 }
+    }
+
+    /// The rotation of the model in `frame`. Orbiting the camera instead is the same as applying the
+    /// inverse, the transpose, to the view.
+    pub fn rotation(&self, frame: u32) -> Matrix3<f64> {
+        let angle = 2.0 * PI * frame as f64 / self.frame_count.max(1) as f64;
+        Matrix3::from_axis_angle(self.axis.normalize(), Rad(angle))
+    }
+
+    /// Calls `render_frame` with every frame's rotation and collects the final images of the renderers it returns.
+    pub fn render<F>(&self, mut render_frame: F) -> Result<Vec<RgbaImage>, RendererError>
+    where
+        F: FnMut(Matrix3<f64>) -> Result<Renderer, RendererError>,
+    {
+        (0..self.frame_count).map(|frame| Ok(render_frame(self.rotation(frame))?.to_image())).collect()
+    }
+
+    /// Renders the frames and saves them as an animation, in the format `path`'s extension asks for
+    /// unless it is given.
+    pub fn save<P, F>(&self, path: P, format: Option<AnimationFormat>, render_frame: F) -> Result<(), RendererError>
+    where
+        P: AsRef<Path>,
+        F: FnMut(Matrix3<f64>) -> Result<Renderer, RendererError>,
+    {
+        save_animation(path, &self.render(render_frame)?, self.frame_delay, format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+    use wavefront_obj::obj::Vertex;
+
+    #[test]
+    fn frames_should_make_a_full_turn() {
+        let turntable = Turntable::new(4);
+        let quarter = turntable.rotation(1) * Vector3::new(1.0, 0.0, 0.0);
+        assert!((quarter - Vector3::new(0.0, 0.0, -1.0)).magnitude() < 1e-9);
+        let frames = turntable
+            .render(|rotation| {
+                let tip = rotation * Vector3::new(0.9, 0.0, 0.0);
+                let mut renderer = Renderer::new(8, 8);
+                renderer.draw_point_2d(&Vertex { x: tip.x, y: tip.y, z: tip.z }, Rgb([255, 255, 255]))?;
+                Ok(renderer)
+            })
+            .unwrap();
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0][(6, 4)].data[3], 255);
+        assert_eq!(frames[1][(6, 4)].data[3], 0);
+        assert_eq!(frames[2][(0, 4)].data[3], 255);
+    }
+}
@@ -1,26 +1,47 @@
 use renderer_error::RendererError;
 use wavefront_obj::obj::Vertex;
 use cgmath::{Point3};
+use z_buffer::MAX_DEPTH;
+
+/// Where the first row of the buffers lies on screen.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FramebufferOrigin {
+    /// Row 0 is at the bottom, y = -1 in normalized device coordinates, like OpenGL.
+    #[default]
+    BottomLeft,
+    /// Row 0 is at the top, y = 1, like image files.
+    TopLeft,
+}
 
 pub struct VertexCoordinateMapper {
     buffer_width: u32,
     buffer_height: u32,
+    origin: FramebufferOrigin,
 }
 
 impl VertexCoordinateMapper {
     pub fn new(buffer_width: u32, buffer_height: u32) -> Self {
+        VertexCoordinateMapper::with_origin(buffer_width, buffer_height, FramebufferOrigin::default())
+    }
+
+    pub fn with_origin(buffer_width: u32, buffer_height: u32, origin: FramebufferOrigin) -> Self {
         VertexCoordinateMapper {
             buffer_width,
             buffer_height,
+            origin,
         }
     }
 
     pub fn map_vertex_coords_to_pixel_coords(&self, v: &Vertex) -> Result<Point3<u32>, RendererError> {
         check_if_in_normalized_device_coordinates(v)?;
+        let y = match self.origin {
+            FramebufferOrigin::BottomLeft => v.y,
+            FramebufferOrigin::TopLeft => -v.y,
+        };
         Ok(Point3::new(
             ((v.x + 1.0) * (self.buffer_width - 1) as f64 / 2.0) as u32,
-            ((v.y + 1.0) * (self.buffer_height - 1) as f64 / 2.0) as u32,
-            ((v.z + 1.0) * 255.0 / 2.0).round() as u32
+            ((y + 1.0) * (self.buffer_height - 1) as f64 / 2.0) as u32,
+            ((v.z + 1.0) * MAX_DEPTH as f64 / 2.0).round() as u32
         ))
     }
 }
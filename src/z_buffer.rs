@@ -1,12 +1,29 @@
 use image::GrayImage;
+use image::ImageBuffer;
 use image::Luma;
 
+/// Depth values range from 0 (farthest) to `MAX_DEPTH` (closest), giving 24 bits of precision.
+pub const MAX_DEPTH: u32 = (1 << 24) - 1;
+
 #[derive(Debug, PartialEq)]
 pub enum PixelVisibility {
     Visible,
     Hidden
 }
 
+/// Whether drawing tests against the depth buffer, and whether it writes the depth of visible pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthState {
+    pub test: bool,
+    pub write: bool,
+}
+
+impl Default for DepthState {
+    fn default() -> Self {
+        DepthState { test: true, write: true }
+    }
+}
+
 pub struct UpdateResult {
     pixel_visibility: PixelVisibility
 }
@@ -29,27 +46,72 @@ impl UpdateResult {
 
 #[derive(Debug, Clone)]
 pub struct ZBuffer {
-    z_buffer: GrayImage
+    z_buffer: ImageBuffer<Luma<u32>, Vec<u32>>
 }
 
 impl ZBuffer {
     pub fn new(width: u32, height: u32) -> ZBuffer {
         ZBuffer {
-            z_buffer: GrayImage::new(width, height)
+            z_buffer: ImageBuffer::new(width, height)
         }
     }
 
     pub fn update_buffer(&mut self, x: u32, y: u32, z: u32) -> UpdateResult {
-        if *&self.z_buffer[(x, y)].data[0] <= z as u8 {
-            *&mut self.z_buffer[(x, y)] = Luma([z as u8]);
+        if self.z_buffer[(x, y)].data[0] <= z {
+            self.z_buffer[(x, y)] = Luma([z]);
             UpdateResult::new(PixelVisibility::Visible)
         } else {
             UpdateResult::new(PixelVisibility::Hidden)
         }
     }
 
+    /// Like `update_buffer`, with the test and the write switched on or off by `depth`.
+    pub fn update_with_state(&mut self, x: u32, y: u32, z: u32, depth: DepthState) -> PixelVisibility {
+        let visibility = if depth.test { self.test_depth(x, y, z) } else { PixelVisibility::Visible };
+        if visibility == PixelVisibility::Visible && depth.write {
+            self.z_buffer[(x, y)] = Luma([z]);
+        }
+        visibility
+    }
+
+    /// Tells whether a point at depth `z` would be visible without writing it to the buffer.
+    pub fn test_depth(&self, x: u32, y: u32, z: u32) -> PixelVisibility {
+        if self.z_buffer[(x, y)].data[0] <= z {
+            PixelVisibility::Visible
+        } else {
+            PixelVisibility::Hidden
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.z_buffer.dimensions()
+    }
+
+    pub fn get_depth(&self, x: u32, y: u32) -> u32 {
+        self.z_buffer[(x, y)].data[0]
+    }
+
+    /// Shrinks the buffer by `factor` on both axes, keeping the closest depth of each block.
+    pub fn downsample(&self, factor: u32) -> ZBuffer {
+        let (width, height) = (self.z_buffer.width() / factor, self.z_buffer.height() / factor);
+        ZBuffer {
+            z_buffer: ImageBuffer::from_fn(width, height, |x, y| {
+                let mut closest = 0;
+                for sample_x in x * factor..(x + 1) * factor {
+                    for sample_y in y * factor..(y + 1) * factor {
+                        closest = closest.max(self.z_buffer[(sample_x, sample_y)].data[0]);
+                    }
+                }
+                Luma([closest])
+            }),
+        }
+    }
+
+    /// Converts the buffer into an 8 bit grayscale image, brighter pixels are closer.
     pub fn unpack(self) -> GrayImage {
-        self.z_buffer
+        GrayImage::from_fn(self.z_buffer.width(), self.z_buffer.height(), |x, y| {
+            Luma([(self.z_buffer[(x, y)].data[0] >> 16) as u8])
+        })
     }
 }
 
@@ -70,4 +132,46 @@ mod tests {
         assert_eq!(z_buffer.update_buffer(x, y, 1).unwrap(), PixelVisibility::Visible);
         assert_eq!(z_buffer.z_buffer[(1, 1)].data, [1]);
     }
+
+    #[test]
+    fn unpacking_should_keep_the_most_significant_bits() {
+        let mut z_buffer = ZBuffer::new(2, 1);
+        z_buffer.update_buffer(0, 0, MAX_DEPTH);
+        z_buffer.update_buffer(1, 0, 1 << 16);
+        let image = z_buffer.unpack();
+        assert_eq!(image[(0, 0)].data, [255]);
+        assert_eq!(image[(1, 0)].data, [1]);
+    }
+
+    #[test]
+    fn testing_depth_should_not_update_z_buffer() {
+        let mut z_buffer = ZBuffer::new(2, 2);
+        z_buffer.update_buffer(0, 0, 5);
+        assert_eq!(z_buffer.test_depth(0, 0, 9), PixelVisibility::Visible);
+        assert_eq!(z_buffer.test_depth(0, 0, 2), PixelVisibility::Hidden);
+        assert_eq!(z_buffer.z_buffer[(0, 0)].data, [5]);
+    }
+
+    #[test]
+    fn disabled_depth_test_and_write_should_be_respected() {
+        let mut z_buffer = ZBuffer::new(1, 1);
+        z_buffer.update_buffer(0, 0, 5);
+        let no_test = DepthState { test: false, write: true };
+        assert_eq!(z_buffer.update_with_state(0, 0, 2, no_test), PixelVisibility::Visible);
+        assert_eq!(z_buffer.z_buffer[(0, 0)].data, [2]);
+        let no_write = DepthState { test: true, write: false };
+        assert_eq!(z_buffer.update_with_state(0, 0, 9, no_write), PixelVisibility::Visible);
+        assert_eq!(z_buffer.z_buffer[(0, 0)].data, [2]);
+    }
+
+    #[test]
+    fn downsampling_should_keep_closest_depth() {
+        let mut z_buffer = ZBuffer::new(4, 4);
+        z_buffer.update_buffer(1, 0, 7);
+        z_buffer.update_buffer(0, 1, 3);
+        let downsampled = z_buffer.downsample(2);
+        assert_eq!(downsampled.z_buffer.dimensions(), (2, 2));
+        assert_eq!(downsampled.z_buffer[(0, 0)].data, [7]);
+        assert_eq!(downsampled.z_buffer[(1, 1)].data, [0]);
+    }
 }
\ No newline at end of file